@@ -0,0 +1,104 @@
+use crate::{vec3::Vec3, ray::Ray};
+
+/**
+ *  Axis-aligned bounding box given by its two extreme corners.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /**
+     *  An inverted box which contains nothing. Growing it by any box or point
+     *  gives back that box or point.
+     */
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vec3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    /**
+     *  Smallest box containing both a and b.
+     */
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
+    /**
+     *  Smallest box containing both self and the point p.
+     */
+    pub fn grow(&self, p: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    /**
+     *  Index of the axis (0 = x, 1 = y, 2 = z) along which the box is longest.
+     */
+    pub fn longest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x > e.y && e.x > e.z {
+            0
+        } else if e.y > e.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        if e.x < 0.0 || e.y < 0.0 || e.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /**
+     *  Slab test. Returns true if the ray enters the box somewhere in
+     *  [t_min, t_max]. inv_dir is 1 / r.direction, precomputed by the caller
+     *  since the same ray is tested against many boxes.
+     */
+    pub fn hit(&self, r: &Ray, inv_dir: Vec3, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for axis in 0..3 {
+            let mut t0 = (self.min[axis] - r.origin[axis]) * inv_dir[axis];
+            let mut t1 = (self.max[axis] - r.origin[axis]) * inv_dir[axis];
+            if inv_dir[axis] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // Written so that a NaN from 0 * inf keeps the old bound.
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{aabb::Aabb, ray::{Intersectable, Intersection, Ray}, vec3::Vec3};
+
+// Number of buckets used when evaluating the surface area heuristic.
+const SAH_BUCKETS: usize = 12;
+
+// Leaves are never split below this many primitives.
+const MAX_LEAF_SIZE: usize = 4;
+
+// Relative cost of visiting a node compared to intersecting a primitive.
+const TRAVERSAL_COST: f32 = 0.5;
+
+// Deepest tree the traversal stack has room for.
+const MAX_DEPTH: usize = 64;
+
+/**
+ *  Flattened BVH node. Nodes are stored depth first so the left child of an
+ *  interior node is always the next node in the array.
+ */
+#[derive(Clone, Copy)]
+enum BvhNode {
+    Interior { bounds: Aabb, right: usize, axis: usize },
+    Leaf { bounds: Aabb, first: usize, count: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Interior { bounds, .. } => bounds,
+            BvhNode::Leaf { bounds, .. } => bounds,
+        }
+    }
+}
+
+/**
+ *  Primitive info only needed while building.
+ */
+struct BuildItem {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+/**
+ *  Bounding volume hierarchy over a set of primitives. Built once with the
+ *  surface area heuristic and traversed without recursion. Finds the same
+ *  closest intersection as testing every primitive in turn.
+ */
+pub struct Bvh<T: Intersectable> {
+    primitives: Vec<T>,
+    nodes: Vec<BvhNode>,
+}
+
+impl<T: Intersectable> Bvh<T> {
+    pub fn new(primitives: Vec<T>) -> Bvh<T> {
+        let mut items: Vec<BuildItem> = primitives
+            .iter()
+            .enumerate()
+            .map(|(index, p)| {
+                let bounds = p.bounding_box();
+                BuildItem { index, bounds, centroid: bounds.centroid() }
+            })
+            .collect();
+
+        let mut nodes: Vec<BvhNode> = vec![];
+        if !items.is_empty() {
+            build_recursive(&mut items, 0, &mut nodes, 0);
+        }
+
+        // Reorder the primitives so every leaf refers to a contiguous range.
+        let mut slots: Vec<Option<T>> = primitives.into_iter().map(Some).collect();
+        let primitives = items
+            .iter()
+            .map(|item| slots[item.index].take().unwrap())
+            .collect();
+
+        Bvh { primitives, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub fn primitives(&self) -> &[T] {
+        &self.primitives
+    }
+}
+
+/**
+ *  Builds the subtree for items and appends it to nodes. offset is the index
+ *  of items[0] in the final primitive order.
+ */
+fn build_recursive(items: &mut [BuildItem], offset: usize, nodes: &mut Vec<BvhNode>, depth: usize) {
+    let bounds = items.iter().fold(Aabb::empty(), |b, item| Aabb::surrounding(&b, &item.bounds));
+    let centroid_bounds = items.iter().fold(Aabb::empty(), |b, item| b.grow(item.centroid));
+
+    let count = items.len();
+    let axis = centroid_bounds.longest_axis();
+    let extent = centroid_bounds.extent()[axis];
+
+    // All centroids on top of each other, no split can separate them.
+    if count <= MAX_LEAF_SIZE || extent <= 0.0 || depth + 1 >= MAX_DEPTH {
+        nodes.push(BvhNode::Leaf { bounds, first: offset, count });
+        return;
+    }
+
+    let mid = match sah_split(items, &bounds, &centroid_bounds, axis) {
+        Some(mid) => mid,
+        None => {
+            // Cheaper to keep as a leaf, unless it is too big to be one.
+            if count <= 2 * MAX_LEAF_SIZE {
+                nodes.push(BvhNode::Leaf { bounds, first: offset, count });
+                return;
+            }
+            midpoint_split(items, &centroid_bounds, axis)
+        }
+    };
+
+    let node_index = nodes.len();
+    nodes.push(BvhNode::Interior { bounds, right: 0, axis });
+
+    let (left, right) = items.split_at_mut(mid);
+    build_recursive(left, offset, nodes, depth + 1);
+    let right_index = nodes.len();
+    build_recursive(right, offset + mid, nodes, depth + 1);
+
+    nodes[node_index] = BvhNode::Interior { bounds, right: right_index, axis };
+}
+
+fn bucket_of(centroid: f32, centroid_bounds: &Aabb, axis: usize) -> usize {
+    let min = centroid_bounds.min[axis];
+    let extent = centroid_bounds.extent()[axis];
+    let b = (SAH_BUCKETS as f32 * (centroid - min) / extent) as usize;
+    usize::min(b, SAH_BUCKETS - 1)
+}
+
+/**
+ *  Partitions items along axis using binned SAH. Returns the index of the
+ *  first item in the right half, or None if not splitting is cheaper.
+ */
+fn sah_split(items: &mut [BuildItem], bounds: &Aabb, centroid_bounds: &Aabb, axis: usize) -> Option<usize> {
+    let mut counts = [0usize; SAH_BUCKETS];
+    let mut boxes = [Aabb::empty(); SAH_BUCKETS];
+
+    for item in items.iter() {
+        let b = bucket_of(item.centroid[axis], centroid_bounds, axis);
+        counts[b] += 1;
+        boxes[b] = Aabb::surrounding(&boxes[b], &item.bounds);
+    }
+
+    // Sweep from the right to get the cost of everything above each split.
+    let mut right_area = [0.0; SAH_BUCKETS];
+    let mut right_count = [0usize; SAH_BUCKETS];
+    let mut acc_box = Aabb::empty();
+    let mut acc_count = 0;
+    for b in (1..SAH_BUCKETS).rev() {
+        acc_box = Aabb::surrounding(&acc_box, &boxes[b]);
+        acc_count += counts[b];
+        right_area[b] = acc_box.surface_area();
+        right_count[b] = acc_count;
+    }
+
+    let parent_area = bounds.surface_area();
+    let mut best_cost = f32::MAX;
+    let mut best_split = 0;
+    let mut acc_box = Aabb::empty();
+    let mut acc_count = 0;
+    for b in 0..SAH_BUCKETS - 1 {
+        acc_box = Aabb::surrounding(&acc_box, &boxes[b]);
+        acc_count += counts[b];
+        if acc_count == 0 || right_count[b + 1] == 0 {
+            continue;
+        }
+        let cost = TRAVERSAL_COST + (acc_count as f32 * acc_box.surface_area() +
+            right_count[b + 1] as f32 * right_area[b + 1]) / parent_area;
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = b;
+        }
+    }
+
+    if best_cost >= items.len() as f32 {
+        return None;
+    }
+
+    Some(partition(items, |item| bucket_of(item.centroid[axis], centroid_bounds, axis) <= best_split))
+}
+
+/**
+ *  Splits at the middle of the centroid bounds. Falls back to splitting by
+ *  count if every centroid ends up on the same side.
+ */
+fn midpoint_split(items: &mut [BuildItem], centroid_bounds: &Aabb, axis: usize) -> usize {
+    let pivot = centroid_bounds.centroid()[axis];
+    let mid = partition(items, |item| item.centroid[axis] < pivot);
+    if mid == 0 || mid == items.len() {
+        items.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+        return items.len() / 2;
+    }
+    mid
+}
+
+/**
+ *  Moves all items matching pred to the front. Returns how many matched.
+ */
+fn partition<F: Fn(&BuildItem) -> bool>(items: &mut [BuildItem], pred: F) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+impl<T: Intersectable> Intersectable for Bvh<T> {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_dir = Vec3::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut closest_so_far = t_max;
+        let mut closest: Option<Intersection> = None;
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+
+            if !node.bounds().hit(r, inv_dir, t_min, closest_so_far) {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for primitive in &self.primitives[first..first + count] {
+                        if let Some(intersection) = primitive.hit(r, t_min, closest_so_far) {
+                            closest_so_far = intersection.t;
+                            closest = Some(intersection);
+                        }
+                    }
+                }
+                BvhNode::Interior { right, axis, .. } => {
+                    // Visit the near child first so the far one can be culled.
+                    let left = node_index + 1;
+                    let (near, far) = if dir_is_neg[axis] { (right, left) } else { (left, right) };
+                    stack[stack_size] = far;
+                    stack[stack_size + 1] = near;
+                    stack_size += 2;
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(node) => *node.bounds(),
+            None => Aabb::empty(),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{vec3::Vec3, ray::Ray, utils::deg_to_rad};
+use crate::{vec3::Vec3, ray::Ray, utils::deg_to_rad, sampler::Sampler};
 
 #[derive(Clone, Copy)]
 pub struct Camera {
@@ -14,6 +14,10 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     lens_radius: f32,
+
+    // Rays are sent at times between these, both 0 by default.
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
@@ -52,18 +56,55 @@ impl Camera {
             lower_left_corner,            
             u,
             v,
-            lens_radius
+            lens_radius,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    /**
+     *  Same camera with the shutter open from open to close, blurring
+     *  whatever moves in between.
+     */
+    pub fn with_shutter(&self, open: f32, close: f32) -> Camera {
+        Camera { shutter_open: open, shutter_close: close, ..*self }
+    }
+
+    /**
+     *  Same camera with the horizontal field of view widened or narrowed to
+     *  fit a new image aspect ratio.
+     */
+    pub fn with_aspect_ratio(&self, aspect_ratio: f32) -> Camera {
+        let scale = aspect_ratio / self.aspect_ratio;
+        let center = self.lower_left_corner + self.horizontal/2.0 + self.vertical/2.0;
+        let horizontal = self.horizontal * scale;
+
+        Camera {
+            aspect_ratio,
+            viewport_width: self.viewport_width * scale,
+            horizontal,
+            lower_left_corner: center - horizontal/2.0 - self.vertical/2.0,
+            ..*self
+        }
+    }
+
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut Sampler) -> Ray {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(sampler);
         let offset = self.u * rd.x + self.v * rd.y;
 
+        // Only moving shutters use up a random number, so still images
+        // stay the same.
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + sampler.next_f32() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
+
         Ray { 
             origin: self.origin + offset, 
             direction: self.lower_left_corner + s * self.horizontal + 
-                t * self.vertical - self.origin - offset
+                t * self.vertical - self.origin - offset,
+            time,
         }
     }
 }
\ No newline at end of file
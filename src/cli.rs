@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+Renders SCENE, a scene file, or the random book cover scene if none is given.
+Settings from the command line override the ones in the scene file.
+
+Options:
+  -W, --width <PIXELS>     Image width
+  -H, --height <PIXELS>    Image height. If only one of width and height is
+                           given the other follows the scene's aspect ratio
+  -s, --spp <N>            Samples per pixel
+  -d, --max-depth <N>      Maximum number of bounces per path
+  -t, --threads <N>        Number of render threads, defaults to the number
+                           of available cores
+      --single-threaded    Render on the main thread only
+      --scene <FILE>       Scene file to render, same as giving SCENE
+  -o, --output <FILE>      Write the image to FILE instead of stdout
+      --seed <N>           Seed for the random number generator
+  -h, --help               Print this help
+";
+
+/**
+ *  Settings given on the command line. Anything left as None falls back to
+ *  the scene file.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub samples_per_pixel: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub threads: Option<usize>,
+    pub single_threaded: bool,
+    pub scene: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Render(Options),
+    Help,
+}
+
+fn parse_number<T>(flag: &str, value: &str, min: T) -> Result<T, String>
+where T: std::str::FromStr + PartialOrd + std::fmt::Display {
+    let n = value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))?;
+    if n < min {
+        return Err(format!("{} must be at least {}, got {}", flag, min, value));
+    }
+    Ok(n)
+}
+
+/**
+ *  Value of a flag, either from `--flag=value` or the next argument.
+ */
+fn flag_value<I: Iterator<Item = String>>(flag: &str, inline: Option<&str>, args: &mut I) -> Result<String, String> {
+    match inline {
+        Some(value) => Ok(value.to_string()),
+        None => args.next().ok_or_else(|| format!("{} needs a value", flag)),
+    }
+}
+
+/**
+ *  Parses the command line arguments, not including the program name.
+ *  Values can be given either as `--flag value` or `--flag=value`.
+ */
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if options.scene.is_some() {
+                return Err("only one scene can be rendered at a time".to_string());
+            }
+            options.scene = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg.as_str(), None),
+        };
+
+        match flag {
+            "-h" | "--help" | "--single-threaded" if inline.is_some() => {
+                return Err(format!("{} does not take a value", flag));
+            }
+            "-h" | "--help" => return Ok(Command::Help),
+            "--single-threaded" => options.single_threaded = true,
+            "-W" | "--width" => {
+                options.width = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 2)?);
+            }
+            "-H" | "--height" => {
+                options.height = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 2)?);
+            }
+            "-s" | "--spp" => {
+                options.samples_per_pixel = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 1)?);
+            }
+            "-d" | "--max-depth" => {
+                options.max_depth = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 1)?);
+            }
+            "-t" | "--threads" => {
+                options.threads = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 1)?);
+            }
+            "--seed" => {
+                options.seed = Some(parse_number(flag, &flag_value(flag, inline, &mut args)?, 0)?);
+            }
+            "-o" | "--output" => {
+                options.output = Some(PathBuf::from(flag_value(flag, inline, &mut args)?));
+            }
+            "--scene" => {
+                if options.scene.is_some() {
+                    return Err("only one scene can be rendered at a time".to_string());
+                }
+                options.scene = Some(PathBuf::from(flag_value(flag, inline, &mut args)?));
+            }
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    if options.single_threaded && options.threads.is_some_and(|n| n > 1) {
+        return Err("--single-threaded can't be combined with --threads".to_string());
+    }
+
+    Ok(Command::Render(options))
+}
+
+impl Options {
+    /**
+     *  Final image size given the size from the scene. If only one side is
+     *  overridden the other one keeps the scene's aspect ratio.
+     */
+    pub fn image_size(&self, scene_width: i32, scene_height: i32) -> (i32, i32) {
+        let aspect_ratio = scene_width as f32 / scene_height as f32;
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, i32::max((w as f32 / aspect_ratio) as i32, 2)),
+            (None, Some(h)) => (i32::max((h as f32 * aspect_ratio) as i32, 2), h),
+            (None, None) => (scene_width, scene_height),
+        }
+    }
+}
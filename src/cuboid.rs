@@ -0,0 +1,83 @@
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb};
+
+/**
+ *  Axis aligned box, solid so rays starting inside hit the inside of its
+ *  faces. Each face has texture coordinates from 0 to 1.
+ */
+#[derive(Clone)]
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub material: Material,
+}
+
+impl Cuboid {
+    pub fn new(a: Vec3, b: Vec3, material: Material) -> Cuboid {
+        Cuboid { min: a.min(b), max: a.max(b), material }
+    }
+}
+
+/**
+ *  Vector of length along axis.
+ */
+fn axis_vector(axis: usize, length: f32) -> Vec3 {
+    match axis {
+        0 => Vec3::new(length, 0.0, 0.0),
+        1 => Vec3::new(0.0, length, 0.0),
+        _ => Vec3::new(0.0, 0.0, length),
+    }
+}
+
+impl Intersectable for Cuboid {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        // Slabs, keeping track of which one the ray enters and leaves last.
+        let mut near = (f32::NEG_INFINITY, 0);
+        let mut far = (f32::INFINITY, 0);
+        for axis in 0..3 {
+            let inv = 1.0 / r.direction[axis];
+            let mut t0 = (self.min[axis] - r.origin[axis]) * inv;
+            let mut t1 = (self.max[axis] - r.origin[axis]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // A NaN from 0 * inf, a ray running along a face, is ignored.
+            if t0 > near.0 {
+                near = (t0, axis);
+            }
+            if t1 < far.0 {
+                far = (t1, axis);
+            }
+        }
+        if near.0 > far.0 {
+            return None;
+        }
+
+        // The face the ray enters by, or leaves by if it starts inside.
+        let (t, axis, sign) = if near.0 >= t_min && near.0 <= t_max {
+            (near.0, near.1, -r.direction[near.1].signum())
+        } else if far.0 >= t_min && far.0 <= t_max {
+            (far.0, far.1, r.direction[far.1].signum())
+        } else {
+            return None;
+        };
+
+        let point = r.at(t);
+        let outward_normal = axis_vector(axis, sign);
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
+        intersection.set_face_normal(r, outward_normal);
+
+        // The other two axes in an order that makes dpdu x dpdv point out.
+        let (a, b) = if sign > 0.0 { ((axis + 1) % 3, (axis + 2) % 3) } else { ((axis + 2) % 3, (axis + 1) % 3) };
+        let extent = self.max - self.min;
+        intersection.u = (point[a] - self.min[a]) / extent[a];
+        intersection.v = (point[b] - self.min[b]) / extent[b];
+        intersection.dpdu = axis_vector(a, extent[a]);
+        intersection.dpdv = axis_vector(b, extent[b]);
+        Some(intersection)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+}
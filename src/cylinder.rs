@@ -0,0 +1,174 @@
+use std::f32::consts::PI;
+
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb, plane::hit_disc};
+
+/**
+ *  Closest of two intersections, either of which may be missing.
+ */
+fn closer<'a>(a: Option<Intersection<'a>>, b: Option<Intersection<'a>>) -> Option<Intersection<'a>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.t <= b.t { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+/**
+ *  Roots of a t^2 + b t + c in increasing order, None if there are none.
+ */
+fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // Avoids cancellation between -b and the root.
+    let q = -0.5 * (b + b.signum() * f32::sqrt(discriminant));
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/**
+ *  u around the y axis starting at -x like sphere_uv(), and the derivative
+ *  of the point at offset from the axis with respect to it.
+ */
+fn around_y(offset: Vec3) -> (f32, Vec3) {
+    let phi = f32::atan2(-offset.z, offset.x) + PI;
+    (phi / (2.0 * PI), 2.0 * PI * Vec3::new(offset.z, 0.0, -offset.x))
+}
+
+/**
+ *  Closed cylinder standing on the disc of radius around base, reaching
+ *  height up the y axis. On the side u goes around the axis and v up it.
+ */
+#[derive(Clone)]
+pub struct Cylinder {
+    pub base: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, radius: f32, height: f32, material: Material) -> Cylinder {
+        Cylinder { base, radius, height, material }
+    }
+
+    fn hit_side(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let o = r.origin - self.base;
+        let d = r.direction;
+        let a = d.x * d.x + d.z * d.z;
+        if a == 0.0 {
+            return None;
+        }
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+
+        let t = [t0, t1].into_iter().find(|&t| {
+            let y = o.y + t * d.y;
+            t >= t_min && t <= t_max && (0.0..=self.height).contains(&y)
+        })?;
+
+        let point = r.at(t);
+        let offset = point - self.base;
+        let outward_normal = Vec3::new(offset.x, 0.0, offset.z) / self.radius;
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
+        intersection.set_face_normal(r, outward_normal);
+        (intersection.u, intersection.dpdu) = around_y(offset);
+        intersection.v = offset.y / self.height;
+        intersection.dpdv = Vec3::new(0.0, self.height, 0.0);
+        Some(intersection)
+    }
+}
+
+impl Intersectable for Cylinder {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let top = self.base + self.height * up;
+        let side = self.hit_side(r, t_min, t_max);
+        let t_max = side.as_ref().map_or(t_max, |hit| hit.t);
+        let bottom = hit_disc(r, self.base, -up, self.radius, &self.material, t_min, t_max);
+        let t_max = bottom.as_ref().map_or(t_max, |hit| hit.t);
+        let top = hit_disc(r, top, up, self.radius, &self.material, t_min, t_max);
+        closer(closer(side, bottom), top)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, 0.0, self.radius);
+        Aabb::new(self.base - r, self.base + r + Vec3::new(0.0, self.height, 0.0))
+    }
+}
+
+/**
+ *  Closed cone standing on the disc of radius around base, with its tip
+ *  height up the y axis. On the side u goes around the axis and v up it.
+ */
+#[derive(Clone)]
+pub struct Cone {
+    pub base: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, radius: f32, height: f32, material: Material) -> Cone {
+        Cone { base, radius, height, material }
+    }
+
+    fn hit_side(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        // x^2 + z^2 = k^2 (height - y)^2, the y range cuts off the upper
+        // half of the double cone.
+        let k = self.radius / self.height;
+        let o = r.origin - self.base;
+        let d = r.direction;
+        let below_tip = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k * k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k * k * below_tip * d.y);
+        let c = o.x * o.x + o.z * o.z - k * k * below_tip * below_tip;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+
+        let t = [t0, t1].into_iter().find(|&t| {
+            let y = o.y + t * d.y;
+            t >= t_min && t <= t_max && (0.0..=self.height).contains(&y)
+        })?;
+
+        let point = r.at(t);
+        let offset = point - self.base;
+        let rho = f32::sqrt(offset.x * offset.x + offset.z * offset.z);
+        let outward_normal = if rho > 0.0 {
+            Vec3::new(offset.x / rho, k, offset.z / rho).normalized()
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        };
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
+        intersection.set_face_normal(r, outward_normal);
+        (intersection.u, intersection.dpdu) = around_y(offset);
+        intersection.v = offset.y / self.height;
+        if rho > 0.0 {
+            intersection.dpdv = Vec3::new(-self.radius * offset.x / rho, self.height, -self.radius * offset.z / rho);
+        }
+        Some(intersection)
+    }
+}
+
+impl Intersectable for Cone {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let side = self.hit_side(r, t_min, t_max);
+        let t_max = side.as_ref().map_or(t_max, |hit| hit.t);
+        let bottom = hit_disc(r, self.base, Vec3::new(0.0, -1.0, 0.0), self.radius, &self.material, t_min, t_max);
+        closer(side, bottom)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, 0.0, self.radius);
+        Aabb::new(self.base - r, self.base + r + Vec3::new(0.0, self.height, 0.0))
+    }
+}
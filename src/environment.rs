@@ -0,0 +1,320 @@
+use std::f32::consts::PI;
+
+use crate::{vec3::Vec3, hdr::HdrImage, sampler::Sampler, utils::{clamp, deg_to_rad}};
+
+/**
+ *  Radiance arriving from infinitely far away, seen by every ray that leaves
+ *  the scene.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Environment {
+    // The same radiance in every direction, black for closed scenes.
+    Constant(Vec3),
+    // Blends from horizon to zenith with the height of the direction, the
+    // horizon color continues below.
+    Gradient { horizon: Vec3, zenith: Vec3 },
+    // An equirectangular image.
+    Map(EnvironmentMap),
+    // Daylight for a given sun position.
+    Sky(PreethamSky),
+}
+
+impl Default for Environment {
+    /**
+     *  White at the horizon blending into blue at the zenith.
+     */
+    fn default() -> Self {
+        Environment::Gradient { horizon: Vec3::new(1.0, 1.0, 1.0), zenith: Vec3::new(0.5, 0.7, 1.0) }
+    }
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Environment::Constant(color) => *color,
+            Environment::Gradient { horizon, zenith } => {
+                let t = 0.5 * (direction.normalized().y + 1.0);
+                (1.0 - t) * *horizon + t * *zenith
+            }
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /**
+     *  Whether sample() picks directions by brightness. Smooth environments
+     *  are found well enough by scattered rays.
+     */
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Environment::Map(_))
+    }
+
+    /**
+     *  Random direction with its solid angle density, favoring bright
+     *  directions. None if the environment is not sampled.
+     */
+    pub fn sample(&self, sampler: &mut Sampler) -> Option<(Vec3, f32)> {
+        match self {
+            Environment::Map(map) => map.sample(sampler),
+            _ => None,
+        }
+    }
+
+    /**
+     *  Solid angle density of sample() returning direction.
+     */
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+/**
+ *  Piecewise constant distribution over [0, 1) with one piece per value of
+ *  func.
+ */
+#[derive(Clone, Debug, PartialEq)]
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f32;
+        }
+        let integral = cdf[n];
+
+        // Nothing to favor, fall back to uniform.
+        if integral <= 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        }
+
+        Distribution1D { func, cdf, integral }
+    }
+
+    fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    /**
+     *  Maps u in [0, 1) to a position in [0, 1), returns the position and
+     *  the index of the piece it is in.
+     */
+    fn sample(&self, u: f32) -> (f32, usize) {
+        let n = self.len();
+        let index = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let mut du = u - self.cdf[index];
+        if width > 0.0 {
+            du /= width;
+        }
+        ((index as f32 + du) / n as f32, index)
+    }
+
+    /**
+     *  Density of sample() landing in piece index.
+     */
+    fn pdf(&self, index: usize) -> f32 {
+        if self.integral <= 0.0 {
+            return 1.0;
+        }
+        self.func[index] / self.integral
+    }
+}
+
+/**
+ *  An equirectangular (latitude-longitude) environment image. The top row is
+ *  straight up and the center of the image looks along -z before rotation.
+ *  Directions are importance sampled by pixel brightness.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    intensity: f32,
+    // Around the y axis, in radians.
+    rotation: f32,
+
+    // Distribution of rows, and of columns within each row.
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>,
+}
+
+impl EnvironmentMap {
+    /**
+     *  Environment from image, scaled by intensity and turned by rotation
+     *  degrees around the y axis.
+     */
+    pub fn new(image: HdrImage, intensity: f32, rotation: f32) -> EnvironmentMap {
+        let HdrImage { width, height, pixels } = image;
+
+        // Rows near the poles cover less solid angle.
+        let columns: Vec<Distribution1D> = pixels
+            .chunks_exact(width)
+            .enumerate()
+            .map(|(row, colors)| {
+                let sin_theta = f32::sin(PI * (row as f32 + 0.5) / height as f32);
+                Distribution1D::new(colors.iter().map(|c| luminance(*c) * sin_theta).collect())
+            })
+            .collect();
+        let rows = Distribution1D::new(columns.iter().map(|c| c.integral).collect());
+
+        EnvironmentMap { width, height, pixels, intensity, rotation: deg_to_rad(rotation), rows, columns }
+    }
+
+    fn uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.normalized();
+        let phi = f32::atan2(d.x, -d.z) - self.rotation;
+        let u = (0.5 + phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = f32::acos(clamp(d.y, -1.0, 1.0)) / PI;
+        (u, v)
+    }
+
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = 2.0 * PI * (u - 0.5) + self.rotation;
+        let theta = PI * v;
+        Vec3::new(f32::sin(theta) * f32::sin(phi), f32::cos(theta), -f32::sin(theta) * f32::cos(phi))
+    }
+
+    fn pixel_at(&self, u: f32, v: f32) -> (usize, usize) {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.uv(direction);
+        let (x, y) = self.pixel_at(u, v);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    pub fn sample(&self, sampler: &mut Sampler) -> Option<(Vec3, f32)> {
+        let (v, y) = self.rows.sample(sampler.next_f32());
+        let (u, _) = self.columns[y].sample(sampler.next_f32());
+
+        let direction = self.direction(u, v);
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((direction, pdf))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.uv(direction);
+        let sin_theta = f32::sin(PI * v);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // From density over the image to density over the sphere.
+        let (x, y) = self.pixel_at(u, v);
+        let pdf_uv = self.rows.pdf(y) * self.columns[y].pdf(x);
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+fn luminance(c: Vec3) -> f32 {
+    f32::max(0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z, 0.0)
+}
+
+/**
+ *  The analytic daylight model of Preetham, Shirley and Smits, "A Practical
+ *  Analytic Model for Daylight" (1999). Turbidity describes the haze, from
+ *  about 2 for a clear sky to 10 for a hazy one.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreethamSky {
+    // Unit vector towards the sun.
+    sun: Vec3,
+    intensity: f32,
+
+    // Perez coefficients A to E for luminance Y and chromaticity x, y.
+    perez: [[f32; 5]; 3],
+    // Y, x and y at the zenith divided by the Perez function there.
+    zenith: [f32; 3],
+}
+
+impl PreethamSky {
+    pub fn new(sun: Vec3, turbidity: f32, intensity: f32) -> PreethamSky {
+        // The model is only defined for the sun above the horizon.
+        let mut sun = sun.normalized();
+        if sun.y < 0.0 {
+            sun = Vec3::new(sun.x, 0.0, sun.z).normalized();
+        }
+        let t = turbidity;
+        let theta_s = f32::acos(clamp(sun.y, 0.0, 1.0));
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * f32::tan(chi) - 0.2155 * t + 2.4192;
+
+        let theta = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+        let dot = |c: [f32; 4]| c[0] * theta[0] + c[1] * theta[1] + c[2] * theta[2] + c[3] * theta[3];
+        let zenith_x = t * t * dot([0.00166, -0.00375, 0.00209, 0.0])
+            + t * dot([-0.02903, 0.06377, -0.03202, 0.00394])
+            + dot([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_yc = t * t * dot([0.00275, -0.00610, 0.00317, 0.0])
+            + t * dot([-0.04214, 0.08970, -0.04153, 0.00516])
+            + dot([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut zenith = [zenith_y, zenith_x, zenith_yc];
+        for (value, coefficients) in zenith.iter_mut().zip(&perez) {
+            *value /= perez_function(coefficients, 1.0, theta_s);
+        }
+
+        PreethamSky { sun, intensity, perez, zenith }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        // Below the horizon the sky continues as it is at the horizon.
+        let d = direction.normalized();
+        let cos_theta = f32::max(d.y, 0.01);
+        let gamma = f32::acos(clamp(d.dot(self.sun), -1.0, 1.0));
+
+        let big_y = self.zenith[0] * perez_function(&self.perez[0], cos_theta, gamma);
+        let x = self.zenith[1] * perez_function(&self.perez[1], cos_theta, gamma);
+        let y = self.zenith[2] * perez_function(&self.perez[2], cos_theta, gamma);
+
+        // Yxy to XYZ to linear sRGB. Y is in kcd/m^2, scaled down so the
+        // sky has a brightness similar to the other environments.
+        let luminance = big_y * self.intensity * 0.05;
+        let cx = x / y * luminance;
+        let cz = (1.0 - x - y) / y * luminance;
+        let rgb = Vec3::new(
+            3.2406 * cx - 1.5372 * luminance - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * luminance + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * luminance + 1.0570 * cz,
+        );
+        Vec3::new(f32::max(rgb.x, 0.0), f32::max(rgb.y, 0.0), f32::max(rgb.z, 0.0))
+    }
+}
+
+/**
+ *  Perez et al. sky luminance distribution, for a direction at cos_theta
+ *  from the zenith and gamma radians from the sun.
+ */
+fn perez_function(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = f32::cos(gamma);
+    (1.0 + c[0] * f32::exp(c[1] / cos_theta)) * (1.0 + c[2] * f32::exp(c[3] * gamma) + c[4] * cos_gamma * cos_gamma)
+}
@@ -0,0 +1,182 @@
+use std::{ops::{Index, IndexMut}, path::Path};
+
+use crate::{vec3::{Vec3, IVec3}, image::{Image, ImageFormat, ImageError}, hdr::save_hdr, utils::clamp};
+
+/**
+ *  Accumulated radiance of a single pixel.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pixel {
+    pub sum: Vec3,
+    pub samples: u32,
+}
+
+impl Pixel {
+    /**
+     *  Average radiance of the samples taken so far, black if none.
+     */
+    pub fn mean(&self) -> Vec3 {
+        if self.samples == 0 {
+            return Vec3::zero();
+        }
+        self.sum / self.samples as f32
+    }
+}
+
+/**
+ *  Turns linear radiance into a display color in [0, 1]. Applied to every
+ *  pixel when converting to an 8-bit image.
+ */
+pub trait PostProcess {
+    fn apply(&self, radiance: Vec3) -> Vec3;
+}
+
+/**
+ *  Plain gamma correction, values above 1 are clipped later.
+ */
+pub struct Gamma {
+    pub gamma: f32,
+}
+
+impl Default for Gamma {
+    fn default() -> Self {
+        Gamma { gamma: 2.0 }
+    }
+}
+
+impl PostProcess for Gamma {
+    fn apply(&self, radiance: Vec3) -> Vec3 {
+        // Gamma 2 is common enough to skip powf for.
+        if self.gamma == 2.0 {
+            return Vec3::new(f32::sqrt(radiance.x), f32::sqrt(radiance.y), f32::sqrt(radiance.z));
+        }
+        let inv = 1.0 / self.gamma;
+        Vec3::new(radiance.x.powf(inv), radiance.y.powf(inv), radiance.z.powf(inv))
+    }
+}
+
+/**
+ *  Reinhard tone mapping, compresses highlights instead of clipping them.
+ *  Followed by gamma correction.
+ */
+pub struct Reinhard {
+    pub exposure: f32,
+    pub gamma: Gamma,
+}
+
+impl PostProcess for Reinhard {
+    fn apply(&self, radiance: Vec3) -> Vec3 {
+        let c = radiance * self.exposure;
+        let mapped = Vec3::new(c.x / (1.0 + c.x), c.y / (1.0 + c.y), c.z / (1.0 + c.z));
+        self.gamma.apply(mapped)
+    }
+}
+
+/**
+ *  Linear radiance and sample count of every pixel in an image, or in a tile
+ *  of one. Pixel (0, 0) is the top left corner.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer { width, height, pixels: vec![Pixel::default(); width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    /**
+     *  Adds one radiance sample to pixel (x, y).
+     */
+    pub fn add_sample(&mut self, x: usize, y: usize, radiance: Vec3) {
+        let pixel = &mut self[(x, y)];
+        pixel.sum += radiance;
+        pixel.samples += 1;
+    }
+
+    /**
+     *  Adds all samples of tile to this framebuffer, with the top left corner
+     *  of the tile at (x0, y0). Parts of the tile outside are ignored.
+     */
+    pub fn merge(&mut self, tile: &Framebuffer, x0: usize, y0: usize) {
+        let w = usize::min(tile.width, self.width.saturating_sub(x0));
+        let h = usize::min(tile.height, self.height.saturating_sub(y0));
+
+        for y in 0..h {
+            for x in 0..w {
+                let src = tile[(x, y)];
+                let dst = &mut self[(x0 + x, y0 + y)];
+                dst.sum += src.sum;
+                dst.samples += src.samples;
+            }
+        }
+    }
+
+    /**
+     *  Average radiance of every pixel, row by row from the top.
+     */
+    pub fn to_linear(&self) -> Vec<Vec3> {
+        self.pixels.iter().map(|p| p.mean()).collect()
+    }
+
+    /**
+     *  Converts to a displayable 8-bit image.
+     */
+    pub fn to_image(&self, post: &dyn PostProcess) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = post.apply(self[(x, y)].mean());
+                let color = IVec3::new(
+                    (clamp(c.x, 0.0, 0.999) * 256.0) as i32,
+                    (clamp(c.y, 0.0, 0.999) * 256.0) as i32,
+                    (clamp(c.z, 0.0, 0.999) * 256.0) as i32,
+                );
+                image.set_pixel(x, y, color);
+            }
+        }
+        image
+    }
+
+    /**
+     *  Writes to path in the format given by its extension. Linear formats
+     *  get the radiance as is, the others go through post first.
+     */
+    pub fn save(&self, path: &Path, post: &dyn PostProcess) -> Result<(), ImageError> {
+        match ImageFormat::from_path(path) {
+            Some(format) if format.is_hdr() => {
+                save_hdr(path, self.width, self.height, &self.to_linear()).map_err(ImageError::Io)
+            }
+            _ => self.to_image(post).save(path),
+        }
+    }
+}
+
+impl Index<(usize, usize)> for Framebuffer {
+    type Output = Pixel;
+
+    fn index(&self, (x, y): (usize, usize)) -> &Pixel {
+        &self.pixels[y * self.width + x]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Framebuffer {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Pixel {
+        &mut self.pixels[y * self.width + x]
+    }
+}
@@ -0,0 +1,483 @@
+use std::{fs, io, path::Path};
+
+use crate::{vec3::Vec3, aabb::Aabb};
+
+// Voxels along each side of the bricks majorants are kept for.
+pub const BRICK_SIZE: usize = 8;
+
+// Largest grid files may ask for, so a corrupt header can't make us
+// allocate without bounds.
+pub const MAX_VOXELS: usize = 1 << 30;
+
+/**
+ *  Dense 3D grid of densities, like a smoke simulation, placed in the
+ *  world by a box. Values are at the voxel centers and interpolated
+ *  trilinearly in between, outside of the box the density is zero.
+ *
+ *  For tracking rays through the grid it is split into bricks of
+ *  BRICK_SIZE^3 voxels, each with a majorant: the largest density anywhere
+ *  inside it.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    // x varies fastest, then y, then z.
+    values: Vec<f32>,
+    pub bounds: Aabb,
+    bricks: [usize; 3],
+    majorants: Vec<f32>,
+}
+
+impl DensityGrid {
+    /**
+     *  Grid with resolution voxels along x, y and z, filling the unit cube.
+     *  Panics if an axis has no voxels or values doesn't hold one value per
+     *  voxel.
+     */
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> DensityGrid {
+        assert!(resolution.iter().all(|&n| n > 0), "empty grid");
+        assert_eq!(values.len(), resolution[0] * resolution[1] * resolution[2], "wrong number of voxels");
+        let bricks = resolution.map(|n| n.div_ceil(BRICK_SIZE));
+        let mut grid = DensityGrid {
+            resolution,
+            values,
+            bounds: Aabb::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0)),
+            bricks,
+            majorants: vec![0.0; bricks[0] * bricks[1] * bricks[2]],
+        };
+        grid.majorants = grid.compute_majorants();
+        grid
+    }
+
+    /**
+     *  The grid stretched to fill the box from min to max.
+     */
+    pub fn with_bounds(mut self, min: Vec3, max: Vec3) -> DensityGrid {
+        self.bounds = Aabb::new(min, max);
+        self
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(z * ny + y) * nx + x]
+    }
+
+    /**
+     *  Largest density inside brick (x, y, z). Interpolation reaches one
+     *  voxel into the neighboring bricks.
+     */
+    pub fn majorant(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [bx, by, _] = self.bricks;
+        self.majorants[(z * by + y) * bx + x]
+    }
+
+    fn compute_majorants(&self) -> Vec<f32> {
+        let [bx, by, bz] = self.bricks;
+        let mut majorants = Vec::with_capacity(bx * by * bz);
+        let range = |brick: usize, n: usize| {
+            let start = (brick * BRICK_SIZE).saturating_sub(1);
+            let end = usize::min((brick + 1) * BRICK_SIZE + 1, n);
+            start..end
+        };
+        for z in 0..bz {
+            for y in 0..by {
+                for x in 0..bx {
+                    let mut max = 0.0f32;
+                    for k in range(z, self.resolution[2]) {
+                        for j in range(y, self.resolution[1]) {
+                            for i in range(x, self.resolution[0]) {
+                                max = max.max(self.voxel(i, j, k));
+                            }
+                        }
+                    }
+                    majorants.push(max);
+                }
+            }
+        }
+        majorants
+    }
+
+    /**
+     *  Position of the world space point p in the grid, 0 to 1 inside the
+     *  bounds.
+     */
+    fn to_grid(&self, p: Vec3) -> Vec3 {
+        let extent = self.bounds.extent();
+        let d = p - self.bounds.min;
+        Vec3::new(d.x / extent.x, d.y / extent.y, d.z / extent.z)
+    }
+
+    /**
+     *  Density at the world space point p.
+     */
+    pub fn density(&self, p: Vec3) -> f32 {
+        let g = self.to_grid(p);
+        if !(0.0..=1.0).contains(&g.x) || !(0.0..=1.0).contains(&g.y) || !(0.0..=1.0).contains(&g.z) {
+            return 0.0;
+        }
+
+        // Voxel centers are at (i + 0.5) / n.
+        let [nx, ny, nz] = self.resolution;
+        let axis = |t: f32, n: usize| {
+            let x = t * n as f32 - 0.5;
+            let i = f32::floor(x);
+            let i0 = usize::min(f32::max(i, 0.0) as usize, n - 1);
+            let i1 = usize::min(f32::max(i + 1.0, 0.0) as usize, n - 1);
+            (i0, i1, x - i)
+        };
+        let (x0, x1, tx) = axis(g.x, nx);
+        let (y0, y1, ty) = axis(g.y, ny);
+        let (z0, z1, tz) = axis(g.z, nz);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), tx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), tx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), tx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), tx);
+        lerp(lerp(c00, c10, ty), lerp(c01, c11, ty), tz)
+    }
+
+    /**
+     *  Walks the bricks the ray from origin along the unit vector direction
+     *  passes between t_min and t_max, in order. visit gets the start and
+     *  end of the part inside each brick and the brick's majorant, and
+     *  returns false to stop.
+     */
+    pub fn march<F: FnMut(f32, f32, f32) -> bool>(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32, mut visit: F) {
+        // In grid space t still measures world distance along the ray.
+        let o = self.to_grid(origin);
+        let extent = self.bounds.extent();
+        let d = Vec3::new(direction.x / extent.x, direction.y / extent.y, direction.z / extent.z);
+        let (o, d) = ([o.x, o.y, o.z], [d.x, d.y, d.z]);
+
+        // Clip against the unit cube.
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            if d[a] == 0.0 {
+                if !(0.0..=1.0).contains(&o[a]) {
+                    return;
+                }
+                continue;
+            }
+            let (near, far) = ((0.0 - o[a]) / d[a], (1.0 - o[a]) / d[a]);
+            t0 = f32::max(t0, f32::min(near, far));
+            t1 = f32::min(t1, f32::max(near, far));
+        }
+        if t0 >= t1 {
+            return;
+        }
+
+        // Amanatides and Woo's traversal, over bricks instead of voxels.
+        let cell = [0, 1, 2].map(|a| BRICK_SIZE as f32 / self.resolution[a] as f32);
+        let mut brick = [0usize; 3];
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        for a in 0..3 {
+            let p = o[a] + t0 * d[a];
+            brick[a] = usize::min(f32::max(p / cell[a], 0.0) as usize, self.bricks[a] - 1);
+            if d[a] > 0.0 {
+                next[a] = t0 + ((brick[a] + 1) as f32 * cell[a] - p) / d[a];
+                delta[a] = cell[a] / d[a];
+            } else if d[a] < 0.0 {
+                next[a] = t0 + (brick[a] as f32 * cell[a] - p) / d[a];
+                delta[a] = -cell[a] / d[a];
+            }
+        }
+
+        let mut t = t0;
+        while t < t1 {
+            let a = if next[0] < next[1] && next[0] < next[2] {
+                0
+            } else if next[1] < next[2] {
+                1
+            } else {
+                2
+            };
+            let end = f32::min(next[a], t1);
+            if end > t && !visit(t, end, self.majorant(brick[0], brick[1], brick[2])) {
+                return;
+            }
+            t = end;
+
+            if d[a] > 0.0 {
+                brick[a] += 1;
+                if brick[a] == self.bricks[a] {
+                    return;
+                }
+            } else {
+                if brick[a] == 0 {
+                    return;
+                }
+                brick[a] -= 1;
+            }
+            next[a] += delta[a];
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/**
+ *  Voxel format of raw grid files.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawFormat {
+    // One byte per voxel, 0 to 255 mapping to 0 to 1.
+    U8,
+    // Little endian 32 bit floats.
+    F32,
+}
+
+/**
+ *  Parses a raw grid: nothing but the voxels, x varying fastest, then y,
+ *  then z. The resolution has to be known.
+ */
+pub fn parse_raw(bytes: &[u8], resolution: [usize; 3], format: RawFormat) -> io::Result<DensityGrid> {
+    let n = resolution[0].checked_mul(resolution[1]).and_then(|n| n.checked_mul(resolution[2]));
+    let n = match n {
+        Some(0) => return Err(invalid_data("empty grid")),
+        Some(n) if n <= MAX_VOXELS => n,
+        _ => return Err(invalid_data("grid is too large")),
+    };
+    let values: Vec<f32> = match format {
+        RawFormat::U8 => {
+            if bytes.len() != n {
+                return Err(invalid_data("file size doesn't match the resolution"));
+            }
+            bytes.iter().map(|&b| b as f32 / 255.0).collect()
+        }
+        RawFormat::F32 => {
+            if bytes.len() != 4 * n {
+                return Err(invalid_data("file size doesn't match the resolution"));
+            }
+            bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+        }
+    };
+    Ok(DensityGrid::new(resolution, values))
+}
+
+/**
+ *  Parses a dense .vol grid, the format of Mitsuba's grid volumes. Only
+ *  the subset for density grids is supported:
+ *
+ *  - the bytes 'V', 'O', 'L' and the version 3
+ *  - i32 encoding, 1 for 32 bit floats
+ *  - i32 resolution along x, y and z
+ *  - i32 channels, 1
+ *  - f32 bounds: min x, y, z, max x, y, z
+ *  - the voxels as f32, x varying fastest, then y, then z
+ *
+ *  Everything is little endian. Sparse grids are read from NanoVDB files by
+ *  parse_nvdb().
+ */
+pub fn parse_vol(bytes: &[u8]) -> io::Result<DensityGrid> {
+    const HEADER: usize = 48;
+    if bytes.len() < HEADER || &bytes[0..3] != b"VOL" {
+        return Err(invalid_data("not a .vol grid"));
+    }
+    if bytes[3] != 3 {
+        return Err(invalid_data("unsupported .vol version"));
+    }
+
+    let word = |i: usize| [bytes[4 + 4 * i], bytes[5 + 4 * i], bytes[6 + 4 * i], bytes[7 + 4 * i]];
+    let int = |i: usize| i32::from_le_bytes(word(i));
+    let float = |i: usize| f32::from_le_bytes(word(i));
+
+    if int(0) != 1 {
+        return Err(invalid_data("only float32 .vol grids are supported"));
+    }
+    let (nx, ny, nz) = (int(1), int(2), int(3));
+    if nx <= 0 || ny <= 0 || nz <= 0 {
+        return Err(invalid_data("invalid resolution"));
+    }
+    if int(4) != 1 {
+        return Err(invalid_data("only single channel .vol grids are supported"));
+    }
+    let min = Vec3::new(float(5), float(6), float(7));
+    let max = Vec3::new(float(8), float(9), float(10));
+    if !(min.x < max.x && min.y < max.y && min.z < max.z) {
+        return Err(invalid_data("invalid bounds"));
+    }
+
+    let resolution = [nx as usize, ny as usize, nz as usize];
+    let grid = parse_raw(&bytes[HEADER..], resolution, RawFormat::F32)?;
+    Ok(grid.with_bounds(min, max))
+}
+
+/**
+ *  Writes grid in the .vol format read by parse_vol().
+ */
+pub fn write_vol(grid: &DensityGrid) -> Vec<u8> {
+    let [nx, ny, nz] = grid.resolution;
+    let mut bytes = b"VOL\x03".to_vec();
+    for int in [1, nx as i32, ny as i32, nz as i32, 1] {
+        bytes.extend(int.to_le_bytes());
+    }
+    let (min, max) = (grid.bounds.min, grid.bounds.max);
+    for float in [min.x, min.y, min.z, max.x, max.y, max.z] {
+        bytes.extend(float.to_le_bytes());
+    }
+    for value in &grid.values {
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes
+}
+
+/**
+ *  Reads a .vol grid from path.
+ */
+pub fn load_vol(path: &Path) -> io::Result<DensityGrid> {
+    parse_vol(&fs::read(path)?)
+}
+
+// Sizes and offsets of the NanoVDB 32.x layout, in bytes.
+const NVDB_FILE_HEADER: usize = 16;
+const NVDB_FILE_METADATA: usize = 176;
+const NVDB_GRID_DATA: usize = 672;
+const NVDB_TREE_DATA: usize = 64;
+const NVDB_LEAF: usize = 2144;
+
+/**
+ *  Parses a NanoVDB file, as written by nanovdb_convert from an OpenVDB
+ *  fog volume. Only the subset for density grids is supported:
+ *
+ *  - version 32 files without compression (codec 0), of which the first
+ *    grid is read
+ *  - float grids (grid type 1) whose map only scales and translates
+ *  - values in leaf nodes, the 8^3 voxel blocks OpenVDB stores detail
+ *    in; constant tiles on the upper levels of the tree are ignored
+ *
+ *  The active voxels of all leaves are copied into a dense grid spanning
+ *  their bounding box, inactive voxels are zero. The grid is placed in
+ *  the world by its map, with voxel i centered at index coordinate i.
+ */
+pub fn parse_nvdb(bytes: &[u8]) -> io::Result<DensityGrid> {
+    let slice = |start: usize, len: usize| {
+        start.checked_add(len)
+            .and_then(|end| bytes.get(start..end))
+            .ok_or_else(|| invalid_data("truncated NanoVDB file"))
+    };
+    let u16_at = |at: usize| slice(at, 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |at: usize| slice(at, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let u64_at = |at: usize| slice(at, 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+    let f64_at = |at: usize| u64_at(at).map(f64::from_bits);
+
+    // File header: magic, version, grid count and codec.
+    let magic = slice(0, 8)?;
+    if &magic[..7] != b"NanoVDB" || !matches!(magic[7], b'0' | b'2') {
+        return Err(invalid_data("not a NanoVDB file"));
+    }
+    if u32_at(8)? >> 21 != 32 {
+        return Err(invalid_data("unsupported NanoVDB version"));
+    }
+    if u16_at(12)? == 0 {
+        return Err(invalid_data("NanoVDB file without grids"));
+    }
+    if u16_at(14)? != 0 {
+        return Err(invalid_data("compressed NanoVDB files are not supported"));
+    }
+
+    // Metadata of the first grid, followed by its name and the grid itself.
+    let name_size = u32_at(NVDB_FILE_HEADER + 136)? as usize;
+    let grid = (NVDB_FILE_HEADER + NVDB_FILE_METADATA).checked_add(name_size)
+        .ok_or_else(|| invalid_data("truncated NanoVDB file"))?;
+    // Offsets within the grid and tree data are safe from here on.
+    slice(grid, NVDB_GRID_DATA + NVDB_TREE_DATA)?;
+    let magic = slice(grid, 8)?;
+    if &magic[..7] != b"NanoVDB" || !matches!(magic[7], b'0' | b'1') {
+        return Err(invalid_data("not a NanoVDB grid"));
+    }
+    if u32_at(grid + 636)? != 1 {
+        return Err(invalid_data("only float NanoVDB grids are supported"));
+    }
+
+    // The map from index to world space, a row major 3x3 matrix and a
+    // translation in double precision.
+    let mut matrix = [0.0; 9];
+    for (i, m) in matrix.iter_mut().enumerate() {
+        *m = f64_at(grid + 384 + 8 * i)?;
+    }
+    if [1, 2, 3, 5, 6, 7].iter().any(|&i| matrix[i] != 0.0) || [0, 4, 8].iter().any(|&i| matrix[i] <= 0.0) {
+        return Err(invalid_data("only NanoVDB grids that are scaled and translated are supported"));
+    }
+    let scale = [matrix[0], matrix[4], matrix[8]];
+    let translation = [f64_at(grid + 528)?, f64_at(grid + 536)?, f64_at(grid + 544)?];
+
+    // The tree follows the grid, with the offset of the first leaf relative
+    // to it and the number of leaves.
+    let tree = grid + NVDB_GRID_DATA;
+    let leaf_count = u32_at(tree + 32)? as usize;
+    let leaves = usize::try_from(u64_at(tree)?).ok()
+        .and_then(|offset| tree.checked_add(offset))
+        .zip(leaf_count.checked_mul(NVDB_LEAF))
+        .ok_or_else(|| invalid_data("truncated NanoVDB file"))?;
+    let leaves = slice(leaves.0, leaves.1)?;
+
+    // Leaves are fixed size, so reading inside one can't go out of bounds.
+    // Coordinates are widened to i64 so corrupt ones can't overflow.
+    let coordinate = |leaf: &[u8], a: usize| i32::from_le_bytes(leaf[4 * a..4 * a + 4].try_into().unwrap()) as i64;
+
+    // Bounding box of the active voxels, each leaf knowing its own.
+    let mut min = [i64::MAX; 3];
+    let mut max = [i64::MIN; 3];
+    for leaf in leaves.chunks_exact(NVDB_LEAF) {
+        for a in 0..3 {
+            let start = coordinate(leaf, a);
+            min[a] = min[a].min(start);
+            max[a] = max[a].max(start + leaf[12 + a] as i64);
+        }
+    }
+    if leaf_count == 0 {
+        return Err(invalid_data("NanoVDB grid without voxels"));
+    }
+
+    let resolution = [0, 1, 2].map(|a| (max[a] - min[a] + 1) as u64);
+    let voxels = resolution[0].checked_mul(resolution[1]).and_then(|n| n.checked_mul(resolution[2]));
+    if voxels.is_none_or(|n| n > MAX_VOXELS as u64) {
+        return Err(invalid_data("NanoVDB grid is too large"));
+    }
+    let resolution = resolution.map(|n| n as usize);
+    let mut values = vec![0.0; resolution[0] * resolution[1] * resolution[2]];
+    for leaf in leaves.chunks_exact(NVDB_LEAF) {
+        // Leaves start at multiples of 8, their voxels are ordered with z
+        // varying fastest and marked active in a bit mask.
+        let origin = [0, 1, 2].map(|a| coordinate(leaf, a) & !7);
+        for n in 0..512 {
+            if leaf[16 + n / 8] & (1 << (n % 8)) == 0 {
+                continue;
+            }
+            let p = [origin[0] + (n >> 6) as i64, origin[1] + ((n >> 3) & 7) as i64, origin[2] + (n & 7) as i64];
+            if (0..3).any(|a| p[a] < min[a] || p[a] > max[a]) {
+                continue;
+            }
+            let [x, y, z] = [0, 1, 2].map(|a| (p[a] - min[a]) as usize);
+            let value = &leaf[96 + 4 * n..100 + 4 * n];
+            values[(z * resolution[1] + y) * resolution[0] + x] = f32::from_le_bytes(value.try_into().unwrap());
+        }
+    }
+
+    let world = |a: usize, index: f64| (scale[a] * index + translation[a]) as f32;
+    let bounds_min = Vec3::new(world(0, min[0] as f64 - 0.5), world(1, min[1] as f64 - 0.5), world(2, min[2] as f64 - 0.5));
+    let bounds_max = Vec3::new(world(0, max[0] as f64 + 0.5), world(1, max[1] as f64 + 0.5), world(2, max[2] as f64 + 0.5));
+    Ok(DensityGrid::new(resolution, values).with_bounds(bounds_min, bounds_max))
+}
+
+/**
+ *  Reads a NanoVDB grid from path.
+ */
+pub fn load_nvdb(path: &Path) -> io::Result<DensityGrid> {
+    parse_nvdb(&fs::read(path)?)
+}
+
+/**
+ *  Reads a raw grid from path.
+ */
+pub fn load_raw(path: &Path, resolution: [usize; 3], format: RawFormat) -> io::Result<DensityGrid> {
+    parse_raw(&fs::read(path)?, resolution, format)
+}
@@ -0,0 +1,256 @@
+use std::{fs::File, io::{self, BufRead, BufReader, BufWriter, Read, Write}, path::Path};
+
+use crate::vec3::Vec3;
+
+// Scanlines of this width can be run length encoded.
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+
+// Longest run and longest literal in the RLE scheme.
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+// Largest image we read, so a corrupt header can't make us allocate without
+// bounds.
+pub const MAX_PIXELS: usize = 1 << 28;
+
+/**
+ *  Shared-exponent encoding of a linear color: three 8-bit mantissas and an
+ *  exponent biased by 128.
+ */
+pub fn to_rgbe(color: Vec3) -> [u8; 4] {
+    let v = f32::max(color.x, f32::max(color.y, color.z));
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1), as frexp would give us. Infinities
+    // and values beyond the largest exponent saturate instead.
+    let mut e = if v.is_finite() { v.log2().floor() as i32 + 1 } else { 127 };
+    if v >= f32::powi(2.0, e) {
+        e += 1;
+    }
+    let e = e.clamp(-128, 127);
+    let scale = 256.0 / f32::powi(2.0, e);
+
+    let byte = |c: f32| (f32::max(c, 0.0) * scale) as u8;
+    [byte(color.x), byte(color.y), byte(color.z), (e + 128) as u8]
+}
+
+/**
+ *  Linear color of an RGBE pixel.
+ */
+pub fn from_rgbe(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::zero();
+    }
+    // Mantissas are stored as m * 256, sample the middle of each step.
+    let scale = f32::powi(2.0, rgbe[3] as i32 - 128 - 8);
+    Vec3::new(
+        (rgbe[0] as f32 + 0.5) * scale,
+        (rgbe[1] as f32 + 0.5) * scale,
+        (rgbe[2] as f32 + 0.5) * scale,
+    )
+}
+
+/**
+ *  A decoded Radiance .hdr image. Pixels are linear radiance, row by row
+ *  from the top.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/**
+ *  Reads a Radiance .hdr image. Only the common -Y h +X w orientation is
+ *  supported, with flat or run length encoded scanlines.
+ */
+pub fn read_hdr<R: BufRead>(mut input: R) -> io::Result<HdrImage> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("not a Radiance .hdr file"));
+    }
+
+    // Header variables up to an empty line.
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of header"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("only 32-bit_rle_rgbe is supported"));
+            }
+        }
+    }
+
+    line.clear();
+    input.read_line(&mut line)?;
+    let (height, width) = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) => (h, w),
+            _ => return Err(invalid_data("invalid resolution")),
+        },
+        _ => return Err(invalid_data("unsupported resolution line")),
+    };
+
+    let size = match width.checked_mul(height) {
+        Some(size) if size <= MAX_PIXELS && width <= MAX_PIXELS => size,
+        _ => return Err(invalid_data("image is too large")),
+    };
+
+    let mut pixels: Vec<Vec3> = Vec::with_capacity(size);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(&mut input, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|rgbe| from_rgbe(*rgbe)));
+    }
+
+    Ok(HdrImage { width, height, pixels })
+}
+
+fn read_scanline<R: Read>(input: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if width == 0 {
+        return Ok(());
+    }
+
+    let mut first = [0u8; 4];
+    input.read_exact(&mut first)?;
+
+    // Anything but the new style RLE marker starts a flat scanline.
+    let is_rle = first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+    if !is_rle || !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+        scanline[0] = first;
+        for pixel in scanline.iter_mut().skip(1) {
+            input.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid_data("scanline width mismatch"));
+    }
+
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            input.read_exact(&mut count)?;
+            let count = count[0] as usize;
+
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return Err(invalid_data("run past end of scanline"));
+                }
+                let mut value = [0u8; 1];
+                input.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[c] = value[0];
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("invalid literal in scanline"));
+                }
+                let mut values = [0u8; MAX_LITERAL];
+                input.read_exact(&mut values[..count])?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(&values[..count]) {
+                    pixel[c] = *value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ *  Reads a Radiance .hdr image from path.
+ */
+pub fn load_hdr(path: &Path) -> io::Result<HdrImage> {
+    read_hdr(BufReader::new(File::open(path)?))
+}
+
+/**
+ *  Writes a Radiance .hdr (RGBE) image. Pixels are linear radiance, row by
+ *  row from the top.
+ */
+pub fn write_hdr<W: Write>(mut out: W, width: usize, height: usize, pixels: &[Vec3]) -> io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+
+    let mut channels: [Vec<u8>; 4] = Default::default();
+    for row in pixels.chunks_exact(width) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|c| to_rgbe(*c)).collect();
+
+        if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+            for pixel in &rgbe {
+                out.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        // New style RLE: a marker, then each channel encoded on its own.
+        out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for (c, channel) in channels.iter_mut().enumerate() {
+            channel.clear();
+            channel.extend(rgbe.iter().map(|pixel| pixel[c]));
+            write_rle_channel(&mut out, channel)?;
+        }
+    }
+    out.flush()
+}
+
+/**
+ *  Runs of three or more equal bytes are written as (128 + count, value),
+ *  everything else as (count, bytes...).
+ */
+fn write_rle_channel<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < MAX_RUN && data[i + run] == data[i] {
+            run += 1;
+        }
+
+        if run >= 3 {
+            out.write_all(&[128 + run as u8, data[i]])?;
+            i += run;
+            continue;
+        }
+
+        // Collect a literal up to the start of the next run.
+        let start = i;
+        while i < data.len() && i - start < MAX_LITERAL {
+            if i + 2 < data.len() && data[i] == data[i + 1] && data[i] == data[i + 2] {
+                break;
+            }
+            i += 1;
+        }
+        out.write_all(&[(i - start) as u8])?;
+        out.write_all(&data[start..i])?;
+    }
+    Ok(())
+}
+
+/**
+ *  Writes a Radiance .hdr image to path.
+ */
+pub fn save_hdr(path: &Path, width: usize, height: usize, pixels: &[Vec3]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_hdr(&mut out, width, height, pixels)?;
+    out.flush()
+}
@@ -0,0 +1,67 @@
+use crate::{aabb::Aabb, bvh::Bvh, ray::{Intersectable, Intersection, Ray}};
+
+/**
+ *  Any primitive that can be shared between render threads.
+ */
+pub type Hittable = Box<dyn Intersectable + Send + Sync>;
+
+/**
+ *  A list of primitives of any type. Intersecting the list tests every
+ *  object, use into_bvh() for larger scenes.
+ */
+#[derive(Default)]
+pub struct HittableList {
+    objects: Vec<Hittable>,
+}
+
+impl HittableList {
+    pub fn new() -> HittableList {
+        HittableList { objects: vec![] }
+    }
+
+    pub fn add<T: Intersectable + Send + Sync + 'static>(&mut self, object: T) {
+        self.objects.push(Box::new(object));
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn objects(&self) -> &[Hittable] {
+        &self.objects
+    }
+
+    /**
+     *  Builds a BVH over the objects in the list.
+     */
+    pub fn into_bvh(self) -> Bvh<Hittable> {
+        Bvh::new(self.objects)
+    }
+}
+
+impl Intersectable for HittableList {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let mut closest_so_far = t_max;
+        let mut closest: Option<Intersection> = None;
+
+        for object in &self.objects {
+            if let Some(intersection) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = intersection.t;
+                closest = Some(intersection);
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::empty(), |b, object| Aabb::surrounding(&b, &object.bounding_box()))
+    }
+}
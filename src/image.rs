@@ -0,0 +1,259 @@
+use std::{fmt, fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use crate::vec3::IVec3;
+
+/**
+ *  File formats an image can be saved as.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Hdr,
+}
+
+impl ImageFormat {
+    /**
+     *  True for formats that store linear floating point radiance rather
+     *  than 8-bit display colors.
+     */
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ImageFormat::Hdr)
+    }
+
+    /**
+     *  Picks the format from the file extension, case insensitive.
+     */
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    UnsupportedFormat(PathBuf),
+    // An image to read, e.g. a texture, is not a .png or .jpg.
+    UnsupportedInput(PathBuf),
+    Png(png::EncodingError),
+    PngDecoding(png::DecodingError),
+    Jpeg(jpeg_decoder::Error),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "{}", err),
+            ImageError::UnsupportedFormat(path) => {
+                write!(f, "{}: unsupported image format, use .png, .ppm or .hdr", path.display())
+            }
+            ImageError::UnsupportedInput(path) => {
+                write!(f, "{}: unsupported image format, use .png or .jpg", path.display())
+            }
+            ImageError::Png(err) => write!(f, "png: {}", err),
+            ImageError::PngDecoding(err) => write!(f, "png: {}", err),
+            ImageError::Jpeg(err) => write!(f, "jpeg: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(err) => Some(err),
+            ImageError::UnsupportedFormat(_) | ImageError::UnsupportedInput(_) => None,
+            ImageError::Png(err) => Some(err),
+            ImageError::PngDecoding(err) => Some(err),
+            ImageError::Jpeg(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for ImageError {
+    fn from(err: png::EncodingError) -> ImageError {
+        ImageError::Png(err)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(err: png::DecodingError) -> ImageError {
+        ImageError::PngDecoding(err)
+    }
+}
+
+impl From<jpeg_decoder::Error> for ImageError {
+    fn from(err: jpeg_decoder::Error) -> ImageError {
+        ImageError::Jpeg(err)
+    }
+}
+
+/**
+ *  An 8-bit RGB image. Pixel (0, 0) is the top left corner.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /**
+     *  A black image.
+     */
+    pub fn new(width: usize, height: usize) -> Image {
+        Image { width, height, data: vec![0; width * height * 3] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /**
+     *  Raw RGB bytes, row by row from the top.
+     */
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /**
+     *  Sets a pixel from a color in [0, 255].
+     */
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: IVec3) {
+        let i = (y * self.width + x) * 3;
+        self.data[i] = color.x.clamp(0, 255) as u8;
+        self.data[i + 1] = color.y.clamp(0, 255) as u8;
+        self.data[i + 2] = color.z.clamp(0, 255) as u8;
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> IVec3 {
+        let i = (y * self.width + x) * 3;
+        IVec3::new(self.data[i] as i32, self.data[i + 1] as i32, self.data[i + 2] as i32)
+    }
+
+    /**
+     *  Reads a .png or .jpg image. Alpha is dropped and grayscale is
+     *  expanded to RGB.
+     */
+    pub fn load(path: &Path) -> Result<Image, ImageError> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        let file = BufReader::new(File::open(path)?);
+        match extension.as_deref() {
+            Some("png") => Image::read_png(file),
+            Some("jpg") | Some("jpeg") => Image::read_jpeg(file),
+            _ => Err(ImageError::UnsupportedInput(path.to_path_buf())),
+        }
+    }
+
+    pub fn read_png<R: Read>(input: R) -> Result<Image, ImageError> {
+        let mut decoder = png::Decoder::new(input);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels = info.color_type.samples();
+        let pixels = &buffer[..info.buffer_size()];
+        Ok(Image::from_channels(info.width as usize, info.height as usize, channels, pixels))
+    }
+
+    pub fn read_jpeg<R: Read>(input: R) -> Result<Image, ImageError> {
+        let mut decoder = jpeg_decoder::Decoder::new(input);
+        let pixels = decoder.decode()?;
+        let info = decoder.info().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing jpeg header"))?;
+
+        let channels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => 1,
+            jpeg_decoder::PixelFormat::RGB24 => 3,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported jpeg pixel format").into()),
+        };
+        Ok(Image::from_channels(info.width as usize, info.height as usize, channels, &pixels))
+    }
+
+    /**
+     *  Image from 8-bit gray, gray and alpha, RGB or RGBA pixels.
+     */
+    fn from_channels(width: usize, height: usize, channels: usize, pixels: &[u8]) -> Image {
+        let mut image = Image::new(width, height);
+        for (rgb, pixel) in image.data.chunks_exact_mut(3).zip(pixels.chunks_exact(channels)) {
+            if channels < 3 {
+                rgb.fill(pixel[0]);
+            } else {
+                rgb.copy_from_slice(&pixel[..3]);
+            }
+        }
+        image
+    }
+
+    /**
+     *  Writes the image to path, in the format given by its extension. Only
+     *  8-bit formats can be used, see hdr::save_hdr for linear images.
+     */
+    pub fn save(&self, path: &Path) -> Result<(), ImageError> {
+        let format = ImageFormat::from_path(path)
+            .filter(|format| !format.is_hdr())
+            .ok_or_else(|| ImageError::UnsupportedFormat(path.to_path_buf()))?;
+
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Png => self.write_png(&mut out)?,
+            ImageFormat::Ppm => self.write_ppm(&mut out)?,
+            ImageFormat::Hdr => unreachable!(),
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn write_png<W: Write>(&self, mut out: W) -> Result<(), ImageError> {
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Best);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /**
+     *  Binary (P6) .ppm.
+     */
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.data)?;
+        out.flush()
+    }
+
+    /**
+     *  Plain text (P3) .ppm, one pixel per line.
+     */
+    pub fn write_ppm_ascii<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+        for rgb in self.data.chunks_exact(3) {
+            writeln!(out, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
+        }
+        out.flush()
+    }
+}
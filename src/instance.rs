@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use crate::{ray::{Intersectable, Intersection, Ray}, transform::{AnimatedTransform, Transform}, aabb::Aabb, vec3::Vec3, sampler::Sampler};
+
+/**
+ *  An object placed in the scene by a transform. The object itself stays in
+ *  its own space and may be shared by any number of instances.
+ *
+ *  Rays are moved into object space without normalizing the direction, so
+ *  distances along them mean the same in both spaces.
+ */
+pub struct Instance<T: ?Sized> {
+    pub object: Arc<T>,
+    pub transform: Transform,
+    // Replaces transform for instances that move, looked up at the time of
+    // each ray.
+    pub motion: Option<AnimatedTransform>,
+    // World space bounds, cached since the BVH asks for them repeatedly.
+    bounds: Aabb,
+}
+
+impl<T: Intersectable + ?Sized> Instance<T> {
+    pub fn new(object: Arc<T>, transform: Transform) -> Instance<T> {
+        let bounds = transform.bounds(&object.bounding_box());
+        Instance { object, transform, motion: None, bounds }
+    }
+
+    /**
+     *  An instance following motion. Its bounds cover the whole animation.
+     */
+    pub fn animated(object: Arc<T>, motion: AnimatedTransform) -> Instance<T> {
+        let bounds = motion.bounds(&object.bounding_box());
+        let transform = motion.at(motion.keyframes()[0].time);
+        Instance { object, transform, motion: Some(motion), bounds }
+    }
+
+    pub fn transform_at(&self, time: f32) -> Transform {
+        match &self.motion {
+            Some(motion) => motion.at(time),
+            None => self.transform,
+        }
+    }
+}
+
+impl<T: Intersectable + ?Sized> Intersectable for Instance<T> {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let transform = self.transform_at(r.time);
+        let to_object = transform.inverse();
+        let local = Ray::new(to_object.point(r.origin), to_object.vector(r.direction)).with_time(r.time);
+        let mut intersection = self.object.hit(&local, t_min, t_max)?;
+
+        // The inverse transpose keeps the normal on the side of the surface
+        // it was on, so front_face stays valid.
+        intersection.point = transform.point(intersection.point);
+        intersection.normal = transform.normal(intersection.normal).normalized();
+        intersection.dpdu = transform.vector(intersection.dpdu);
+        intersection.dpdv = transform.vector(intersection.dpdv);
+        Some(intersection)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    /**
+     *  Density of the object in its own space, changed to world space by
+     *  how much the transform stretches solid angle around the direction.
+     */
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let transform = self.transform_at(time);
+        let local = transform.inverse().vector(direction);
+        let pdf = self.object.pdf_value(transform.inverse().point(origin), local, time);
+        if pdf <= 0.0 {
+            return 0.0;
+        }
+        let stretch = transform.vector(local.normalized()).length();
+        let det = transform.matrix.determinant3().abs();
+        pdf * stretch * stretch * stretch / det
+    }
+
+    fn random_direction(&self, origin: Vec3, time: f32, sampler: &mut Sampler) -> Vec3 {
+        let transform = self.transform_at(time);
+        let local_origin = transform.inverse().point(origin);
+        transform.vector(self.object.random_direction(local_origin, time, sampler))
+    }
+}
@@ -1,6 +1,32 @@
 pub mod vec3;
+pub mod transform;
 pub mod ray;
 pub mod sphere;
+pub mod triangle;
+pub mod plane;
+pub mod cuboid;
+pub mod cylinder;
+pub mod torus;
+pub mod obj;
 pub mod camera;
 pub mod utils;
-pub mod material;
\ No newline at end of file
+pub mod sampler;
+pub mod material;
+pub mod texture;
+pub mod aabb;
+pub mod onb;
+pub mod microfacet;
+pub mod medium;
+pub mod grid;
+pub mod light;
+pub mod bvh;
+pub mod instance;
+pub mod hittable_list;
+pub mod scene;
+pub mod cli;
+pub mod image;
+
+pub mod hdr;
+pub mod environment;
+pub mod framebuffer;
+pub mod render;
\ No newline at end of file
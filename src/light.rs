@@ -0,0 +1,186 @@
+use crate::{
+    vec3::Vec3,
+    ray::Intersectable,
+    hittable_list::Hittable,
+    sampler::Sampler,
+    onb::{Onb, random_in_cone},
+    utils::{clamp, deg_to_rad},
+};
+
+/**
+ *  Lights that are not geometry: they can't be hit by rays and are only
+ *  found by sampling them directly.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PunctualLight {
+    // Radiant intensity falling off with the squared distance.
+    Point { position: Vec3, intensity: Vec3 },
+    // A point light restricted to a cone, fading out between the inner and
+    // outer angle.
+    Spot { position: Vec3, direction: Vec3, intensity: Vec3, cos_inner: f32, cos_outer: f32 },
+    // Parallel light from far away, like the sun. A non-zero angular radius
+    // gives soft shadows.
+    Directional { direction: Vec3, irradiance: Vec3, cos_radius: f32 },
+}
+
+/**
+ *  Light arriving at a point from a punctual light.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+    // Unit vector towards the light.
+    pub direction: Vec3,
+    // Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    // Irradiance on a surface facing the light.
+    pub light: Vec3,
+}
+
+impl PunctualLight {
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> PunctualLight {
+        PunctualLight::Point { position, intensity: color * intensity }
+    }
+
+    /**
+     *  A spot light at position shining along direction. The angles are
+     *  measured from the axis in degrees, the light fades out between them.
+     */
+    pub fn spot(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, inner: f32, outer: f32) -> PunctualLight {
+        PunctualLight::Spot {
+            position,
+            direction: direction.normalized(),
+            intensity: color * intensity,
+            cos_inner: f32::cos(deg_to_rad(f32::min(inner, outer))),
+            cos_outer: f32::cos(deg_to_rad(outer)),
+        }
+    }
+
+    /**
+     *  Light travelling along direction. angle is the angular diameter of
+     *  the light source in degrees, about 0.5 for the real sun.
+     */
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32, angle: f32) -> PunctualLight {
+        PunctualLight::Directional {
+            direction: direction.normalized(),
+            irradiance: color * intensity,
+            cos_radius: f32::cos(deg_to_rad(angle / 2.0)),
+        }
+    }
+
+    /**
+     *  Light arriving at point, not taking occlusion into account. None if
+     *  the point is not lit at all, or sits right on a point or spot light.
+     */
+    pub fn sample(&self, point: Vec3, sampler: &mut Sampler) -> Option<LightSample> {
+        match *self {
+            PunctualLight::Point { position, intensity } => {
+                let to_light = position - point;
+                let distance = to_light.length();
+                if distance == 0.0 {
+                    return None;
+                }
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    light: intensity / (distance * distance),
+                })
+            }
+            PunctualLight::Spot { position, direction, intensity, cos_inner, cos_outer } => {
+                let to_light = position - point;
+                let distance = to_light.length();
+                if distance == 0.0 {
+                    return None;
+                }
+                let cos_theta = -to_light.dot(direction) / distance;
+                if cos_theta <= cos_outer {
+                    return None;
+                }
+
+                // Smoothstep from the outer to the inner edge of the cone.
+                let t = if cos_inner > cos_outer {
+                    clamp((cos_theta - cos_outer) / (cos_inner - cos_outer), 0.0, 1.0)
+                } else {
+                    1.0
+                };
+                let falloff = t * t * (3.0 - 2.0 * t);
+
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    light: intensity * (falloff / (distance * distance)),
+                })
+            }
+            PunctualLight::Directional { direction, irradiance, cos_radius } => {
+                let to_light = if cos_radius < 1.0 {
+                    Onb::from_w(-direction).local(random_in_cone(sampler, cos_radius))
+                } else {
+                    -direction
+                };
+                Some(LightSample { direction: to_light, distance: f32::INFINITY, light: irradiance })
+            }
+        }
+    }
+}
+
+/**
+ *  The lights of a scene. Emissive objects are sampled to find light faster
+ *  than by bouncing into it, they are also part of the world and this only
+ *  holds copies to sample from. Punctual lights exist only here.
+ */
+#[derive(Default)]
+pub struct LightList {
+    shapes: Vec<Hittable>,
+    punctual: Vec<PunctualLight>,
+}
+
+impl LightList {
+    pub fn new() -> LightList {
+        LightList { shapes: vec![], punctual: vec![] }
+    }
+
+    pub fn add<T: Intersectable + Send + Sync + 'static>(&mut self, light: T) {
+        self.shapes.push(Box::new(light));
+    }
+
+    pub fn add_punctual(&mut self, light: PunctualLight) {
+        self.punctual.push(light);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shapes.len() + self.punctual.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty() && self.punctual.is_empty()
+    }
+
+    pub fn has_shapes(&self) -> bool {
+        !self.shapes.is_empty()
+    }
+
+    pub fn punctual(&self) -> &[PunctualLight] {
+        &self.punctual
+    }
+
+    /**
+     *  Direction from origin towards a random point on an emissive object
+     *  picked uniformly from the list, as it is at time. There has to be at
+     *  least one.
+     */
+    pub fn random_direction(&self, origin: Vec3, time: f32, sampler: &mut Sampler) -> Vec3 {
+        let index = (sampler.next_f32() * self.shapes.len() as f32) as usize;
+        self.shapes[index.min(self.shapes.len() - 1)].random_direction(origin, time, sampler)
+    }
+
+    /**
+     *  Solid angle density of random_direction() returning direction. Lights
+     *  may overlap as seen from origin, so all of them are asked.
+     */
+    pub fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        if self.shapes.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self.shapes.iter().map(|light| light.pdf_value(origin, direction, time)).sum();
+        sum / self.shapes.len() as f32
+    }
+}
@@ -1,114 +1,732 @@
-use crate::{vec3::Vec3, ray::{Intersection, Ray}, utils::random_f32};
+use std::f32::consts::PI;
+
+use crate::{vec3::Vec3, ray::{Intersection, Ray}, sampler::Sampler, texture::{TextureRef, solid}, onb::Onb, microfacet::{Conductor, Ggx, reflect, refract, fresnel_dielectric}, medium::Medium};
+
+// Step in u and v for the finite differences of bump maps.
+const BUMP_DELTA: f32 = 1e-3;
+
+// Metals and dielectrics smoother than this are perfect mirrors.
+const SPECULAR_ROUGHNESS: f32 = 0.01;
+
+// Principled materials mix several lobes and are never perfect mirrors,
+// their roughness is kept above this.
+const MIN_PRINCIPLED_ROUGHNESS: f32 = 0.02;
 
 #[derive(Clone, Copy)]
 pub enum MaterialType {
     Diffuse,
     Metal,
     Dielectric,
+    Emissive,
+    Principled,
+    // No surface at all, light passes straight through. Marks the boundary
+    // of a medium.
+    Interface,
 }
 
-#[derive(Clone, Copy)]
+/**
+ *  The knobs of a principled material, after Burley, "Physically Based
+ *  Shading at Disney" (2012) and its 2015 extension with transmission. Base
+ *  color, roughness, anisotropy, ir and emission are the ones of Material,
+ *  all of these are between 0 and 1.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Principled {
+    // Blends from a dielectric to a metal tinted by the base color.
+    pub metallic: f32,
+    // Reflectance of the dielectric part, 0.5 is 4% at normal incidence.
+    pub specular: f32,
+    // Tints dielectric reflections towards the base color.
+    pub specular_tint: f32,
+    // A white isotropic specular layer on top, like varnish.
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    // Extra reflection at grazing angles, for cloth.
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    // Light refracted into the surface instead of diffused, like glass.
+    pub transmission: f32,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            metallic: 0.0,
+            specular: 0.5,
+            specular_tint: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            transmission: 0.0,
+        }
+    }
+}
+
+/**
+ *  Small scale surface structure that only changes the shading normal.
+ */
+#[derive(Clone)]
+pub enum SurfaceDetail {
+    // Tangent space normals, colors 0..1 mapping to -1..1 along the u
+    // tangent, the v tangent and the normal. Should be a linear texture.
+    NormalMap(TextureRef),
+    // Height along the normal, the first channel times scale.
+    BumpMap { height: TextureRef, scale: f32 },
+}
+
+/**
+ *  How a surface reflects, refracts or emits light. Color, roughness and
+ *  emission are textures so they can vary over the surface.
+ */
+#[derive(Clone)]
 pub struct Material {
-    pub albedo: Vec3,
-    pub roughness: f32,
+    pub albedo: TextureRef,
+    // Grayscale, only the first channel is used.
+    pub roughness: TextureRef,
     pub ir: f32,
     pub material_type: MaterialType,
+    // Radiance given off by the front side of the surface.
+    pub emission: TextureRef,
+    pub detail: Option<SurfaceDetail>,
+
+    // Metals: the complex index of refraction, tinted by albedo. Without
+    // one albedo is the reflectance at normal incidence.
+    pub conductor: Option<Conductor>,
+    // Metals: -1 to 1, stretches highlights along the u tangent when
+    // positive and along v when negative.
+    pub anisotropy: f32,
+
+    // Dielectrics: fraction of light absorbed per unit distance traveled
+    // inside, per channel.
+    pub absorption: Vec3,
+    // Dielectrics: an infinitely thin sheet, like a window or a bubble,
+    // that light passes straight through. Transmission is tinted by albedo.
+    pub thin: bool,
+
+    pub principled: Principled,
+
+    // What the inside of a closed object is filled with, scattering light
+    // that travels through it.
+    pub medium: Option<Medium>,
 }
 
 impl Material {
 
     pub fn new(albedo: Vec3, roughness: f32, ir: f32, material_type: MaterialType) -> Material {
         Material {
-            albedo,
-            roughness,
+            albedo: solid(albedo),
+            roughness: solid(Vec3::new(roughness, roughness, roughness)),
             ir,
             material_type,
+            emission: solid(Vec3::zero()),
+            detail: None,
+            conductor: None,
+            anisotropy: 0.0,
+            absorption: Vec3::zero(),
+            thin: false,
+            principled: Principled::default(),
+            medium: None,
+        }
+    }
+
+    /**
+     *  An invisible boundary around a volume of medium, like smoke.
+     */
+    pub fn interface(medium: Medium) -> Material {
+        Material { medium: Some(medium), ..Material::new(Vec3::zero(), 0.0, 1.0, MaterialType::Interface) }
+    }
+
+    pub fn is_interface(&self) -> bool {
+        matches!(self.material_type, MaterialType::Interface)
+    }
+
+    /**
+     *  A principled material with base color and an index of refraction of
+     *  1.5 for the transmission.
+     */
+    pub fn principled(base_color: Vec3, roughness: f32, principled: Principled) -> Material {
+        Material { principled, ..Material::new(base_color, roughness, 1.5, MaterialType::Principled) }
+    }
+
+    /**
+     *  A light that emits color scaled by intensity and reflects nothing.
+     */
+    pub fn emissive(color: Vec3, intensity: f32) -> Material {
+        Material {
+            emission: solid(color * intensity),
+            ..Material::new(Vec3::zero(), 0.0, 0.0, MaterialType::Emissive)
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.constant() != Some(Vec3::zero())
+    }
+
+    pub fn albedo_at(&self, intersection: &Intersection) -> Vec3 {
+        self.albedo.value(intersection.u, intersection.v, intersection.point)
+    }
+
+    pub fn roughness_at(&self, intersection: &Intersection) -> f32 {
+        self.roughness.value(intersection.u, intersection.v, intersection.point).x
+    }
+
+    /**
+     *  Radiance emitted towards the ray that produced intersection. Only the
+     *  front face glows, so a light can't shine into the object it sits on.
+     */
+    pub fn emitted(&self, intersection: &Intersection) -> Vec3 {
+        if intersection.front_face {
+            self.emission.value(intersection.u, intersection.v, intersection.point)
+        } else {
+            Vec3::zero()
         }
     }
     
-    fn lambertian_scatter(&self, _r_in: &Ray, intersection: &Intersection) -> (bool, Vec3, Ray) {
-        let mut scatter_direction = intersection.normal + Vec3::random_unit_vector();
+    /**
+     *  Tilts the shading normal of intersection by the normal or bump map of
+     *  the material. Has to run before scatter(), eval() and pdf().
+     */
+    pub fn apply_detail(&self, r_in: &Ray, intersection: &mut Intersection) {
+        let detail = match &self.detail {
+            Some(detail) => detail,
+            None => return,
+        };
+
+        let n = intersection.normal;
+        let (u, v, point) = (intersection.u, intersection.v, intersection.point);
+        let (dpdu, dpdv) = tangent_frame(intersection);
+
+        let perturbed = match detail {
+            SurfaceDetail::NormalMap(texture) => {
+                let c = 2.0 * texture.value(u, v, point) - Vec3::new(1.0, 1.0, 1.0);
+                let tangent = (dpdu - n * n.dot(dpdu)).normalized();
+                let mut bitangent = n.cross(tangent);
+                if bitangent.dot(dpdv) < 0.0 {
+                    bitangent = -bitangent;
+                }
+                c.x * tangent + c.y * bitangent + c.z * n
+            }
+            SurfaceDetail::BumpMap { height, scale } => {
+                let h = |du: f32, dv: f32| {
+                    *scale * height.value(u + du, v + dv, point + du * dpdu + dv * dpdv).x
+                };
+                let h0 = h(0.0, 0.0);
+                let slope_u = (h(BUMP_DELTA, 0.0) - h0) / BUMP_DELTA;
+                let slope_v = (h(0.0, BUMP_DELTA) - h0) / BUMP_DELTA;
+
+                let m = (dpdu + slope_u * n).cross(dpdv + slope_v * n);
+                if m.dot(n) < 0.0 { -m } else { m }
+            }
+        };
+
+        // Normals tilted away from the viewer would let light through the
+        // surface, keep the geometric one for those.
+        let length = perturbed.length();
+        if length > 0.0 && length.is_finite() && perturbed.dot(r_in.direction) < 0.0 {
+            intersection.normal = perturbed / length;
+        }
+    }
+
+    fn lambertian_scatter(&self, _r_in: &Ray, intersection: &Intersection, sampler: &mut Sampler) -> (bool, Vec3, Ray) {
+        let mut scatter_direction = intersection.normal + Vec3::random_unit_vector(sampler);
 
         if scatter_direction.near_zero() {
             scatter_direction = intersection.normal;
         }
 
         let scattered = Ray::new(intersection.point, scatter_direction);
-        let attenuation = self.albedo;
+        let attenuation = self.albedo_at(intersection);
         (true, attenuation, scattered)
     }
 
-    fn metal_scatter(&self, r_in: &Ray, intersection: &Intersection) -> (bool, Vec3, Ray) {
-        let reflected = Vec3::reflect(r_in.direction.normalized(), intersection.normal);
+    /**
+     *  GGX microfacet distribution at intersection.
+     */
+    fn ggx(&self, intersection: &Intersection) -> Ggx {
+        Ggx::from_roughness(self.roughness_at(intersection), self.anisotropy)
+    }
+
+    /**
+     *  Reflectance of a metal at cos_theta from the (micro) normal.
+     */
+    fn metal_fresnel(&self, intersection: &Intersection, cos_theta: f32) -> Vec3 {
+        let albedo = self.albedo_at(intersection);
+        match &self.conductor {
+            Some(conductor) => albedo * conductor.fresnel(cos_theta),
+            // Schlick's approximation with albedo as the reflectance at
+            // normal incidence.
+            None => albedo + (Vec3::new(1.0, 1.0, 1.0) - albedo) * f32::powi(1.0 - cos_theta, 5),
+        }
+    }
 
-        let scattered = Ray::new(
-            intersection.point, 
-            reflected + self.roughness * Vec3::random_in_unit_sphere()
-        );
+    fn metal_scatter(&self, r_in: &Ray, intersection: &Intersection, sampler: &mut Sampler) -> (bool, Vec3, Ray) {
+        let frame = shading_frame(intersection);
+        let wo = frame.to_local(-r_in.direction.normalized());
 
-        let attenuation = self.albedo;
-        
-        if scattered.direction.dot(intersection.normal) > 0.0 {
-            (true, attenuation, scattered)
+        if self.roughness_at(intersection) < SPECULAR_ROUGHNESS {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            let attenuation = self.metal_fresnel(intersection, f32::max(wo.z, 0.0));
+            return (wo.z > 0.0, attenuation, Ray::new(intersection.point, frame.local(wi)));
+        }
+
+        // Sample a microfacet normal seen from wo and mirror around it. The
+        // weight f * cos / pdf simplifies to F * G2 / G1.
+        let ggx = self.ggx(intersection);
+        let h = ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32());
+        let wi = reflect(wo, h);
+        let scattered = Ray::new(intersection.point, frame.local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (false, Vec3::zero(), scattered);
+        }
+
+        let attenuation = self.metal_fresnel(intersection, wo.dot(h)) * (ggx.g2(wo, wi) / ggx.g1(wo));
+        (true, attenuation, scattered)
+    }
+
+    /**
+     *  Index of refraction on the far side of the surface over the one on
+     *  the side of the ray.
+     */
+    fn relative_ir(&self, intersection: &Intersection) -> f32 {
+        if intersection.front_face || self.thin {
+            self.ir
         } else {
-            (false, attenuation, scattered)
+            1.0 / self.ir
         }
     }
 
-    fn dielectric_scatter(&self, r_in: &Ray, intersection: &Intersection,) -> (bool, Vec3, Ray) {
-        let attenuation = Vec3::new(1.0, 1.0, 1.0);
-        
-        let mut refraction_ratio = self.ir;
-        if intersection.front_face {
-            refraction_ratio = 1.0 / self.ir;
+    /**
+     *  Reflectance of a dielectric at cos_i from the (micro) normal. Thin
+     *  sheets add the light bouncing back and forth between their sides.
+     */
+    fn dielectric_fresnel(&self, cos_i: f32, eta: f32) -> f32 {
+        let r = fresnel_dielectric(cos_i, eta);
+        if self.thin {
+            2.0 * r / (1.0 + r)
+        } else {
+            r
+        }
+    }
+
+    /**
+     *  Whether light can refract into closed objects of this material and
+     *  travel through their inside.
+     */
+    pub fn is_refractive(&self) -> bool {
+        match self.material_type {
+            MaterialType::Dielectric => !self.thin,
+            MaterialType::Principled => self.principled.transmission > 0.0,
+            _ => false,
+        }
+    }
+
+    /**
+     *  Fraction of light left after traveling distance through the inside
+     *  of the material to reach intersection, following Beer-Lambert.
+     */
+    pub fn transmittance(&self, intersection: &Intersection, distance: f32) -> Vec3 {
+        if !self.is_refractive() || intersection.front_face {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }
+        let a = self.absorption;
+        Vec3::new(f32::exp(-a.x * distance), f32::exp(-a.y * distance), f32::exp(-a.z * distance))
+    }
+
+    fn dielectric_scatter(&self, r_in: &Ray, intersection: &Intersection, sampler: &mut Sampler) -> (bool, Vec3, Ray) {
+        let frame = shading_frame(intersection);
+        let wo = frame.to_local(-r_in.direction.normalized());
+        let eta = self.relative_ir(intersection);
+
+        // Smooth surfaces reflect and refract around the normal itself.
+        let ggx = self.ggx(intersection);
+        let smooth = self.is_specular(intersection);
+        let h = if smooth {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32())
+        };
+
+        let (wi, tint) = match self.reflect_or_refract(intersection, wo, h, eta, sampler) {
+            Some(sample) => sample,
+            None => return (false, Vec3::zero(), Ray::new(intersection.point, intersection.normal)),
+        };
+        let scattered = Ray::new(intersection.point, frame.local(wi));
+
+        let attenuation = if smooth { tint } else { tint * (ggx.g2(wo, wi) / ggx.g1(wo)) };
+        (true, attenuation, scattered)
+    }
+
+    /**
+     *  Reflects wo around the microfacet normal h or refracts it, picked by
+     *  the Fresnel term, which then cancels from the weight. Returns the
+     *  direction with the tint of thin sheets, None if the microfacet sends
+     *  it to the wrong side of the surface.
+     */
+    fn reflect_or_refract(&self, intersection: &Intersection, wo: Vec3, h: Vec3, eta: f32, sampler: &mut Sampler) -> Option<(Vec3, Vec3)> {
+        let cos_i = wo.dot(h);
+        let mut tint = Vec3::new(1.0, 1.0, 1.0);
+        let (wi, reflected) = if sampler.next_f32() < self.dielectric_fresnel(cos_i, eta) {
+            (reflect(wo, h), true)
+        } else if self.thin {
+            let r = reflect(wo, h);
+            tint = self.albedo_at(intersection);
+            (Vec3::new(r.x, r.y, -r.z), false)
+        } else {
+            match refract(wo, h, eta) {
+                Some(wi) => (wi, false),
+                None => (reflect(wo, h), true),
+            }
+        };
+
+        if wo.z <= 0.0 || cos_i <= 0.0 || (wi.z > 0.0) != reflected {
+            return None;
+        }
+        Some((wi, tint))
+    }
+
+    /**
+     *  Microfacet normal between wo and a transmitted wi, facing +z. None
+     *  if there is none.
+     */
+    fn transmission_half_vector(wo: Vec3, wi: Vec3, eta: f32) -> Option<Vec3> {
+        let h = wo + eta * wi;
+        if h.length_squared() < 1e-12 {
+            return None;
+        }
+        let h = h.normalized();
+        let h = if h.z < 0.0 { -h } else { h };
+        if wo.dot(h) <= 0.0 || wi.dot(h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    /**
+     *  BSDF times cosine and density of picking wi, for a rough dielectric
+     *  in the local shading frame.
+     */
+    fn rough_dielectric(&self, intersection: &Intersection, ggx: &Ggx, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        let none = (Vec3::zero(), 0.0);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return none;
+        }
+        let eta = self.relative_ir(intersection);
+
+        if wi.z > 0.0 || self.thin {
+            // Thin sheets pass light through as if reflected and mirrored
+            // through the surface.
+            let mirrored = Vec3::new(wi.x, wi.y, wi.z.abs());
+            let h = (wo + mirrored).normalized();
+            let fresnel = self.dielectric_fresnel(wo.dot(h), eta);
+            let (weight, tint) = if wi.z > 0.0 {
+                (fresnel, Vec3::new(1.0, 1.0, 1.0))
+            } else {
+                (1.0 - fresnel, self.albedo_at(intersection))
+            };
+            let f = tint * (weight * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z));
+            let pdf = weight * ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h));
+            return (f, pdf);
         }
 
-        let unit_direction = r_in.direction.normalized();
+        let h = match Material::transmission_half_vector(wo, wi, eta) {
+            Some(h) => h,
+            None => return none,
+        };
+        let (cos_o, cos_i) = (wo.dot(h), wi.dot(h).abs());
+        let denom = (cos_o - eta * cos_i) * (cos_o - eta * cos_i);
+        let weight = 1.0 - self.dielectric_fresnel(cos_o, eta);
+        // Change of variables from the half vector to wi.
+        let dh_dwi = eta * eta * cos_i / denom;
+
+        let f = weight * ggx.d(h) * ggx.g2(wo, wi) * cos_o * dh_dwi / wo.z;
+        let pdf = weight * ggx.pdf_visible(wo, h) * dh_dwi;
+        (Vec3::new(f, f, f), pdf)
+    }
 
-        let cos_theta = f32::min(-unit_direction.dot(intersection.normal), 1.0);
-        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+    /**
+     *  Probabilities of sampling the diffuse (with sheen), specular,
+     *  clearcoat and transmission lobes of a principled material, roughly
+     *  following how much light each of them reflects.
+     */
+    fn principled_lobes(&self, base_color: Vec3) -> [f32; 4] {
+        let p = &self.principled;
+        let dielectric = 1.0 - p.metallic;
+        let diffuse = dielectric * (1.0 - p.transmission);
+        let specular = (1.0 - dielectric * p.transmission) * f32::max(luminance(self.principled_f0(base_color)), 0.2);
+        let clearcoat = 0.25 * p.clearcoat;
+        let transmission = dielectric * p.transmission;
+
+        let total = diffuse + specular + clearcoat + transmission;
+        [diffuse / total, specular / total, clearcoat / total, transmission / total]
+    }
+
+    /**
+     *  Specular reflectance at normal incidence of a principled material.
+     */
+    fn principled_f0(&self, base_color: Vec3) -> Vec3 {
+        let p = &self.principled;
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let dielectric = 0.08 * p.specular * lerp(white, tint_color(base_color), p.specular_tint);
+        lerp(dielectric, base_color, p.metallic)
+    }
 
-        let mut total_reflection = false;
-        if refraction_ratio * sin_theta > 1.0 {
-            total_reflection = true;
+    fn principled_ggx(&self, intersection: &Intersection) -> (Ggx, Ggx) {
+        let roughness = f32::max(self.roughness_at(intersection), MIN_PRINCIPLED_ROUGHNESS);
+        let clearcoat_roughness = f32::max(self.principled.clearcoat_roughness, MIN_PRINCIPLED_ROUGHNESS);
+        (
+            Ggx::from_roughness(roughness, self.anisotropy),
+            Ggx::from_roughness(clearcoat_roughness, 0.0),
+        )
+    }
+
+    /**
+     *  BSDF times cosine and density of scattering towards wi, of a
+     *  principled material in the local shading frame. The density is the
+     *  mixture of the densities of the lobes.
+     */
+    fn principled_eval(&self, intersection: &Intersection, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (Vec3::zero(), 0.0);
         }
+        let p = &self.principled;
+        let base_color = self.albedo_at(intersection);
+        let lobes = self.principled_lobes(base_color);
+        let (ggx, clearcoat_ggx) = self.principled_ggx(intersection);
+        let dielectric = 1.0 - p.metallic;
+
+        let f0 = self.principled_f0(base_color);
+        // Light reflected by the clearcoat and the specular layer doesn't
+        // reach the layers below them.
+        let coated = 1.0 - p.clearcoat * schlick_fresnel(0.04, wo.z);
+        let below_specular = 1.0 - luminance(f0) - (1.0 - luminance(f0)) * f32::powi(1.0 - wo.z, 5);
+
+        let mut f = Vec3::zero();
+        let mut pdf = 0.0;
 
-        let direction;
-        if total_reflection || reflectance(cos_theta, refraction_ratio) > random_f32() {
-            direction = Vec3::reflect(unit_direction, intersection.normal);
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalized();
+            let cos_d = wi.dot(h);
+            let schlick = f32::powi(1.0 - cos_d, 5);
+
+            let sheen = p.sheen * schlick * lerp(Vec3::new(1.0, 1.0, 1.0), tint_color(base_color), p.sheen_tint);
+            let diffuse = (base_color / PI) * below_specular + sheen;
+            f += coated * dielectric * (1.0 - p.transmission) * diffuse * wi.z;
+            pdf += lobes[0] * wi.z / PI;
+
+            let fresnel = f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * schlick;
+            let weight = coated * (1.0 - dielectric * p.transmission);
+            f += weight * fresnel * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z));
+            pdf += lobes[1] * ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h));
+
+            let fresnel = schlick_fresnel(0.04, cos_d);
+            let clearcoat = p.clearcoat * fresnel * clearcoat_ggx.d(h) * clearcoat_ggx.g2(wo, wi) / (4.0 * wo.z);
+            f += Vec3::new(clearcoat, clearcoat, clearcoat);
+            pdf += lobes[2] * clearcoat_ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h));
+        }
+
+        if lobes[3] > 0.0 {
+            let (transmitted, transmitted_pdf) = self.rough_dielectric(intersection, &ggx, wo, wi);
+            // Tinted once on the way in.
+            let tint = if wi.z < 0.0 && intersection.front_face { base_color } else { Vec3::new(1.0, 1.0, 1.0) };
+            f += coated * dielectric * p.transmission * tint * transmitted;
+            pdf += lobes[3] * transmitted_pdf;
+        }
+
+        (f, pdf)
+    }
+
+    fn principled_scatter(&self, r_in: &Ray, intersection: &Intersection, sampler: &mut Sampler) -> (bool, Vec3, Ray) {
+        let frame = shading_frame(intersection);
+        let wo = frame.to_local(-r_in.direction.normalized());
+        let absorbed = (false, Vec3::zero(), Ray::new(intersection.point, intersection.normal));
+        if wo.z <= 0.0 {
+            return absorbed;
+        }
+
+        let lobes = self.principled_lobes(self.albedo_at(intersection));
+        let (ggx, clearcoat_ggx) = self.principled_ggx(intersection);
+
+        let u = sampler.next_f32();
+        let wi = if u < lobes[0] {
+            let direction = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector(sampler);
+            if direction.near_zero() { Vec3::new(0.0, 0.0, 1.0) } else { direction.normalized() }
+        } else if u < lobes[0] + lobes[1] {
+            reflect(wo, ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32()))
+        } else if u < lobes[0] + lobes[1] + lobes[2] {
+            reflect(wo, clearcoat_ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32()))
         } else {
-            direction = Vec3::refract(unit_direction, intersection.normal, refraction_ratio);
+            let h = ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32());
+            match self.reflect_or_refract(intersection, wo, h, self.relative_ir(intersection), sampler) {
+                Some((wi, _)) => wi,
+                None => return absorbed,
+            }
+        };
+
+        // Weighted against all lobes that could have picked wi.
+        let (f, pdf) = self.principled_eval(intersection, wo, wi);
+        if pdf <= 0.0 {
+            return absorbed;
         }
+        (true, f / pdf, Ray::new(intersection.point, frame.local(wi)))
+    }
 
-        let scattered = Ray::new(intersection.point, direction);
+    /**
+     *  Materials that scatter into a single direction (or a few) can't make
+     *  use of light sampling, their scattered rays are followed as they are.
+     */
+    pub fn is_specular(&self, intersection: &Intersection) -> bool {
+        match self.material_type {
+            MaterialType::Metal | MaterialType::Dielectric => self.roughness_at(intersection) < SPECULAR_ROUGHNESS,
+            MaterialType::Interface => true,
+            _ => false,
+        }
+    }
 
-        (true, attenuation, scattered)
+    /**
+     *  BSDF times the cosine towards direction, the light reflected into
+     *  r_in from direction. Zero for specular materials.
+     */
+    pub fn eval(&self, r_in: &Ray, intersection: &Intersection, direction: Vec3) -> Vec3 {
+        if self.is_specular(intersection) {
+            return Vec3::zero();
+        }
+        match self.material_type {
+            MaterialType::Diffuse => {
+                let cosine = intersection.normal.dot(direction.normalized());
+                if cosine <= 0.0 {
+                    return Vec3::zero();
+                }
+                self.albedo_at(intersection) * cosine / PI
+            }
+            MaterialType::Metal => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return Vec3::zero();
+                }
+                let h = (wo + wi).normalized();
+                let ggx = self.ggx(intersection);
+                // F * D * G2 / (4 cos_o cos_i), times cos_i.
+                self.metal_fresnel(intersection, wi.dot(h)) * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z))
+            }
+            MaterialType::Dielectric => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                self.rough_dielectric(intersection, &self.ggx(intersection), wo, wi).0
+            }
+            MaterialType::Principled => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                self.principled_eval(intersection, wo, wi).0
+            }
+            _ => Vec3::zero(),
+        }
     }
 
-    pub fn scatter(&self, r_in: &Ray, intersection: &Intersection) -> (bool, Vec3, Ray) {
+    /**
+     *  Solid angle density of scatter() picking direction. Zero for specular
+     *  materials.
+     */
+    pub fn pdf(&self, r_in: &Ray, intersection: &Intersection, direction: Vec3) -> f32 {
+        if self.is_specular(intersection) {
+            return 0.0;
+        }
         match self.material_type {
-            MaterialType::Diffuse => self.lambertian_scatter(r_in, intersection),
-            MaterialType::Metal => self.metal_scatter(r_in, intersection),
-            MaterialType::Dielectric => self.dielectric_scatter(r_in, intersection),
+            // normal + random unit vector is cosine distributed.
+            MaterialType::Diffuse => f32::max(intersection.normal.dot(direction.normalized()), 0.0) / PI,
+            MaterialType::Metal => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                if wi.z <= 0.0 {
+                    return 0.0;
+                }
+                // Density of h, changed to density of the mirrored direction.
+                let h = (wo + wi).normalized();
+                self.ggx(intersection).pdf_visible(wo, h) / (4.0 * wo.dot(h))
+            }
+            MaterialType::Dielectric => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                self.rough_dielectric(intersection, &self.ggx(intersection), wo, wi).1
+            }
+            MaterialType::Principled => {
+                let frame = shading_frame(intersection);
+                let wo = frame.to_local(-r_in.direction.normalized());
+                let wi = frame.to_local(direction.normalized());
+                self.principled_eval(intersection, wo, wi).1
+            }
+            _ => 0.0,
         }
     }
+
+    pub fn scatter(&self, r_in: &Ray, intersection: &Intersection, sampler: &mut Sampler) -> (bool, Vec3, Ray) {
+        let (scattered, attenuation, ray) = match self.material_type {
+            MaterialType::Diffuse => self.lambertian_scatter(r_in, intersection, sampler),
+            MaterialType::Metal => self.metal_scatter(r_in, intersection, sampler),
+            MaterialType::Dielectric => self.dielectric_scatter(r_in, intersection, sampler),
+            MaterialType::Emissive => (false, Vec3::zero(), Ray::new(r_in.origin, r_in.direction)),
+            MaterialType::Principled => self.principled_scatter(r_in, intersection, sampler),
+            MaterialType::Interface => (true, Vec3::new(1.0, 1.0, 1.0), Ray::new(intersection.point, r_in.direction)),
+        };
+        (scattered, attenuation, ray.with_time(r_in.time))
+    }
 }
 
 impl Default for Material {
     fn default() -> Self {
-        Material { 
-            albedo: Vec3::default(), 
-            roughness: 0.0,
-            ir: 0.0,
-            material_type: MaterialType::Diffuse 
-        }
+        Material::new(Vec3::default(), 0.0, 0.0, MaterialType::Diffuse)
+    }
+}
+
+/**
+ *  dpdu and dpdv of intersection, or any frame around the normal if the
+ *  surface has no texture parametrization there.
+ */
+fn tangent_frame(intersection: &Intersection) -> (Vec3, Vec3) {
+    let (dpdu, dpdv) = (intersection.dpdu, intersection.dpdv);
+    if dpdu.cross(dpdv).length_squared() > 0.0 {
+        return (dpdu, dpdv);
+    }
+    let onb = Onb::from_w(intersection.normal);
+    (onb.u, onb.v)
+}
+
+/**
+ *  Frame with the shading normal as z and x following the u tangent.
+ */
+fn shading_frame(intersection: &Intersection) -> Onb {
+    let n = intersection.normal;
+    let (dpdu, _) = tangent_frame(intersection);
+    let tangent = dpdu - n * n.dot(dpdu);
+    if tangent.length_squared() < 1e-12 {
+        return Onb::from_w(n);
     }
+    let u = tangent.normalized();
+    Onb { u, v: n.cross(u), w: n }
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+fn schlick_fresnel(f0: f32, cos_theta: f32) -> f32 {
+    f0 + (1.0 - f0) * f32::powi(1.0 - cos_theta, 5)
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
 }
 
-fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
-    // Schlick's approximation for reflectance.
-    let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    r0 = r0 * r0;
-    let r1 = 1.0 - cosine;
-    r0 + (1.0 - r0) * r1.powi(5)
+/**
+ *  Hue and saturation of color, at a luminance of one.
+ */
+fn tint_color(color: Vec3) -> Vec3 {
+    let l = luminance(color);
+    if l > 0.0 { color / l } else { Vec3::new(1.0, 1.0, 1.0) }
 }
@@ -0,0 +1,183 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{vec3::Vec3, onb::Onb, sampler::Sampler, grid::DensityGrid};
+
+/**
+ *  Distribution of the directions light scatters into inside a medium.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    // Every direction is equally likely.
+    Isotropic,
+    // Forward scattering for g > 0, backward for g < 0, with g the mean
+    // cosine of the scattering angle.
+    HenyeyGreenstein(f32),
+}
+
+impl Phase {
+    /**
+     *  Isotropic for g = 0, Henyey-Greenstein otherwise.
+     */
+    pub fn from_g(g: f32) -> Phase {
+        if g == 0.0 {
+            Phase::Isotropic
+        } else {
+            Phase::HenyeyGreenstein(g)
+        }
+    }
+
+    /**
+     *  Density of light traveling along direction scattering into
+     *  scattered, per solid angle.
+     */
+    pub fn eval(&self, direction: Vec3, scattered: Vec3) -> f32 {
+        match *self {
+            Phase::Isotropic => 1.0 / (4.0 * PI),
+            Phase::HenyeyGreenstein(g) => {
+                let cos_theta = direction.normalized().dot(scattered.normalized());
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denom * f32::sqrt(denom))
+            }
+        }
+    }
+
+    /**
+     *  Random direction light traveling along direction scatters into,
+     *  picked with density eval().
+     */
+    pub fn sample(&self, direction: Vec3, sampler: &mut Sampler) -> Vec3 {
+        let u = sampler.next_f32();
+        let cos_theta = match *self {
+            Phase::Isotropic => 1.0 - 2.0 * u,
+            Phase::HenyeyGreenstein(g) => {
+                let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+                f32::clamp((1.0 + g * g - s * s) / (2.0 * g), -1.0, 1.0)
+            }
+        };
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * PI * sampler.next_f32();
+        let local = Vec3::new(sin_theta * f32::cos(phi), sin_theta * f32::sin(phi), cos_theta);
+        Onb::from_w(direction).local(local)
+    }
+}
+
+/**
+ *  A participating medium like fog, smoke or the inside of skin. Light is
+ *  scattered or absorbed at the same rate in every color, the albedo decides
+ *  how much of it is scattered rather than absorbed.
+ *
+ *  The density is the same everywhere unless the medium has a grid, whose
+ *  values it is then scaled by. Such media are tracked by sampling
+ *  tentative collisions against the majorant of each brick of the grid
+ *  (delta and ratio tracking, see Novák et al., "Monte Carlo Methods for
+ *  Volumetric Light Transport Simulation", 2018).
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Medium {
+    // Chance per unit distance of light hitting a particle.
+    pub density: f32,
+    pub grid: Option<Arc<DensityGrid>>,
+    // Fraction of the light hitting a particle that is scattered.
+    pub albedo: Vec3,
+    pub phase: Phase,
+    // How far the medium reaches past the last surface for rays leaving the
+    // scene. Only used for fog, media inside objects end at their surface.
+    pub distance: f32,
+}
+
+impl Medium {
+    pub fn new(density: f32, albedo: Vec3, g: f32) -> Medium {
+        Medium { density, grid: None, albedo, phase: Phase::from_g(g), distance: 0.0 }
+    }
+
+    /**
+     *  A medium with density times the values of grid.
+     */
+    pub fn with_grid(mut self, grid: Arc<DensityGrid>) -> Medium {
+        self.grid = Some(grid);
+        self
+    }
+
+    /**
+     *  Chance per unit distance of light hitting a particle at point.
+     */
+    pub fn density_at(&self, point: Vec3) -> f32 {
+        match &self.grid {
+            Some(grid) => self.density * grid.density(point),
+            None => self.density,
+        }
+    }
+
+    /**
+     *  Fraction of light passing distance from origin along the unit vector
+     *  direction unscattered. Exact for homogeneous media, an unbiased
+     *  estimate by ratio tracking for grids.
+     */
+    pub fn transmittance(&self, origin: Vec3, direction: Vec3, distance: f32, sampler: &mut Sampler) -> f32 {
+        let grid = match &self.grid {
+            Some(grid) => grid,
+            None => return f32::exp(-self.density * distance),
+        };
+
+        let mut transmittance = 1.0;
+        grid.march(origin, direction, 0.0, distance, |start, end, majorant| {
+            let majorant = self.density * majorant;
+            if majorant <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= f32::ln(1.0 - sampler.next_f32()) / majorant;
+                if t >= end {
+                    return true;
+                }
+                transmittance *= 1.0 - self.density_at(origin + t * direction) / majorant;
+                if transmittance <= 0.0 {
+                    return false;
+                }
+            }
+        });
+        transmittance
+    }
+
+    /**
+     *  Distance light travels from origin along the unit vector direction
+     *  before it hits a particle, picked with density proportional to the
+     *  transmittance (free-flight sampling, delta tracking for grids). None
+     *  if it gets further than max_distance, which happens with a
+     *  probability of exactly the transmittance, so neither case needs a
+     *  weight.
+     */
+    pub fn sample_distance(&self, origin: Vec3, direction: Vec3, max_distance: f32, sampler: &mut Sampler) -> Option<f32> {
+        let grid = match &self.grid {
+            Some(grid) => grid,
+            None if self.density <= 0.0 => return None,
+            None => {
+                let distance = -f32::ln(1.0 - sampler.next_f32()) / self.density;
+                return if distance < max_distance { Some(distance) } else { None };
+            }
+        };
+
+        // Tentative collisions against the majorant, real ones with the
+        // chance of the actual density.
+        let mut collision = None;
+        grid.march(origin, direction, 0.0, max_distance, |start, end, majorant| {
+            let majorant = self.density * majorant;
+            if majorant <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= f32::ln(1.0 - sampler.next_f32()) / majorant;
+                if t >= end {
+                    return true;
+                }
+                if sampler.next_f32() * majorant < self.density_at(origin + t * direction) {
+                    collision = Some(t);
+                    return false;
+                }
+            }
+        });
+        collision
+    }
+}
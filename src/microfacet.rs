@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+use crate::vec3::Vec3;
+
+// Below this the distribution can't be evaluated in single precision.
+const MIN_ALPHA: f32 = 1e-4;
+
+/**
+ *  The GGX (Trowbridge-Reitz) distribution of microfacet normals, with
+ *  separate roughness along the two tangents for anisotropic surfaces.
+ *
+ *  Everything is in the local shading frame: the macro surface normal is
+ *  +z and the u tangent is +x.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Ggx {
+        Ggx { alpha_x, alpha_y }
+    }
+
+    /**
+     *  Distribution of roughness, the perceptually linear parameter used by
+     *  materials, stretched along x by anisotropy > 0 and along y by
+     *  anisotropy < 0.
+     */
+    pub fn from_roughness(roughness: f32, anisotropy: f32) -> Ggx {
+        let alpha = f32::max(roughness * roughness, MIN_ALPHA);
+        let aspect = f32::sqrt(1.0 - 0.9 * f32::min(anisotropy.abs(), 1.0));
+        if anisotropy >= 0.0 {
+            Ggx::new(alpha / aspect, alpha * aspect)
+        } else {
+            Ggx::new(alpha * aspect, alpha / aspect)
+        }
+    }
+
+    /**
+     *  Density of microfacet normals h, per unit projected area.
+     */
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x / self.alpha_x;
+        let y = h.y / self.alpha_y;
+        let e = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let x = self.alpha_x * w.x;
+        let y = self.alpha_y * w.y;
+        let tan2 = (x * x + y * y) / (w.z * w.z);
+        0.5 * (f32::sqrt(1.0 + tan2) - 1.0)
+    }
+
+    /**
+     *  Fraction of the microfacets facing h that are visible from w.
+     */
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /**
+     *  Fraction visible from both wo and wi, height correlated.
+     */
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /**
+     *  Random microfacet normal visible from wo (z > 0), following Heitz,
+     *  "Sampling the GGX Distribution of Visible Normals" (2018).
+     */
+    pub fn sample_visible(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        // Stretch the view so the distribution becomes a hemisphere.
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalized();
+
+        let length_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_sq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / f32::sqrt(length_sq)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // A point on the disk, squeezed towards the visible half.
+        let r = f32::sqrt(u1);
+        let phi = 2.0 * PI * u2;
+        let p1 = r * f32::cos(phi);
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f32::sqrt(1.0 - p1 * p1) + s * r * f32::sin(phi);
+
+        let nh = p1 * t1 + p2 * t2 + f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+
+        // Back to the ellipsoid.
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, f32::max(nh.z, 1e-6)).normalized()
+    }
+
+    /**
+     *  Density of sample_visible() returning h, over solid angle of h.
+     */
+    pub fn pdf_visible(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * f32::max(wo.dot(h), 0.0) * self.d(h) / wo.z
+    }
+}
+
+/**
+ *  Mirror direction of w around the normal h.
+ */
+pub fn reflect(w: Vec3, h: Vec3) -> Vec3 {
+    2.0 * w.dot(h) * h - w
+}
+
+/**
+ *  Direction w (on the side h points to) refracts into when crossing into a
+ *  medium with eta times the index of refraction. None on total internal
+ *  reflection.
+ */
+pub fn refract(w: Vec3, h: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = w.dot(h);
+    let sin2_t = f32::max(1.0 - cos_i * cos_i, 0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    Some(-w / eta + (cos_i / eta - cos_t) * h)
+}
+
+/**
+ *  Fraction of unpolarized light reflected at cos_i from the normal when
+ *  crossing into a medium with eta times the index of refraction.
+ */
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = f32::min(cos_i, 1.0);
+    let sin2_t = f32::max(1.0 - cos_i * cos_i, 0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/**
+ *  Complex index of refraction of a metal, per RGB channel.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+}
+
+impl Conductor {
+    // Measured data sampled at about 650, 550 and 450 nm.
+    pub const GOLD: Conductor = Conductor {
+        eta: Vec3 { x: 0.143119, y: 0.374957, z: 1.44248 },
+        k: Vec3 { x: 3.98316, y: 2.38572, z: 1.60322 },
+    };
+    pub const COPPER: Conductor = Conductor {
+        eta: Vec3 { x: 0.200438, y: 0.924033, z: 1.10221 },
+        k: Vec3 { x: 3.91295, y: 2.45285, z: 2.14219 },
+    };
+    pub const ALUMINIUM: Conductor = Conductor {
+        eta: Vec3 { x: 1.65746, y: 0.880369, z: 0.521229 },
+        k: Vec3 { x: 9.22387, y: 6.26952, z: 4.837 },
+    };
+    pub const SILVER: Conductor = Conductor {
+        eta: Vec3 { x: 0.155265, y: 0.116723, z: 0.138342 },
+        k: Vec3 { x: 4.82835, y: 3.12225, z: 2.14696 },
+    };
+
+    pub fn from_name(name: &str) -> Option<Conductor> {
+        match name {
+            "gold" => Some(Conductor::GOLD),
+            "copper" => Some(Conductor::COPPER),
+            "aluminium" | "aluminum" => Some(Conductor::ALUMINIUM),
+            "silver" => Some(Conductor::SILVER),
+            _ => None,
+        }
+    }
+
+    /**
+     *  Fraction of light reflected at cos_theta from the normal, seen from
+     *  air.
+     */
+    pub fn fresnel(&self, cos_theta: f32) -> Vec3 {
+        Vec3::new(
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z),
+        )
+    }
+}
+
+/**
+ *  Exact Fresnel reflectance of unpolarized light on a conductor.
+ */
+fn fresnel_conductor(cos_theta: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = f32::min(cos_theta * cos_theta, 1.0);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f32::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let a = f32::sqrt(f32::max(0.5 * (a2_plus_b2 + t0), 0.0));
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
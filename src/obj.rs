@@ -0,0 +1,395 @@
+use std::{collections::HashMap, fmt, fs, io, path::{Path, PathBuf}};
+
+use std::sync::Arc;
+
+use crate::{
+    vec3::Vec3,
+    triangle::Triangle,
+    material::{Material, MaterialType, SurfaceDetail},
+    image::{Image, ImageError},
+    texture::{ImageTexture, WrapMode, solid},
+};
+
+/**
+ *  Everything that can go wrong while loading an .obj or .mtl file.
+ */
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+    Texture { path: PathBuf, source: ImageError },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+/**
+ *  A named range of triangles from a `g` or `o` statement.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjGroup {
+    pub name: String,
+    pub first: usize,
+    pub count: usize,
+}
+
+/**
+ *  Triangulated contents of an .obj file.
+ */
+pub struct ObjMesh {
+    pub triangles: Vec<Triangle>,
+    pub groups: Vec<ObjGroup>,
+}
+
+/**
+ *  Material used for faces without a `usemtl` statement.
+ */
+pub fn default_material() -> Material {
+    Material::new(Vec3::new(0.8, 0.8, 0.8), 0.0, 0.0, MaterialType::Diffuse)
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })
+}
+
+/**
+ *  Loads an .obj file. Material libraries are looked up relative to the
+ *  directory of the .obj file.
+ */
+pub fn load_obj(path: &Path) -> Result<ObjMesh, ObjError> {
+    let source = read_file(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_obj(&source, path, dir)
+}
+
+/**
+ *  Loads an .mtl file into a map from material name to material.
+ */
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Material>, ObjError> {
+    let source = read_file(path)?;
+    parse_mtl(&source, path)
+}
+
+/**
+ *  Helper for turning the tokens of a single line into values. Keeps track
+ *  of where we are so errors point at the offending line.
+ */
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { path: self.path.to_path_buf(), line: self.line, message }
+    }
+
+    fn f32(&self, token: Option<&str>, what: &str) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token
+            .parse::<f32>()
+            .map_err(|_| self.error(format!("invalid number '{}' for {}", token, what)))
+    }
+
+    fn vec3(&self, tokens: &mut std::str::SplitWhitespace, what: &str) -> Result<Vec3, ObjError> {
+        let x = self.f32(tokens.next(), what)?;
+        let y = self.f32(tokens.next(), what)?;
+        let z = self.f32(tokens.next(), what)?;
+        Ok(Vec3::new(x, y, z))
+    }
+
+    /**
+     *  Turns a 1-based (or negative, relative to the end) .obj index into a
+     *  0-based index into a list of len elements.
+     */
+    fn index(&self, token: &str, len: usize, what: &str) -> Result<usize, ObjError> {
+        let i = token
+            .parse::<i64>()
+            .map_err(|_| self.error(format!("invalid {} index '{}'", what, token)))?;
+
+        let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!("{} index {} out of range", what, i)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+/**
+ *  One corner of a face: indices into the position, texcoord and normal
+ *  lists.
+ */
+#[derive(Clone, Copy)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+/**
+ *  Parses the contents of an .obj file. path is only used in error messages,
+ *  mtllib statements are resolved relative to dir.
+ */
+pub fn parse_obj(source: &str, path: &Path, dir: &Path) -> Result<ObjMesh, ObjError> {
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f32, f32)> = vec![];
+
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    let mut material = default_material();
+
+    let mut triangles: Vec<Triangle> = vec![];
+    let mut groups: Vec<ObjGroup> = vec![];
+
+    for (i, line) in source.lines().enumerate() {
+        let p = LineParser { path, line: i + 1 };
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(p.vec3(&mut tokens, "vertex position")?),
+            "vn" => normals.push(p.vec3(&mut tokens, "vertex normal")?.normalized()),
+            "vt" => {
+                let u = p.f32(tokens.next(), "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(v) => p.f32(Some(v), "texture coordinate")?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                let mut face: Vec<FaceVertex> = vec![];
+                for corner in tokens {
+                    let mut parts = corner.split('/');
+                    let position = p.index(parts.next().unwrap_or(""), positions.len(), "vertex")?;
+                    let uv = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(p.index(t, uvs.len(), "texture coordinate")?),
+                    };
+                    let normal = match parts.next() {
+                        Some("") | None => None,
+                        Some(n) => Some(p.index(n, normals.len(), "normal")?),
+                    };
+                    face.push(FaceVertex { position, uv, normal });
+                }
+
+                if face.len() < 3 {
+                    return Err(p.error(format!("face needs at least 3 vertices, got {}", face.len())));
+                }
+
+                // Triangulate polygons as a fan around the first vertex.
+                for k in 1..face.len() - 1 {
+                    let corners = [face[0], face[k], face[k + 1]];
+                    let mut tri = Triangle::new(
+                        positions[corners[0].position],
+                        positions[corners[1].position],
+                        positions[corners[2].position],
+                        material.clone(),
+                    );
+                    if let [Some(n0), Some(n1), Some(n2)] = corners.map(|c| c.normal) {
+                        tri = tri.with_normals([normals[n0], normals[n1], normals[n2]]);
+                    }
+                    if let [Some(t0), Some(t1), Some(t2)] = corners.map(|c| c.uv) {
+                        tri = tri.with_uvs([uvs[t0], uvs[t1], uvs[t2]]);
+                    }
+                    triangles.push(tri);
+                }
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                groups.push(ObjGroup { name, first: triangles.len(), count: 0 });
+            }
+            "mtllib" => {
+                for file in tokens {
+                    materials.extend(load_mtl(&dir.join(file))?);
+                }
+            }
+            "usemtl" => {
+                let name = tokens.next().ok_or_else(|| p.error("missing material name".to_string()))?;
+                material = materials
+                    .get(name)
+                    .ok_or_else(|| p.error(format!("unknown material '{}'", name)))?
+                    .clone();
+            }
+            // Smoothing groups, lines, points and anything else are ignored.
+            _ => {}
+        }
+    }
+
+    // Each group runs until the next one starts.
+    for i in 0..groups.len() {
+        let end = groups.get(i + 1).map_or(triangles.len(), |g| g.first);
+        groups[i].count = end - groups[i].first;
+    }
+
+    Ok(ObjMesh { triangles, groups })
+}
+
+/**
+ *  Loads the image of a texture map statement, relative to the .mtl file.
+ *  Returns it with the -bm bump multiplier, the only option supported.
+ */
+fn load_map(p: &LineParser, mut tokens: std::str::SplitWhitespace, mtl_path: &Path, keyword: &str) -> Result<(Image, f32), ObjError> {
+    let mut bump_multiplier = 1.0;
+    let mut file: Vec<&str> = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            "-bm" if file.is_empty() => bump_multiplier = p.f32(tokens.next(), "-bm")?,
+            option if file.is_empty() && option.starts_with('-') => {
+                return Err(p.error(format!("unsupported option '{}' for {}", option, keyword)));
+            }
+            // The file name may contain spaces.
+            _ => file.push(token),
+        }
+    }
+    if file.is_empty() {
+        return Err(p.error(format!("expected a file name for {}", keyword)));
+    }
+
+    let texture_path = mtl_path.parent().unwrap_or_else(|| Path::new("")).join(file.join(" "));
+    let image = Image::load(&texture_path)
+        .map_err(|source| ObjError::Texture { path: texture_path, source })?;
+    Ok((image, bump_multiplier))
+}
+
+/**
+ *  Parses the contents of an .mtl file. The Wavefront parameters are mapped
+ *  onto our materials as follows:
+ *
+ *  - Kd becomes the albedo.
+ *  - Ns, the Phong exponent, is converted to roughness, except for
+ *    dielectrics, which stay smooth since Ns describes highlights.
+ *  - Pr, from the PBR extension, sets the roughness of any material.
+ *  - Ni becomes ir.
+ *  - A dissolve d < 1 (or Tr > 0) makes the material Dielectric.
+ *  - illum 3 or 5 (ray traced reflection) makes it Metal.
+ *  - Ke becomes the emission.
+ *  - map_Kd, an image relative to the .mtl file, becomes the albedo.
+ *  - norm is a tangent space normal map, map_Bump or bump a height map
+ *    scaled by its -bm option.
+ */
+pub fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    // The material being parsed and whether Pr set its roughness.
+    let mut current: Option<(String, Material, bool)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let p = LineParser { path, line: i + 1 };
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<&str>>().join(" ");
+            if name.is_empty() {
+                return Err(p.error("missing material name".to_string()));
+            }
+            if let Some((name, material, explicit_roughness)) = current.take() {
+                materials.insert(name, finish_mtl(material, explicit_roughness));
+            }
+            current = Some((name, default_material(), false));
+            continue;
+        }
+
+        let (material, explicit_roughness) = match current.as_mut() {
+            Some((_, material, explicit_roughness)) => (material, explicit_roughness),
+            None => return Err(p.error(format!("'{}' before any newmtl", keyword))),
+        };
+
+        match keyword {
+            "Kd" => material.albedo = solid(p.vec3(&mut tokens, "Kd")?),
+            "Ns" => {
+                // Blinn-Phong exponent to an equivalent microfacet roughness.
+                let ns = p.f32(tokens.next(), "Ns")?;
+                let roughness = f32::min(f32::sqrt(2.0 / (ns.max(0.0) + 2.0)), 1.0);
+                if !*explicit_roughness {
+                    material.roughness = solid(Vec3::new(roughness, roughness, roughness));
+                }
+            }
+            "Pr" => {
+                let roughness = p.f32(tokens.next(), "Pr")?.clamp(0.0, 1.0);
+                material.roughness = solid(Vec3::new(roughness, roughness, roughness));
+                *explicit_roughness = true;
+            }
+            "Ke" => material.emission = solid(p.vec3(&mut tokens, "Ke")?),
+            "map_Kd" => {
+                let (image, _) = load_map(&p, tokens, path, keyword)?;
+                material.albedo = Arc::new(ImageTexture::new(&image, WrapMode::Repeat, true));
+            }
+            "norm" => {
+                let (image, _) = load_map(&p, tokens, path, keyword)?;
+                let texture = Arc::new(ImageTexture::new(&image, WrapMode::Repeat, false));
+                material.detail = Some(SurfaceDetail::NormalMap(texture));
+            }
+            "map_Bump" | "bump" => {
+                let (image, scale) = load_map(&p, tokens, path, keyword)?;
+                let height = Arc::new(ImageTexture::new(&image, WrapMode::Repeat, false));
+                material.detail = Some(SurfaceDetail::BumpMap { height, scale });
+            }
+            "Ni" => material.ir = p.f32(tokens.next(), "Ni")?,
+            "d" | "Tr" => {
+                let mut dissolve = p.f32(tokens.next(), keyword)?;
+                if keyword == "Tr" {
+                    dissolve = 1.0 - dissolve;
+                }
+                if dissolve < 1.0 {
+                    material.material_type = MaterialType::Dielectric;
+                    if material.ir == 0.0 {
+                        material.ir = 1.5;
+                    }
+                }
+            }
+            "illum" => {
+                let model = p.f32(tokens.next(), "illum")? as i32;
+                if (model == 3 || model == 5) && !matches!(material.material_type, MaterialType::Dielectric) {
+                    material.material_type = MaterialType::Metal;
+                }
+            }
+            // Other maps, Ka, Ks and friends are not supported yet.
+            _ => {}
+        }
+    }
+
+    if let Some((name, material, explicit_roughness)) = current.take() {
+        materials.insert(name, finish_mtl(material, explicit_roughness));
+    }
+
+    Ok(materials)
+}
+
+/**
+ *  Resets the roughness Ns gave a dielectric, so ordinary .mtl glass isn't
+ *  turned into frosted glass.
+ */
+fn finish_mtl(mut material: Material, explicit_roughness: bool) -> Material {
+    if matches!(material.material_type, MaterialType::Dielectric) && !explicit_roughness {
+        material.roughness = solid(Vec3::zero());
+    }
+    material
+}
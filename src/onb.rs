@@ -0,0 +1,57 @@
+use std::f32::consts::PI;
+
+use crate::{vec3::Vec3, sampler::Sampler};
+
+/**
+ *  Orthonormal basis around a direction w, used to turn directions sampled
+ *  around the z axis into world space.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(w: Vec3) -> Onb {
+        let w = w.normalized();
+        // Any vector not parallel to w will do.
+        let a = if f32::abs(w.x) > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).normalized();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    /**
+     *  World space vector of the local coordinates a.
+     */
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /**
+     *  Local coordinates of the world space vector a, the inverse of
+     *  local().
+     */
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
+/**
+ *  Uniformly distributed direction in the cone of directions within
+ *  acos(cos_theta_max) of the z axis, in local coordinates.
+ */
+pub fn random_in_cone(sampler: &mut Sampler, cos_theta_max: f32) -> Vec3 {
+    let r1 = sampler.next_f32();
+    let r2 = sampler.next_f32();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+    let phi = 2.0 * PI * r1;
+    let sin_theta = f32::sqrt(f32::max(1.0 - z * z, 0.0));
+    Vec3::new(f32::cos(phi) * sin_theta, f32::sin(phi) * sin_theta, z)
+}
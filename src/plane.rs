@@ -0,0 +1,268 @@
+use std::f32::consts::PI;
+
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb, onb::Onb, sampler::Sampler};
+
+// Rays closer to parallel with the plane than this are misses.
+const EPSILON: f32 = 1e-8;
+
+// Planes are infinite, but acceleration structures need a finite box. They
+// aren't hit further than this from the origin.
+pub const PLANE_EXTENT: f32 = 1e5;
+
+// Thickness added to flat boxes so they still have volume.
+const PAD: f32 = 1e-4;
+
+/**
+ *  Unit vectors a and b along a plane with normal n, with a x b = n.
+ */
+fn plane_axes(n: Vec3) -> (Vec3, Vec3) {
+    let a = Onb::from_w(n).u;
+    (a, n.cross(a))
+}
+
+/**
+ *  Distance along r to the plane through point with unit normal n, None if
+ *  r runs along the plane or the hit is outside [t_min, t_max].
+ */
+fn plane_distance(r: &Ray, point: Vec3, n: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+    let denom = n.dot(r.direction);
+    if denom.abs() < EPSILON * r.direction.length() {
+        return None;
+    }
+    let t = (point - r.origin).dot(n) / denom;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some(t)
+}
+
+/**
+ *  Box around a flat shape with unit normal n that reaches extent from
+ *  center along the plane.
+ */
+fn flat_bounds(center: Vec3, n: Vec3, extent: f32) -> Aabb {
+    let reach = |c: f32| extent * f32::sqrt(f32::max(0.0, 1.0 - c * c)) + PAD;
+    let r = Vec3::new(reach(n.x), reach(n.y), reach(n.z));
+    Aabb::new(center - r, center + r)
+}
+
+/**
+ *  Density of sampling direction from origin by picking a point uniformly
+ *  on a flat shape of area, converted to solid angle.
+ */
+fn area_pdf(hit: &Intersection, direction: Vec3, area: f32) -> f32 {
+    let distance_squared = hit.t * hit.t * direction.length_squared();
+    let cosine = f32::abs(direction.dot(hit.normal)) / direction.length();
+    if cosine < EPSILON {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+/**
+ *  Intersection with the disc of radius around center facing the unit
+ *  normal n. u goes around the disc, v from the rim (v = 0) to the center.
+ *  Also used for the caps of cylinders and cones.
+ */
+pub(crate) fn hit_disc<'a>(
+    r: &Ray,
+    center: Vec3,
+    n: Vec3,
+    radius: f32,
+    material: &'a Material,
+    t_min: f32,
+    t_max: f32) -> Option<Intersection<'a>> {
+
+    let t = plane_distance(r, center, n, t_min, t_max)?;
+    let point = r.at(t);
+    let offset = point - center;
+    if offset.length_squared() > radius * radius {
+        return None;
+    }
+
+    let (a, b) = plane_axes(n);
+    let (x, y) = (offset.dot(a), offset.dot(b));
+    let rho = f32::sqrt(x * x + y * y);
+    let phi = f32::atan2(y, x).rem_euclid(2.0 * PI);
+
+    let mut intersection = Intersection::new(point, n, t, material);
+    intersection.set_face_normal(r, n);
+    intersection.u = phi / (2.0 * PI);
+    intersection.v = 1.0 - rho / radius;
+    if rho > 0.0 {
+        intersection.dpdu = 2.0 * PI * (x * b - y * a);
+        intersection.dpdv = -radius / rho * offset;
+    }
+    Some(intersection)
+}
+
+/**
+ *  Infinite plane through point. u and v are distances along the plane, so
+ *  textures repeat across it.
+ */
+#[derive(Clone)]
+pub struct Plane {
+    pub point: Vec3,
+    // Unit length.
+    pub normal: Vec3,
+    pub material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Plane {
+        Plane { point, normal: normal.normalized(), material }
+    }
+}
+
+impl Intersectable for Plane {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let t = plane_distance(r, self.point, self.normal, t_min, t_max)?;
+        let point = r.at(t);
+        if point.x.abs() > PLANE_EXTENT || point.y.abs() > PLANE_EXTENT || point.z.abs() > PLANE_EXTENT {
+            return None;
+        }
+
+        let (a, b) = plane_axes(self.normal);
+        let mut intersection = Intersection::new(point, self.normal, t, &self.material);
+        intersection.set_face_normal(r, self.normal);
+        intersection.u = (point - self.point).dot(a);
+        intersection.v = (point - self.point).dot(b);
+        intersection.dpdu = a;
+        intersection.dpdv = b;
+        Some(intersection)
+    }
+
+    /**
+     *  Thin along the normal if it points along an axis, the whole extent
+     *  otherwise.
+     */
+    fn bounding_box(&self) -> Aabb {
+        let bound = |axis: usize, sign: f32| {
+            if self.normal[axis].abs() > 1.0 - 1e-6 {
+                self.point[axis] + sign * PAD
+            } else {
+                sign * PLANE_EXTENT
+            }
+        };
+        Aabb::new(
+            Vec3::new(bound(0, -1.0), bound(1, -1.0), bound(2, -1.0)),
+            Vec3::new(bound(0, 1.0), bound(1, 1.0), bound(2, 1.0)),
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Disc {
+    pub center: Vec3,
+    // Unit length.
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Disc {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Material) -> Disc {
+        Disc { center, normal: normal.normalized(), radius, material }
+    }
+}
+
+impl Intersectable for Disc {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        hit_disc(r, self.center, self.normal, self.radius, &self.material, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        flat_bounds(self.center, self.normal, self.radius)
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, _time: f32) -> f32 {
+        match self.hit(&Ray::new(origin, direction), 0.001, f32::MAX) {
+            Some(hit) => area_pdf(&hit, direction, PI * self.radius * self.radius),
+            None => 0.0,
+        }
+    }
+
+    /**
+     *  Direction towards a point picked uniformly on the disc.
+     */
+    fn random_direction(&self, origin: Vec3, _time: f32, sampler: &mut Sampler) -> Vec3 {
+        let (a, b) = plane_axes(self.normal);
+        let rho = self.radius * f32::sqrt(sampler.next_f32());
+        let phi = 2.0 * PI * sampler.next_f32();
+        self.center + rho * (f32::cos(phi) * a + f32::sin(phi) * b) - origin
+    }
+}
+
+/**
+ *  Parallelogram spanned by the edges u and v from corner. The texture
+ *  coordinates go from 0 to 1 along each edge, and the front faces the
+ *  direction of u x v.
+ */
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: Material) -> Quad {
+        Quad { corner, u, v, material }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.u.cross(self.v).length()
+    }
+}
+
+impl Intersectable for Quad {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let n = self.u.cross(self.v);
+        let normal = n.normalized();
+        let t = plane_distance(r, self.corner, normal, t_min, t_max)?;
+        let point = r.at(t);
+
+        // Coordinates of the point along the edges.
+        let offset = point - self.corner;
+        let w = n / n.length_squared();
+        let alpha = w.dot(offset.cross(self.v));
+        let beta = w.dot(self.u.cross(offset));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let mut intersection = Intersection::new(point, normal, t, &self.material);
+        intersection.set_face_normal(r, normal);
+        intersection.u = alpha;
+        intersection.v = beta;
+        intersection.dpdu = self.u;
+        intersection.dpdv = self.v;
+        Some(intersection)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let pad = Vec3::new(PAD, PAD, PAD);
+        let opposite = self.corner + self.u + self.v;
+        let min = self.corner.min(opposite).min(self.corner + self.u).min(self.corner + self.v);
+        let max = self.corner.max(opposite).max(self.corner + self.u).max(self.corner + self.v);
+        Aabb::new(min - pad, max + pad)
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, _time: f32) -> f32 {
+        match self.hit(&Ray::new(origin, direction), 0.001, f32::MAX) {
+            Some(hit) => area_pdf(&hit, direction, self.area()),
+            None => 0.0,
+        }
+    }
+
+    /**
+     *  Direction towards a point picked uniformly on the quad.
+     */
+    fn random_direction(&self, origin: Vec3, _time: f32, sampler: &mut Sampler) -> Vec3 {
+        self.corner + sampler.next_f32() * self.u + sampler.next_f32() * self.v - origin
+    }
+}
@@ -1,22 +1,41 @@
-use crate::{vec3::Vec3, material::Material};
+use std::sync::Arc;
+
+use crate::{vec3::Vec3, material::Material, aabb::Aabb, sampler::Sampler};
 
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // When the ray was sent, moving objects are hit where they are then.
+    pub time: f32,
 }
 
-#[derive(Default)]
-pub struct Intersection {
+pub struct Intersection<'a> {
     pub point: Vec3,
     pub normal: Vec3,
     pub t: f32,
     pub front_face: bool,
-    pub material: Material,
+    pub material: &'a Material,
+
+    // Texture coordinates of the hit point.
+    pub u: f32,
+    pub v: f32,
+    // Tangent frame: how the point moves with u and v. Not normalized, zero
+    // if the object has no texture parametrization.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+
+    // Barycentric weights of the three vertices when a triangle was hit.
+    pub barycentric: Vec3,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray { origin, direction, time: 0.0 }
+    }
+
+    pub fn with_time(mut self, time: f32) -> Ray {
+        self.time = time;
+        self
     }
 
     pub fn at(&self, t: f32) -> Vec3 {
@@ -25,12 +44,81 @@ impl Ray {
 }
 
 pub trait Intersectable {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection>;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>>;
+
+    /**
+     *  Box enclosing the whole object. Used by acceleration structures.
+     */
+    fn bounding_box(&self) -> Aabb;
+
+    /**
+     *  Solid angle density of random_direction() picking direction from
+     *  origin at time. Zero for objects that can't be sampled, or if the
+     *  direction misses.
+     */
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3, _time: f32) -> f32 {
+        0.0
+    }
+
+    /**
+     *  Random direction from origin towards the object as it is at time,
+     *  used to sample lights directly.
+     */
+    fn random_direction(&self, _origin: Vec3, _time: f32, _sampler: &mut Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
-impl Intersection {
-    pub fn new(point: Vec3, normal: Vec3, t: f32, material: Material) -> Intersection {
-        Intersection{ point, normal, t, front_face: false, material }
+impl<T: Intersectable + ?Sized> Intersectable for Box<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        (**self).pdf_value(origin, direction, time)
+    }
+
+    fn random_direction(&self, origin: Vec3, time: f32, sampler: &mut Sampler) -> Vec3 {
+        (**self).random_direction(origin, time, sampler)
+    }
+}
+
+impl<T: Intersectable + ?Sized> Intersectable for Arc<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        (**self).pdf_value(origin, direction, time)
+    }
+
+    fn random_direction(&self, origin: Vec3, time: f32, sampler: &mut Sampler) -> Vec3 {
+        (**self).random_direction(origin, time, sampler)
+    }
+}
+
+impl<'a> Intersection<'a> {
+    pub fn new(point: Vec3, normal: Vec3, t: f32, material: &'a Material) -> Intersection<'a> {
+        Intersection {
+            point,
+            normal,
+            t,
+            front_face: false,
+            material,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            barycentric: Vec3::zero(),
+        }
     }
 
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
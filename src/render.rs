@@ -0,0 +1,502 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, mpsc}, thread};
+
+use crate::{
+    vec3::Vec3,
+    ray::{Ray, Intersectable, Intersection},
+    camera::Camera,
+    framebuffer::Framebuffer,
+    sampler::Sampler,
+    light::LightList,
+    environment::Environment,
+    medium::{Medium, Phase},
+};
+
+// Width and height of the square tiles the image is split into.
+pub const TILE_SIZE: usize = 32;
+
+/**
+ *  Everything the renderer needs to know about what to render.
+ */
+pub struct RenderContext {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub camera: Camera,
+    pub world: Box<dyn Intersectable + Send + Sync>,
+    pub lights: LightList,
+    pub environment: Environment,
+    // Medium filling the space between objects.
+    pub fog: Option<Medium>,
+    pub seed: u64,
+}
+
+/**
+ *  A rectangle of the image, (x0, y0) being its top left corner.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/**
+ *  Splits the image into tiles of TILE_SIZE, row by row from the top. Tiles
+ *  along the right and bottom edges may be smaller.
+ */
+pub fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles: Vec<Tile> = vec![];
+    for y0 in (0..height).step_by(TILE_SIZE) {
+        for x0 in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                width: usize::min(TILE_SIZE, width - x0),
+                height: usize::min(TILE_SIZE, height - y0),
+            });
+        }
+    }
+    tiles
+}
+
+/**
+ *  Renders a single tile. Each pixel gets its own sampler seeded from its
+ *  position, so the result does not depend on which thread renders it.
+ */
+pub fn render_tile(ctx: &RenderContext, tile: Tile) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(tile.width, tile.height);
+    for ty in 0..tile.height {
+        // The camera has v = 0 at the bottom of the image.
+        let y = ctx.height - 1 - (tile.y0 + ty);
+        for tx in 0..tile.width {
+            let x = tile.x0 + tx;
+            let mut sampler = Sampler::for_pixel(ctx.seed, x, y, ctx.width);
+            for _ in 0..ctx.samples_per_pixel {
+                let u = (x as f32 + sampler.next_f32()) / (ctx.width-1) as f32;
+                let v = (y as f32 + sampler.next_f32()) / (ctx.height-1) as f32;
+                let r = ctx.camera.get_ray(u, v, &mut sampler);
+                let color = ray_color(r, ctx.world.as_ref(), &ctx.lights, &ctx.environment, ctx.fog.as_ref(), ctx.max_depth, &mut sampler);
+                framebuffer.add_sample(tx, ty, color);
+            }
+        }
+    }
+    framebuffer
+}
+
+/**
+ *  Renders the context on the calling thread.
+ */
+pub fn render(ctx: &RenderContext) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(ctx.width, ctx.height);
+    for tile in tiles(ctx.width, ctx.height) {
+        let result = render_tile(ctx, tile);
+        framebuffer.merge(&result, tile.x0, tile.y0);
+    }
+    framebuffer
+}
+
+/**
+ *  Renders the context on n_threads threads. Each thread keeps pulling the
+ *  next tile from a shared counter until all are done, so threads that got
+ *  cheap tiles simply render more of them. The result is identical for any
+ *  number of threads.
+ */
+pub fn render_multithreaded(ctx: &RenderContext, n_threads: usize) -> Framebuffer {
+    let tiles = tiles(ctx.width, ctx.height);
+    let next_tile = AtomicUsize::new(0);
+    let mut framebuffer = Framebuffer::new(ctx.width, ctx.height);
+
+    let worker = |sender: mpsc::Sender<(Tile, Framebuffer)>| loop {
+        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+        let tile = match tiles.get(index) {
+            Some(tile) => *tile,
+            None => break,
+        };
+        let result = render_tile(ctx, tile);
+        if sender.send((tile, result)).is_err() {
+            break;
+        }
+    };
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+
+        // The calling thread collects results, the others render.
+        for _ in 0..n_threads.max(1) {
+            let sender_n = sender.clone();
+            scope.spawn(move || worker(sender_n));
+        }
+        drop(sender);
+
+        let mut done = 0;
+        let mut last_percent = 0;
+        for (tile, result) in receiver {
+            framebuffer.merge(&result, tile.x0, tile.y0);
+
+            done += 1;
+            let percent = 100 * done / tiles.len();
+            if percent / 10 != last_percent / 10 {
+                eprintln!("Progress: {}%", percent);
+            }
+            last_percent = percent;
+        }
+    });
+
+    framebuffer
+}
+
+/**
+ *  Determine the color for a ray (pixel) depending on intersections with the
+ *  world. Light comes from emissive surfaces, punctual lights and the
+ *  environment.
+ *
+ *  At every non-specular hit emissive objects and the environment are
+ *  sampled directly, and the scattered ray may find them as well. Both
+ *  estimate the same light, so each is weighted against the other with the
+ *  power heuristic (multiple importance sampling). Point, spot and
+ *  directional lights can't be hit by scattered rays, they are sampled
+ *  directly at every non-specular hit and count with full weight.
+ *
+ *  Rays travel through the fog, if any, and through the media inside
+ *  objects. Where a particle is hit is picked by free-flight sampling, the
+ *  light is then scattered by the phase function of the medium and lights
+ *  are sampled from there as from a surface.
+ */
+pub fn ray_color(
+    r: Ray,
+    world: &dyn Intersectable,
+    lights: &LightList,
+    environment: &Environment,
+    fog: Option<&Medium>,
+    max_depth: i32,
+    sampler: &mut Sampler) -> Vec3 {
+
+    let mut radiance = Vec3::zero();
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut ray = r;
+
+    // Density the previous bounce picked ray with, None if the lights were
+    // not sampled there (camera rays and specular bounces).
+    let mut scatter_pdf: Option<f32> = None;
+    // Where that bounce was. Media boundaries are passed through without
+    // counting as a bounce.
+    let mut origin = ray.origin;
+
+    let mut depth = 0;
+    while depth < max_depth {
+        let hit = closest_intersection(&ray, world);
+
+        // Light may hit a particle before reaching the surface.
+        let length = ray.direction.length();
+        let distance = match &hit {
+            Some(hit) => hit.t * length,
+            None => fog.map_or(0.0, |fog| fog.distance),
+        };
+        if let Some(medium) = segment_medium(hit.as_ref(), fog) {
+            if let Some(d) = medium.sample_distance(ray.origin, ray.direction / length, distance, sampler) {
+                let point = ray.at(d / length);
+                throughput *= medium.albedo;
+
+                let vertex = Vertex::Medium { point, direction: ray.direction, time: ray.time, phase: medium.phase };
+                let scattered = medium.phase.sample(ray.direction, sampler);
+                if depth + 1 == max_depth {
+                    scatter_pdf = None;
+                } else {
+                    radiance += throughput * sample_lights(&vertex, world, fog, lights, sampler);
+                    radiance += throughput * sample_punctual_lights(&vertex, world, fog, lights, sampler);
+                    radiance += throughput * sample_environment(&vertex, world, fog, environment, sampler);
+                    scatter_pdf = Some(vertex.pdf(scattered));
+                }
+
+                origin = point;
+                ray = Ray::new(point, scattered).with_time(ray.time);
+                depth += 1;
+                continue;
+            }
+        }
+
+        let mut intersection = match hit {
+            Some(intersection) => intersection,
+            None => {
+                let weight = match scatter_pdf {
+                    Some(pdf) => power_heuristic(pdf, environment.pdf(ray.direction)),
+                    None => 1.0,
+                };
+                radiance += throughput * environment.radiance(ray.direction) * weight;
+                break;
+            }
+        };
+        let material = intersection.material;
+        if material.is_interface() {
+            ray = Ray::new(intersection.point, ray.direction).with_time(ray.time);
+            continue;
+        }
+        material.apply_detail(&ray, &mut intersection);
+
+        // Light is absorbed on its way through the inside of glass.
+        throughput *= material.transmittance(&intersection, intersection.t * length);
+
+        let emitted = material.emitted(&intersection);
+        if emitted != Vec3::zero() {
+            let weight = match scatter_pdf {
+                Some(pdf) => power_heuristic(pdf, lights.pdf_value(origin, ray.direction, ray.time)),
+                None => 1.0,
+            };
+            radiance += throughput * emitted * weight;
+        }
+
+        // Scatter ray based on material
+        let (scatter, attenuation, scattered) = material.scatter(&ray, &intersection, sampler);
+        if !scatter {
+            break;
+        }
+
+        // A light sample stands in for the next segment, so there has to be
+        // room for one.
+        if material.is_specular(&intersection) || depth + 1 == max_depth {
+            scatter_pdf = None;
+        } else {
+            let vertex = Vertex::Surface { r_in: &ray, intersection: &intersection };
+            radiance += throughput * sample_lights(&vertex, world, fog, lights, sampler);
+            radiance += throughput * sample_punctual_lights(&vertex, world, fog, lights, sampler);
+            radiance += throughput * sample_environment(&vertex, world, fog, environment, sampler);
+            scatter_pdf = Some(vertex.pdf(scattered.direction));
+        }
+
+        throughput *= attenuation;
+        origin = intersection.point;
+        ray = scattered;
+        depth += 1;
+    }
+
+    radiance
+}
+
+/**
+ *  A point light is scattered at on its way to the camera: a surface, or a
+ *  particle inside a medium.
+ */
+enum Vertex<'a> {
+    Surface { r_in: &'a Ray, intersection: &'a Intersection<'a> },
+    Medium { point: Vec3, direction: Vec3, time: f32, phase: Phase },
+}
+
+impl Vertex<'_> {
+    fn point(&self) -> Vec3 {
+        match self {
+            Vertex::Surface { intersection, .. } => intersection.point,
+            Vertex::Medium { point, .. } => *point,
+        }
+    }
+
+    fn time(&self) -> f32 {
+        match self {
+            Vertex::Surface { r_in, .. } => r_in.time,
+            Vertex::Medium { time, .. } => *time,
+        }
+    }
+
+    /**
+     *  Fraction of the light arriving from direction that is scattered on
+     *  towards the camera.
+     */
+    fn eval(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Vertex::Surface { r_in, intersection } => intersection.material.eval(r_in, intersection, direction),
+            Vertex::Medium { direction: incoming, phase, .. } => {
+                let p = phase.eval(*incoming, direction);
+                Vec3::new(p, p, p)
+            }
+        }
+    }
+
+    /**
+     *  Density of the vertex scattering light into direction.
+     */
+    fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Vertex::Surface { r_in, intersection } => intersection.material.pdf(r_in, intersection, direction),
+            Vertex::Medium { direction: incoming, phase, .. } => phase.eval(*incoming, direction),
+        }
+    }
+}
+
+/**
+ *  Light arriving at vertex from a direction picked by the light list,
+ *  weighted against the chance of the vertex scattering the same way.
+ */
+fn sample_lights(
+    vertex: &Vertex,
+    world: &dyn Intersectable,
+    fog: Option<&Medium>,
+    lights: &LightList,
+    sampler: &mut Sampler) -> Vec3 {
+
+    if !lights.has_shapes() {
+        return Vec3::zero();
+    }
+
+    let point = vertex.point();
+    let direction = lights.random_direction(point, vertex.time(), sampler);
+
+    let light_pdf = lights.pdf_value(point, direction, vertex.time());
+    if light_pdf <= 0.0 {
+        return Vec3::zero();
+    }
+
+    let f = vertex.eval(direction);
+    if f == Vec3::zero() {
+        return Vec3::zero();
+    }
+
+    // Whatever the shadow ray hits first is what we see in that direction.
+    let shadow_ray = Ray::new(point, direction).with_time(vertex.time());
+    let emitted = match trace_shadow(&shadow_ray, world, fog, f32::INFINITY, sampler) {
+        (Some(hit), transmittance) => hit.material.emitted(&hit) * transmittance,
+        (None, _) => return Vec3::zero(),
+    };
+
+    let weight = power_heuristic(light_pdf, vertex.pdf(direction));
+    f * emitted * (weight / light_pdf)
+}
+
+/**
+ *  Light arriving at vertex from all punctual lights that are not blocked.
+ *  They can't be hit by scattered rays, so no weighting is needed.
+ */
+fn sample_punctual_lights(
+    vertex: &Vertex,
+    world: &dyn Intersectable,
+    fog: Option<&Medium>,
+    lights: &LightList,
+    sampler: &mut Sampler) -> Vec3 {
+
+    let mut radiance = Vec3::zero();
+    for light in lights.punctual() {
+        let sample = match light.sample(vertex.point(), sampler) {
+            Some(sample) => sample,
+            None => continue,
+        };
+
+        let f = vertex.eval(sample.direction);
+        if f == Vec3::zero() {
+            continue;
+        }
+
+        let shadow_ray = Ray::new(vertex.point(), sample.direction).with_time(vertex.time());
+        match trace_shadow(&shadow_ray, world, fog, sample.distance, sampler) {
+            (Some(_), _) => continue,
+            (None, transmittance) => radiance += f * sample.light * transmittance,
+        }
+    }
+    radiance
+}
+
+/**
+ *  Light arriving at vertex from an importance sampled direction of the
+ *  environment, weighted against the vertex like sample_lights().
+ */
+fn sample_environment(
+    vertex: &Vertex,
+    world: &dyn Intersectable,
+    fog: Option<&Medium>,
+    environment: &Environment,
+    sampler: &mut Sampler) -> Vec3 {
+
+    let (direction, env_pdf) = match environment.sample(sampler) {
+        Some(sample) => sample,
+        None => return Vec3::zero(),
+    };
+
+    let f = vertex.eval(direction);
+    if f == Vec3::zero() {
+        return Vec3::zero();
+    }
+
+    let shadow_ray = Ray::new(vertex.point(), direction).with_time(vertex.time());
+    let transmittance = match trace_shadow(&shadow_ray, world, fog, f32::INFINITY, sampler) {
+        (Some(_), _) => return Vec3::zero(),
+        (None, transmittance) => transmittance,
+    };
+
+    let weight = power_heuristic(env_pdf, vertex.pdf(direction));
+    f * environment.radiance(direction) * (transmittance * weight / env_pdf)
+}
+
+/**
+ *  The medium a ray travels through before reaching hit: the inside of an
+ *  object when the ray hits the back of its surface, the fog when it hits
+ *  the front of one or nothing at all. There is no fog inside glass. Media
+ *  can't overlap.
+ */
+fn segment_medium<'a>(hit: Option<&Intersection<'a>>, fog: Option<&'a Medium>) -> Option<&'a Medium> {
+    match hit {
+        Some(hit) if !hit.front_face => match &hit.material.medium {
+            Some(medium) => Some(medium),
+            None if hit.material.is_refractive() => None,
+            None => fog,
+        },
+        _ => fog,
+    }
+}
+
+/**
+ *  Follows ray through media boundaries to the first surface that isn't
+ *  one, closer than max_distance along the ray. Returns it with the
+ *  fraction of light that makes it through the fog and media on the way.
+ *  Infinite rays pass through the fog up to its distance.
+ */
+fn trace_shadow<'a>(
+    ray: &Ray,
+    world: &'a dyn Intersectable,
+    fog: Option<&'a Medium>,
+    max_distance: f32,
+    sampler: &mut Sampler) -> (Option<Intersection<'a>>, f32) {
+
+    let direction = ray.direction.normalized();
+    let mut origin = ray.origin;
+    let mut remaining = max_distance;
+    let mut transmittance = 1.0;
+
+    loop {
+        let hit = world.hit(&Ray::new(origin, direction).with_time(ray.time), 0.001, f32::min(remaining, f32::MAX));
+        let distance = match &hit {
+            Some(hit) => hit.t,
+            None if remaining.is_finite() => remaining,
+            None => fog.map_or(0.0, |fog| fog.distance),
+        };
+        if let Some(medium) = segment_medium(hit.as_ref(), fog) {
+            transmittance *= medium.transmittance(origin, direction, distance, sampler);
+        }
+
+        match hit {
+            Some(hit) if hit.material.is_interface() => {
+                remaining -= hit.t;
+                origin = hit.point;
+            }
+            hit => return (hit, transmittance),
+        }
+    }
+}
+
+/**
+ *  Weight of a sample taken with density pdf_a, when the same direction
+ *  could also have been picked with density pdf_b.
+ */
+fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let a = pdf_a * pdf_a;
+    let b = pdf_b * pdf_b;
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+/**
+ *  Returns the closest intersection for ray, if any.
+ */
+pub fn closest_intersection<'a>(r: &Ray, world: &'a dyn Intersectable) -> Option<Intersection<'a>> {
+    world.hit(r, 0.001, f32::MAX)
+}
@@ -0,0 +1,68 @@
+use crate::utils::hash_seed;
+
+// Multiplier of the PCG32 state transition.
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+/**
+ *  Source of all random numbers used while rendering. It is passed down
+ *  explicitly through the camera, materials and integrator instead of being
+ *  global, so the numbers a pixel sees only depend on the seed it was given.
+ *
+ *  Uses PCG32, which is small, fast and gives the same sequence on every
+ *  platform and rand version.
+ */
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    state: u64,
+    increment: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Sampler {
+        // The increment selects the stream and has to be odd.
+        let mut sampler = Sampler { state: 0, increment: (hash_seed(seed, 0) << 1) | 1 };
+        sampler.next_u32();
+        sampler.state = sampler.state.wrapping_add(seed);
+        sampler.next_u32();
+        sampler
+    }
+
+    /**
+     *  Sampler for pixel (x, y) of an image of the given width. Every pixel
+     *  has its own sequence, so it does not matter which thread renders it
+     *  or in which order.
+     */
+    pub fn for_pixel(seed: u64, x: usize, y: usize, width: usize) -> Sampler {
+        Sampler::new(hash_seed(seed, (y * width + x) as u64))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    /**
+     *  Random number between [0, 1)
+     */
+    pub fn next_f32(&mut self) -> f32 {
+        // The upper 24 bits fit the mantissa exactly.
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    /**
+     *  Random number between [min, max)
+     */
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        let x = min + (max - min) * self.next_f32();
+        // Rounding can land exactly on max.
+        if x < max { x } else { min }
+    }
+}
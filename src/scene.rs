@@ -0,0 +1,1251 @@
+use std::{collections::HashMap, fmt, fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{
+    vec3::Vec3,
+    camera::Camera,
+    sphere::Sphere,
+    triangle::Triangle,
+    plane::{Disc, Plane, Quad},
+    cuboid::Cuboid,
+    cylinder::{Cone, Cylinder},
+    torus::Torus,
+    hittable_list::{Hittable, HittableList},
+    ray::Intersectable,
+    bvh::Bvh,
+    transform::{AnimatedTransform, Keyframe, Transform},
+    instance::Instance,
+    material::{Material, MaterialType, Principled, SurfaceDetail},
+    microfacet::Conductor,
+    medium::Medium,
+    grid::{DensityGrid, RawFormat, load_nvdb, load_raw, load_vol},
+    obj::{load_obj, ObjError},
+    environment::{Environment, EnvironmentMap, PreethamSky},
+    hdr::load_hdr,
+    light::{LightList, PunctualLight},
+    image::{Image, ImageError},
+    texture::{
+        Checker, CheckerSpace, ImageTexture, NoiseKind, NoiseTexture, Scaled, TextureRef, WrapMode, solid,
+    },
+};
+
+/**
+ *  Everything needed to render an image, as described by a scene file.
+ *
+ *  A scene file is plain text with one directive per line. A directive is a
+ *  keyword, possibly followed by positional arguments, followed by key=value
+ *  parameters. Vectors are written as three comma separated numbers and
+ *  everything after a # is a comment. Example:
+ *
+ *  ```text
+ *  image width=400 height=225 samples=100 max_depth=50
+ *  camera lookfrom=13,2,3 lookat=0,0,0 vfov=20 aperture=0.1 focus_dist=10
+ *
+ *  texture tiles uv_checker even=0.9,0.9,0.9 odd=0.2,0.2,0.2 scale=20
+ *  texture wood image file=wood.jpg wrap=repeat
+ *  grid plume file=plume.vol
+ *
+ *  material ground diffuse albedo=tiles
+ *  material glass dielectric ir=1.5
+ *  material steel metal albedo=0.5,0.6,0.7 roughness=0.1
+ *  material brushed metal conductor=aluminium roughness=0.3 anisotropy=0.8
+ *  material wine dielectric ir=1.33 absorption=0.5,4,3 roughness=0.1
+ *  material window dielectric thin=true albedo=0.9,1,0.95
+ *  material brick diffuse albedo=wood normal_map=bricks_normal
+ *  material rough diffuse albedo=0.8,0.8,0.8 bump=clouds bump_scale=0.02
+ *  material lamp emissive color=1,0.9,0.8 intensity=4
+ *  material paint principled base_color=0.8,0.1,0.1 roughness=0.4 clearcoat=1
+ *  material smoke volume density=2 albedo=0.8,0.8,0.8 g=0.3
+ *  material steam volume grid=plume density=10 albedo=0.95,0.95,0.95
+ *  material jade dielectric ir=1.6 medium_density=8 medium_albedo=0.6,0.95,0.7
+ *
+ *  sphere center=0,-1000,0 radius=1000 material=ground
+ *  sphere center=0,1,0 radius=1 material=glass
+ *  triangle v0=-1,0,-2 v1=1,0,-2 v2=0,2,-2 material=steel
+ *  mesh file=teapot.obj material=steel
+ *  plane point=0,0,0 normal=0,1,0 material=ground
+ *  disc center=0,3,0 normal=0,-1,0 radius=0.5 material=lamp
+ *  quad corner=-1,0,-3 edge_u=2,0,0 edge_v=0,2,0 material=steel
+ *  box min=-1,0,-1 max=1,1,1 material=paint
+ *  cylinder base=2,0,0 radius=0.5 height=2 material=glass
+ *  cone base=-2,0,0 radius=0.5 height=1 material=paint
+ *  torus center=0,1,0 major_radius=1 minor_radius=0.25 material=steel
+ *  sphere center=0,0,0 radius=1 material=paint scale=2,0.5,1 rotate_y=30 translate=3,1,0
+ *
+ *  object pillar
+ *  mesh file=column.obj material=steel scale=0.5
+ *  sphere center=0,4,0 radius=0.4 material=lamp
+ *  end
+ *  instance pillar translate=-4,0,0
+ *  instance pillar translate=4,0,0 rotate_y=90
+ *
+ *  camera shutter_open=0 shutter_close=1
+ *  sphere center=0,1,3 center1=1,1,3 radius=0.5 material=steel
+ *  keyframe spin time=0 translate=0,0,-4
+ *  keyframe spin time=1 rotate_y=45 translate=0,0.5,-4
+ *  instance pillar motion=spin
+ *  sphere center=0,5,0 radius=0.5 material=lamp
+ *  point_light position=0,4,2 color=1,1,1 intensity=20
+ *  spot_light position=0,4,0 direction=0,-1,0 intensity=50 inner=20 outer=30
+ *  directional_light direction=-1,-1,0 intensity=2 angle=0.5
+ *  environment sky sun=1,0.5,-1 turbidity=3
+ *  fog density=0.02 albedo=0.9,0.9,0.9 g=0.5 distance=50
+ *  ```
+ *
+ *  Meshes are loaded with their own .mtl materials unless material= is given.
+ *  Metals are GGX microfacet conductors. Their color comes from
+ *  conductor=<gold|copper|aluminium|silver> or a complex index of refraction
+ *  eta=r,g,b k=r,g,b, tinted by albedo, or from albedo alone otherwise.
+ *
+ *  Dielectrics are rough when given a roughness. Light traveling through
+ *  them loses absorption=r,g,b per unit distance, unless they are thin=true
+ *  sheets, whose transmission is tinted by albedo instead.
+ *
+ *  Principled materials blend diffuse, metal, glass and coatings with knobs
+ *  between 0 and 1 instead of picking a type: metallic, specular,
+ *  specular_tint, clearcoat, clearcoat_roughness, sheen, sheen_tint and
+ *  transmission. Their base_color is the albedo, and emission=t makes them
+ *  glow with emission_strength.
+ *
+ *  Media scatter light inside their volume: density is the chance per unit
+ *  distance of light hitting a particle, albedo the fraction of it that is
+ *  scattered rather than absorbed and g from -1 to 1 picks between back
+ *  and forward scattering (Henyey-Greenstein), 0 being isotropic. A volume
+ *  material makes an invisible boundary filled with a medium, like smoke.
+ *  Dielectrics and principled materials can be filled with one by giving
+ *  medium_density, medium_albedo and medium_g, for subsurface scattering.
+ *  The fog fills the space between objects, rays leaving the scene pass
+ *  through distance more of it, 0 by default so the environment stays
+ *  clear. Media can't overlap.
+ *
+ *  A medium with grid=name varies in density, which is the voxel grid
+ *  declared by that name times density. Grids are one of
+ *
+ *  - `grid <name> file=a.nvdb min=x,y,z max=x,y,z`, a sparse NanoVDB
+ *    grid, see grid::parse_nvdb() for the supported subset
+ *  - `grid <name> file=a.vol min=x,y,z max=x,y,z`, a dense Mitsuba style
+ *    grid, see grid::parse_vol() for the supported subset
+ *  - `grid <name> file=a.raw resolution=x,y,z format=<u8|f32> min=x,y,z max=x,y,z`,
+ *    headerless voxels with x varying fastest
+ *
+ *  min and max place the grid in the world, by default where a .nvdb or
+ *  .vol file puts it and in the unit cube for .raw files. The grid still needs a
+ *  closed shape around it, like a sphere with a volume material.
+ *
+ *  Quads are the parallelogram spanned by edge_u and edge_v from corner,
+ *  facing their cross product. Cylinders and cones are closed and stand on
+ *  base along the y axis, tori lie flat around center with a tube of
+ *  minor_radius. Emissive discs and quads are sampled as lights like
+ *  spheres and triangles. Planes, boxes, cylinders, cones and tori glow as
+ *  well but only light what bounces into them.
+ *
+ *  All primitives and instances take an optional transform:
+ *  scale=x,y,z (or one number for all axes), then rotate_x=, rotate_y= and
+ *  rotate_z= in degrees, then translate=x,y,z. Primitives between object
+ *  <name> and end aren't part of the scene until placed with instance,
+ *  which shares them between all instances. Object blocks can contain
+ *  primitives, meshes and instances of earlier objects but no other
+ *  directives.
+ *
+ *  The camera shutter is open from shutter_open= to shutter_close=, both 0
+ *  by default, and whatever moves in that time is blurred. Spheres with a
+ *  center1= move in a straight line from center at time 0 to center1 at
+ *  time 1. Primitives and instances can also follow motion=<name>, the
+ *  keyframes declared with `keyframe <name> time=t` and the transform
+ *  parameters above, blended linearly in between and applied after their
+ *  own transform. Motion holds still outside the times it covers, and all
+ *  keyframes of a name must come before the first motion= that uses it.
+ *
+ *  Spot light angles are measured from the axis, directional light angles
+ *  are the angular diameter of the light source, both in degrees.
+ *
+ *  The environment is one of
+ *
+ *  - `environment constant color=r,g,b`
+ *  - `environment gradient horizon=r,g,b zenith=r,g,b`, the default
+ *  - `environment map file=sky.hdr intensity=f rotation=degrees`
+ *  - `environment sky sun=x,y,z turbidity=f intensity=f`
+ *
+ *  Older scenes set it with `background sky`, the default gradient, or
+ *  `background color=r,g,b`, a constant environment.
+ *
+ *  Textures are one of
+ *
+ *  - `texture <name> solid color=r,g,b`
+ *  - `texture <name> checker even=t odd=t scale=f`, cubes in space
+ *  - `texture <name> uv_checker even=t odd=t scale=f`, squares in u, v
+ *  - `texture <name> <noise|turbulence|marble> color=r,g,b scale=f seed=n`,
+ *    seed being an unsigned integer
+ *  - `texture <name> image file=a.png wrap=<repeat|mirror|clamp> colorspace=<srgb|linear>`
+ *
+ *  Wherever a texture is expected (albedo=, roughness=, the color= of an
+ *  emissive material and the checker colors) a color r,g,b, a single gray
+ *  value or the name of a texture can be given. Normal maps should be
+ *  loaded with colorspace=linear.
+ *
+ *  Paths are relative to the scene file.
+ */
+pub struct Scene {
+    pub width: i32,
+    pub height: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub camera: Camera,
+    pub world: HittableList,
+    // Copies of the emissive objects in world.
+    pub lights: LightList,
+    pub environment: Environment,
+    // Medium filling the space between objects.
+    pub fog: Option<Medium>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+    Mesh(ObjError),
+    Texture { path: PathBuf, source: ImageError },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            SceneError::Mesh(err) => write!(f, "{}", err),
+            SceneError::Texture { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { .. } => None,
+            SceneError::Mesh(err) => Some(err),
+            SceneError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(err: ObjError) -> SceneError {
+        SceneError::Mesh(err)
+    }
+}
+
+// Directives allowed between object and end, everything else describes
+// the scene as a whole. object itself is there to report nesting.
+const OBJECT_DIRECTIVES: [&str; 13] = [
+    "sphere", "triangle", "plane", "disc", "quad", "box", "cylinder", "cone", "torus", "mesh",
+    "instance", "object", "end",
+];
+
+/**
+ *  Loads a scene file from disk.
+ */
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path)
+        .map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&source, path, dir)
+}
+
+/**
+ *  The positional arguments and key=value parameters of a single line. Every
+ *  parameter has to be used, so typos are reported instead of ignored.
+ */
+struct Directive<'a> {
+    path: &'a Path,
+    line: usize,
+    keyword: &'a str,
+    positional: Vec<&'a str>,
+    positional_used: usize,
+    params: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Directive<'a> {
+    fn parse(source_line: &'a str, path: &'a Path, line: usize) -> Result<Option<Directive<'a>>, SceneError> {
+        let source_line = source_line.split('#').next().unwrap_or("");
+        let mut tokens = source_line.split_whitespace();
+
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(None),
+        };
+
+        let mut directive = Directive {
+            path,
+            line,
+            keyword,
+            positional: vec![],
+            positional_used: 0,
+            params: HashMap::new(),
+        };
+
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    if directive.params.insert(key, value).is_some() {
+                        return Err(directive.error(format!("'{}' given more than once", key)));
+                    }
+                }
+                None if directive.params.is_empty() => directive.positional.push(token),
+                None => return Err(directive.error(format!("expected key=value, got '{}'", token))),
+            }
+        }
+
+        Ok(Some(directive))
+    }
+
+    fn error(&self, message: String) -> SceneError {
+        SceneError::Parse { path: self.path.to_path_buf(), line: self.line, message }
+    }
+
+    fn positional(&mut self, index: usize, what: &str) -> Result<&'a str, SceneError> {
+        self.positional_used = usize::max(self.positional_used, index + 1);
+        self.positional
+            .get(index)
+            .copied()
+            .ok_or_else(|| self.error(format!("{} is missing {}", self.keyword, what)))
+    }
+
+    fn take(&mut self, key: &str) -> Option<&'a str> {
+        self.params.remove(key)
+    }
+
+    fn take_str(&mut self, key: &str) -> Result<&'a str, SceneError> {
+        self.take(key)
+            .ok_or_else(|| self.error(format!("{} is missing {}=", self.keyword, key)))
+    }
+
+    fn parse_f32(&self, key: &str, value: &str) -> Result<f32, SceneError> {
+        value
+            .parse::<f32>()
+            .map_err(|_| self.error(format!("invalid number '{}' for {}", value, key)))
+    }
+
+    fn take_f32(&mut self, key: &str) -> Result<Option<f32>, SceneError> {
+        match self.take(key) {
+            Some(value) => self.parse_f32(key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn take_i32(&mut self, key: &str) -> Result<Option<i32>, SceneError> {
+        match self.take(key) {
+            Some(value) => value
+                .parse::<i32>()
+                .map(Some)
+                .map_err(|_| self.error(format!("invalid integer '{}' for {}", value, key))),
+            None => Ok(None),
+        }
+    }
+
+    fn take_u64(&mut self, key: &str) -> Result<Option<u64>, SceneError> {
+        match self.take(key) {
+            Some(value) => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| self.error(format!("invalid unsigned integer '{}' for {}", value, key))),
+            None => Ok(None),
+        }
+    }
+
+    fn take_bool(&mut self, key: &str) -> Result<Option<bool>, SceneError> {
+        match self.take(key) {
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => Err(self.error(format!("expected true or false for {}, got '{}'", key, value))),
+            None => Ok(None),
+        }
+    }
+
+    fn take_vec3(&mut self, key: &str) -> Result<Option<Vec3>, SceneError> {
+        let value = match self.take(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let parts: Vec<&str> = value.split(',').collect();
+        if parts.len() != 3 {
+            return Err(self.error(format!("expected x,y,z for {}, got '{}'", key, value)));
+        }
+        Ok(Some(Vec3::new(
+            self.parse_f32(key, parts[0])?,
+            self.parse_f32(key, parts[1])?,
+            self.parse_f32(key, parts[2])?,
+        )))
+    }
+
+    fn require_vec3(&mut self, key: &str) -> Result<Vec3, SceneError> {
+        self.take_vec3(key)?
+            .ok_or_else(|| self.error(format!("{} is missing {}=", self.keyword, key)))
+    }
+
+    fn require_f32(&mut self, key: &str) -> Result<f32, SceneError> {
+        self.take_f32(key)?
+            .ok_or_else(|| self.error(format!("{} is missing {}=", self.keyword, key)))
+    }
+
+    fn take_material(&mut self, materials: &HashMap<String, Material>) -> Result<Option<Material>, SceneError> {
+        match self.take("material") {
+            Some(name) => materials
+                .get(name)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| self.error(format!("unknown material '{}'", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn require_material(&mut self, materials: &HashMap<String, Material>) -> Result<Material, SceneError> {
+        self.take_material(materials)?
+            .ok_or_else(|| self.error(format!("{} is missing material=", self.keyword)))
+    }
+
+    /**
+     *  A texture given as r,g,b, a gray value or the name of a texture.
+     */
+    fn take_texture(&mut self, key: &str, textures: &HashMap<String, TextureRef>) -> Result<Option<TextureRef>, SceneError> {
+        let value = match self.params.get(key) {
+            Some(value) => *value,
+            None => return Ok(None),
+        };
+
+        if let Some(texture) = textures.get(value) {
+            self.take(key);
+            return Ok(Some(texture.clone()));
+        }
+        if value.contains(',') {
+            return Ok(self.take_vec3(key)?.map(solid));
+        }
+        if let Ok(gray) = value.parse::<f32>() {
+            self.take(key);
+            return Ok(Some(solid(Vec3::new(gray, gray, gray))));
+        }
+        Err(self.error(format!("unknown texture '{}' for {}", value, key)))
+    }
+
+    /**
+     *  Errors on any argument or parameter that was not used.
+     */
+    fn finish(self) -> Result<(), SceneError> {
+        if let Some(extra) = self.positional.get(self.positional_used) {
+            return Err(self.error(format!("unexpected argument '{}' for {}", extra, self.keyword)));
+        }
+
+        let mut unused: Vec<&str> = self.params.keys().copied().collect();
+        if unused.is_empty() {
+            return Ok(());
+        }
+        unused.sort();
+        Err(self.error(format!("unknown parameter '{}' for {}", unused[0], self.keyword)))
+    }
+}
+
+/**
+ *  Camera parameters as given in the file. The camera is created once the
+ *  whole file is read since the aspect ratio depends on the image size.
+ */
+struct CameraSettings {
+    lookfrom: Vec3,
+    lookat: Vec3,
+    vup: Vec3,
+    vfov: f32,
+    aperture: f32,
+    focus_dist: Option<f32>,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+/**
+ *  Primitive shared by instances and light lists.
+ */
+type SharedHittable = Arc<dyn Intersectable + Send + Sync>;
+
+/**
+ *  Primitives read at the top level of the file or inside an object block,
+ *  and copies of the emissive ones to sample as lights.
+ */
+#[derive(Default)]
+struct Scope {
+    objects: HittableList,
+    lights: Vec<SharedHittable>,
+}
+
+/**
+ *  A finished object block. Its lights are in object space and get placed
+ *  together with every instance.
+ */
+struct Object {
+    bvh: Arc<Bvh<Hittable>>,
+    lights: Vec<SharedHittable>,
+}
+
+/**
+ *  Where a primitive or instance goes: the transform given with it, then
+ *  the motion it follows.
+ */
+#[derive(Default)]
+struct Placement {
+    transform: Option<Transform>,
+    motion: Option<AnimatedTransform>,
+}
+
+impl Placement {
+    fn is_identity(&self) -> bool {
+        self.transform.is_none() && self.motion.is_none()
+    }
+
+    fn place(&self, object: SharedHittable) -> SharedHittable {
+        let object = match self.transform {
+            Some(transform) => Arc::new(Instance::new(object, transform)),
+            None => object,
+        };
+        match &self.motion {
+            Some(motion) => Arc::new(Instance::animated(object, motion.clone())),
+            None => object,
+        }
+    }
+}
+
+/**
+ *  The keyframes declared under one name, and whether anything follows them
+ *  yet. Placements copy the keyframes, so the list is closed once used.
+ */
+#[derive(Default)]
+struct Animation {
+    keyframes: Vec<Keyframe>,
+    used: bool,
+}
+
+impl Scope {
+    fn add<T: Intersectable + Clone + Send + Sync + 'static>(&mut self, emissive: bool, primitive: T, placement: &Placement) {
+        if placement.is_identity() {
+            if emissive {
+                self.lights.push(Arc::new(primitive.clone()));
+            }
+            self.objects.add(primitive);
+            return;
+        }
+
+        let placed = placement.place(Arc::new(primitive));
+        if emissive {
+            self.lights.push(placed.clone());
+        }
+        self.objects.add(placed);
+    }
+
+    fn add_instance(&mut self, object: &Object, placement: &Placement) {
+        for light in &object.lights {
+            self.lights.push(placement.place(light.clone()));
+        }
+        self.objects.add(placement.place(object.bvh.clone()));
+    }
+
+    fn into_object(self) -> Object {
+        Object { bvh: Arc::new(self.objects.into_bvh()), lights: self.lights }
+    }
+}
+
+/**
+ *  Parses the contents of a scene file. path is only used in error messages,
+ *  meshes are loaded relative to dir.
+ */
+pub fn parse_scene(source: &str, path: &Path, dir: &Path) -> Result<Scene, SceneError> {
+    let mut width = 400;
+    let mut height = 225;
+    let mut samples_per_pixel = 100;
+    let mut max_depth = 50;
+
+    let mut camera = CameraSettings {
+        lookfrom: Vec3::new(0.0, 0.0, 1.0),
+        lookat: Vec3::zero(),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 90.0,
+        aperture: 0.0,
+        focus_dist: None,
+        shutter_open: 0.0,
+        shutter_close: 0.0,
+    };
+
+    let mut textures: HashMap<String, TextureRef> = HashMap::new();
+    let mut grids: HashMap<String, Arc<DensityGrid>> = HashMap::new();
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    let mut objects: HashMap<String, Object> = HashMap::new();
+    let mut animations: HashMap<String, Animation> = HashMap::new();
+    // The scene itself, and the object block being read if any.
+    let mut top = Scope::default();
+    let mut block: Option<(&str, Scope)> = None;
+    let mut lights = LightList::new();
+    let mut environment = Environment::default();
+    let mut fog = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let mut d = match Directive::parse(line, path, i + 1)? {
+            Some(d) => d,
+            None => continue,
+        };
+
+        match d.keyword {
+            keyword if block.is_some() && !OBJECT_DIRECTIVES.contains(&keyword) => {
+                return Err(d.error(format!("'{}' is not allowed inside an object", keyword)));
+            }
+            "image" => {
+                width = d.take_i32("width")?.unwrap_or(width);
+                height = d.take_i32("height")?.unwrap_or(height);
+                samples_per_pixel = d.take_i32("samples")?.unwrap_or(samples_per_pixel);
+                max_depth = d.take_i32("max_depth")?.unwrap_or(max_depth);
+                if width < 2 || height < 2 {
+                    return Err(d.error(format!("image must be at least 2x2, got {}x{}", width, height)));
+                }
+                if samples_per_pixel < 1 || max_depth < 1 {
+                    return Err(d.error("samples and max_depth must be positive".to_string()));
+                }
+            }
+            "camera" => {
+                camera.lookfrom = d.take_vec3("lookfrom")?.unwrap_or(camera.lookfrom);
+                camera.lookat = d.take_vec3("lookat")?.unwrap_or(camera.lookat);
+                camera.vup = d.take_vec3("vup")?.unwrap_or(camera.vup);
+                camera.vfov = d.take_f32("vfov")?.unwrap_or(camera.vfov);
+                camera.aperture = d.take_f32("aperture")?.unwrap_or(camera.aperture);
+                camera.focus_dist = d.take_f32("focus_dist")?.or(camera.focus_dist);
+                camera.shutter_open = d.take_f32("shutter_open")?.unwrap_or(camera.shutter_open);
+                camera.shutter_close = d.take_f32("shutter_close")?.unwrap_or(camera.shutter_close.max(camera.shutter_open));
+                if camera.shutter_close < camera.shutter_open {
+                    return Err(d.error("shutter_close must not be before shutter_open".to_string()));
+                }
+            }
+            "texture" => {
+                let name = d.positional(0, "a name")?;
+                let texture = parse_texture(&mut d, &textures, dir)?;
+                textures.insert(name.to_string(), texture);
+            }
+            "grid" => {
+                let name = d.positional(0, "a name")?;
+                let grid = parse_grid(&mut d, dir)?;
+                grids.insert(name.to_string(), Arc::new(grid));
+            }
+            "material" => {
+                let name = d.positional(0, "a name")?;
+                let material = parse_material(&mut d, &textures, &grids)?;
+                materials.insert(name.to_string(), material);
+            }
+            "sphere" => {
+                let center = d.require_vec3("center")?;
+                let radius = require_positive(&mut d, "radius")?;
+                let center1 = d.take_vec3("center1")?.unwrap_or(center);
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let sphere = Sphere::moving(center, center1, radius, material);
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(sphere.material.is_emissive(), sphere, &placement);
+            }
+            "triangle" => {
+                let v0 = d.require_vec3("v0")?;
+                let v1 = d.require_vec3("v1")?;
+                let v2 = d.require_vec3("v2")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let triangle = Triangle::new(v0, v1, v2, material);
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(triangle.material.is_emissive(), triangle, &placement);
+            }
+            "plane" => {
+                let point = d.require_vec3("point")?;
+                let normal = require_direction(&mut d, "normal")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(false, Plane::new(point, normal, material), &placement);
+            }
+            "disc" => {
+                let center = d.require_vec3("center")?;
+                let normal = require_direction(&mut d, "normal")?;
+                let radius = require_positive(&mut d, "radius")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let disc = Disc::new(center, normal, radius, material);
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(disc.material.is_emissive(), disc, &placement);
+            }
+            "quad" => {
+                let corner = d.require_vec3("corner")?;
+                let u = d.require_vec3("edge_u")?;
+                let v = d.require_vec3("edge_v")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let quad = Quad::new(corner, u, v, material);
+                if quad.area() <= 0.0 {
+                    return Err(d.error("edge_u and edge_v must not be parallel".to_string()));
+                }
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(quad.material.is_emissive(), quad, &placement);
+            }
+            "box" => {
+                let min = d.require_vec3("min")?;
+                let max = d.require_vec3("max")?;
+                if min.x >= max.x || min.y >= max.y || min.z >= max.z {
+                    return Err(d.error("box min must be below max on every axis".to_string()));
+                }
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(false, Cuboid::new(min, max, material), &placement);
+            }
+            "cylinder" | "cone" => {
+                let base = d.require_vec3("base")?;
+                let radius = require_positive(&mut d, "radius")?;
+                let height = require_positive(&mut d, "height")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                if d.keyword == "cylinder" {
+                    scope.add(false, Cylinder::new(base, radius, height, material), &placement);
+                } else {
+                    scope.add(false, Cone::new(base, radius, height, material), &placement);
+                }
+            }
+            "torus" => {
+                let center = d.require_vec3("center")?;
+                let major_radius = require_positive(&mut d, "major_radius")?;
+                let minor_radius = require_positive(&mut d, "minor_radius")?;
+                let material = d.require_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add(false, Torus::new(center, major_radius, minor_radius, material), &placement);
+            }
+            "mesh" => {
+                let file = d.take_str("file")?;
+                let material = d.take_material(&materials)?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let mesh = load_obj(&dir.join(file))?;
+
+                let triangles = mesh.triangles.into_iter().map(|mut triangle| {
+                    if let Some(material) = &material {
+                        triangle.material = material.clone();
+                    }
+                    triangle
+                });
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                if placement.is_identity() {
+                    for triangle in triangles {
+                        scope.add(triangle.material.is_emissive(), triangle, &placement);
+                    }
+                } else {
+                    // Placed as a whole rather than triangle by triangle.
+                    let mut mesh = Scope::default();
+                    for triangle in triangles {
+                        mesh.add(triangle.material.is_emissive(), triangle, &Placement::default());
+                    }
+                    if !mesh.objects.is_empty() {
+                        scope.add_instance(&mesh.into_object(), &placement);
+                    }
+                }
+            }
+            "object" => {
+                let name = d.positional(0, "a name")?;
+                if let Some((outer, _)) = &block {
+                    return Err(d.error(format!("object '{}' can't be declared inside object '{}'", name, outer)));
+                }
+                block = Some((name, Scope::default()));
+            }
+            "end" => {
+                let (name, scope) = block.take().ok_or_else(|| d.error("end without object".to_string()))?;
+                if scope.objects.is_empty() {
+                    return Err(d.error(format!("object '{}' is empty", name)));
+                }
+                objects.insert(name.to_string(), scope.into_object());
+            }
+            "instance" => {
+                let name = d.positional(0, "an object name")?;
+                let object = objects.get(name).ok_or_else(|| d.error(format!("unknown object '{}'", name)))?;
+                let placement = take_placement(&mut d, &mut animations)?;
+                let scope = block.as_mut().map_or(&mut top, |(_, scope)| scope);
+                scope.add_instance(object, &placement);
+            }
+            "keyframe" => {
+                let name = d.positional(0, "a name")?;
+                let time = d.require_f32("time")?;
+                let keyframe = take_keyframe(&mut d, time)?.unwrap_or(Keyframe::new(time));
+                let animation = animations.entry(name.to_string()).or_default();
+                if animation.used {
+                    return Err(d.error(format!("'{}' is already in use, its keyframes must come first", name)));
+                }
+                if animation.keyframes.iter().any(|k| k.time == time) {
+                    return Err(d.error(format!("'{}' already has a keyframe at time {}", name, time)));
+                }
+                animation.keyframes.push(keyframe);
+            }
+            "point_light" => {
+                let position = d.require_vec3("position")?;
+                let (color, intensity) = light_color(&mut d)?;
+                lights.add_punctual(PunctualLight::point(position, color, intensity));
+            }
+            "spot_light" => {
+                let position = d.require_vec3("position")?;
+                let direction = require_direction(&mut d, "direction")?;
+                let (color, intensity) = light_color(&mut d)?;
+                let outer = d.take_f32("outer")?.unwrap_or(30.0);
+                let inner = d.take_f32("inner")?.unwrap_or(outer);
+                if !(0.0..=90.0).contains(&outer) || inner < 0.0 {
+                    return Err(d.error("spot light angles must be between 0 and 90".to_string()));
+                }
+                lights.add_punctual(PunctualLight::spot(position, direction, color, intensity, inner, outer));
+            }
+            "directional_light" => {
+                let direction = require_direction(&mut d, "direction")?;
+                let (color, intensity) = light_color(&mut d)?;
+                let angle = d.take_f32("angle")?.unwrap_or(0.0);
+                if !(0.0..180.0).contains(&angle) {
+                    return Err(d.error("angle must be between 0 and 180".to_string()));
+                }
+                lights.add_punctual(PunctualLight::directional(direction, color, intensity, angle));
+            }
+            "environment" => environment = parse_environment(&mut d, dir)?,
+            "background" => {
+                // Either "background sky" or "background color=r,g,b".
+                environment = match d.take_vec3("color")? {
+                    Some(color) => Environment::Constant(color),
+                    None => match d.positional(0, "sky or color=r,g,b")? {
+                        "sky" => Environment::default(),
+                        other => return Err(d.error(format!("unknown background '{}', see environment", other))),
+                    },
+                };
+            }
+            "fog" => {
+                let mut medium = parse_medium(&mut d, "", &grids)?.ok_or_else(|| d.error("fog is missing density=".to_string()))?;
+                medium.distance = d.take_f32("distance")?.unwrap_or(0.0);
+                if medium.distance < 0.0 {
+                    return Err(d.error("distance can't be negative".to_string()));
+                }
+                fog = Some(medium);
+            }
+            keyword => return Err(d.error(format!("unknown directive '{}'", keyword))),
+        }
+
+        d.finish()?;
+    }
+
+    if let Some((name, _)) = block {
+        return Err(SceneError::Parse {
+            path: path.to_path_buf(),
+            line: source.lines().count(),
+            message: format!("object '{}' is missing end", name),
+        });
+    }
+    for light in top.lights {
+        lights.add(light);
+    }
+    let world = top.objects;
+
+    let aspect_ratio = width as f32 / height as f32;
+    let focus_dist = camera.focus_dist.unwrap_or((camera.lookfrom - camera.lookat).length());
+    let camera = Camera::new(
+        camera.lookfrom,
+        camera.lookat,
+        camera.vup,
+        camera.vfov,
+        aspect_ratio,
+        camera.aperture,
+        focus_dist,
+    ).with_shutter(camera.shutter_open, camera.shutter_close);
+
+    Ok(Scene { width, height, samples_per_pixel, max_depth, camera, world, lights, environment, fog })
+}
+
+/**
+ *  Optional transform of a primitive or instance, and motion=<name> of the
+ *  keyframes it follows, applied after the transform.
+ */
+fn take_placement(d: &mut Directive, animations: &mut HashMap<String, Animation>) -> Result<Placement, SceneError> {
+    let transform = take_keyframe(d, 0.0)?.map(|keyframe| keyframe.transform());
+    let motion = match d.take("motion") {
+        Some(name) => match animations.get_mut(name) {
+            Some(animation) => {
+                animation.used = true;
+                Some(AnimatedTransform::new(animation.keyframes.clone()))
+            }
+            None => return Err(d.error(format!("unknown motion '{}'", name))),
+        },
+        None => None,
+    };
+    Ok(Placement { transform, motion })
+}
+
+/**
+ *  Optional scale=, rotate_x=, rotate_y=, rotate_z= and translate=, applied
+ *  in that order. Scale is x,y,z or one number for all axes, rotations are
+ *  in degrees. None if none of them are given.
+ */
+fn take_keyframe(d: &mut Directive, time: f32) -> Result<Option<Keyframe>, SceneError> {
+    let mut keyframe = Keyframe::new(time);
+    let mut given = false;
+
+    let scale = match d.params.get("scale").copied() {
+        Some(value) if !value.contains(',') => {
+            d.take("scale");
+            let factor = d.parse_f32("scale", value)?;
+            Some(Vec3::new(factor, factor, factor))
+        }
+        _ => d.take_vec3("scale")?,
+    };
+    if let Some(scale) = scale {
+        if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+            return Err(d.error("scale can't be zero".to_string()));
+        }
+        keyframe.scale = scale;
+        given = true;
+    }
+
+    let rotation = [d.take_f32("rotate_x")?, d.take_f32("rotate_y")?, d.take_f32("rotate_z")?];
+    if rotation.iter().any(Option::is_some) {
+        let [x, y, z] = rotation.map(|degrees| degrees.unwrap_or(0.0));
+        keyframe.rotation = Vec3::new(x, y, z);
+        given = true;
+    }
+    if let Some(offset) = d.take_vec3("translate")? {
+        keyframe.translation = offset;
+        given = true;
+    }
+    Ok(if given { Some(keyframe) } else { None })
+}
+
+/**
+ *  A number that has to be above zero, like a radius.
+ */
+fn require_positive(d: &mut Directive, key: &str) -> Result<f32, SceneError> {
+    let value = d.require_f32(key)?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err(d.error(format!("{} must be positive", key)));
+    }
+    Ok(value)
+}
+
+/**
+ *  A vector that can be normalized, like a normal.
+ */
+fn require_direction(d: &mut Directive, key: &str) -> Result<Vec3, SceneError> {
+    let value = d.require_vec3(key)?;
+    if value.length_squared() == 0.0 {
+        return Err(d.error(format!("{} can't be zero", key)));
+    }
+    Ok(value)
+}
+
+/**
+ *  color=r,g,b intensity=f of a light, white and 1 by default.
+ */
+fn light_color(d: &mut Directive) -> Result<(Vec3, f32), SceneError> {
+    let color = d.take_vec3("color")?.unwrap_or(Vec3::new(1.0, 1.0, 1.0));
+    let intensity = d.take_f32("intensity")?.unwrap_or(1.0);
+    Ok((color, intensity))
+}
+
+/**
+ *  environment <constant|gradient|map|sky> ...
+ */
+fn parse_environment(d: &mut Directive, dir: &Path) -> Result<Environment, SceneError> {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    match d.positional(0, "a type")? {
+        "constant" => Ok(Environment::Constant(d.take_vec3("color")?.unwrap_or(white))),
+        "gradient" => Ok(Environment::Gradient {
+            horizon: d.take_vec3("horizon")?.unwrap_or(white),
+            zenith: d.take_vec3("zenith")?.unwrap_or(Vec3::new(0.5, 0.7, 1.0)),
+        }),
+        "map" => {
+            let path = dir.join(d.take_str("file")?);
+            let intensity = d.take_f32("intensity")?.unwrap_or(1.0);
+            let rotation = d.take_f32("rotation")?.unwrap_or(0.0);
+            let image = load_hdr(&path).map_err(|source| SceneError::Io { path, source })?;
+            if image.width == 0 || image.height == 0 {
+                return Err(d.error("environment map is empty".to_string()));
+            }
+            Ok(Environment::Map(EnvironmentMap::new(image, intensity, rotation)))
+        }
+        "sky" => {
+            let sun = d.take_vec3("sun")?.unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+            let turbidity = d.take_f32("turbidity")?.unwrap_or(3.0);
+            let intensity = d.take_f32("intensity")?.unwrap_or(1.0);
+            if !(1.7..=10.0).contains(&turbidity) {
+                return Err(d.error("turbidity must be between 1.7 and 10".to_string()));
+            }
+            if sun == Vec3::zero() {
+                return Err(d.error("sun direction can't be zero".to_string()));
+            }
+            Ok(Environment::Sky(PreethamSky::new(sun, turbidity, intensity)))
+        }
+        other => Err(d.error(format!("unknown environment type '{}'", other))),
+    }
+}
+
+/**
+ *  texture <name> <solid|checker|uv_checker|noise|turbulence|marble|image> ...
+ */
+fn parse_texture(d: &mut Directive, textures: &HashMap<String, TextureRef>, dir: &Path) -> Result<TextureRef, SceneError> {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let texture: TextureRef = match d.positional(1, "a type")? {
+        "solid" => solid(d.take_vec3("color")?.unwrap_or(white)),
+        kind @ ("checker" | "uv_checker") => {
+            let space = if kind == "checker" { CheckerSpace::Solid } else { CheckerSpace::Uv };
+            Arc::new(Checker {
+                even: d.take_texture("even", textures)?.unwrap_or_else(|| solid(white)),
+                odd: d.take_texture("odd", textures)?.unwrap_or_else(|| solid(Vec3::zero())),
+                scale: d.take_f32("scale")?.unwrap_or(1.0),
+                space,
+            })
+        }
+        kind @ ("noise" | "turbulence" | "marble") => {
+            let kind = match kind {
+                "noise" => NoiseKind::Noise,
+                "turbulence" => NoiseKind::Turbulence,
+                _ => NoiseKind::Marble,
+            };
+            let color = d.take_vec3("color")?.unwrap_or(white);
+            let scale = d.take_f32("scale")?.unwrap_or(1.0);
+            let seed = d.take_u64("seed")?.unwrap_or(0);
+            Arc::new(NoiseTexture::new(kind, color, scale, seed))
+        }
+        "image" => {
+            let path = dir.join(d.take_str("file")?);
+            let wrap = match d.take("wrap").unwrap_or("repeat") {
+                "repeat" => WrapMode::Repeat,
+                "mirror" => WrapMode::Mirror,
+                "clamp" => WrapMode::Clamp,
+                other => return Err(d.error(format!("unknown wrap mode '{}'", other))),
+            };
+            let srgb = match d.take("colorspace").unwrap_or("srgb") {
+                "srgb" => true,
+                "linear" => false,
+                other => return Err(d.error(format!("unknown colorspace '{}'", other))),
+            };
+            let image = Image::load(&path).map_err(|source| SceneError::Texture { path, source })?;
+            if image.width() == 0 || image.height() == 0 {
+                return Err(d.error("texture image is empty".to_string()));
+            }
+            Arc::new(ImageTexture::new(&image, wrap, srgb))
+        }
+        other => return Err(d.error(format!("unknown texture type '{}'", other))),
+    };
+    Ok(texture)
+}
+
+/**
+ *  material <name> <diffuse|metal|dielectric> albedo=t roughness=t ir=f normal_map=t bump=t bump_scale=f
+ *  material <name> metal ... conductor=name eta=r,g,b k=r,g,b anisotropy=f
+ *  material <name> dielectric ... absorption=r,g,b thin=<true|false>
+ *  material <name> emissive color=t intensity=f
+ *  material <name> volume density=f albedo=r,g,b g=f grid=name
+ *  material <name> <dielectric|principled> ... medium_density=f medium_albedo=r,g,b medium_g=f medium_grid=name
+ *  material <name> principled base_color=t roughness=t metallic=f specular=f specular_tint=f
+ *      clearcoat=f clearcoat_roughness=f sheen=f sheen_tint=f transmission=f ir=f
+ *      anisotropy=f absorption=r,g,b emission=t emission_strength=f
+ */
+fn parse_material(
+    d: &mut Directive,
+    textures: &HashMap<String, TextureRef>,
+    grids: &HashMap<String, Arc<DensityGrid>>) -> Result<Material, SceneError> {
+
+    let material_type = match d.positional(1, "a type")? {
+        "diffuse" => MaterialType::Diffuse,
+        "metal" => MaterialType::Metal,
+        "dielectric" => MaterialType::Dielectric,
+        "principled" => MaterialType::Principled,
+        "volume" => {
+            let medium = parse_medium(d, "", grids)?.ok_or_else(|| d.error("volume is missing density=".to_string()))?;
+            return Ok(Material::interface(medium));
+        }
+        "emissive" => {
+            let color = d.take_texture("color", textures)?.unwrap_or_else(|| solid(Vec3::new(1.0, 1.0, 1.0)));
+            let intensity = d.take_f32("intensity")?.unwrap_or(1.0);
+            return Ok(Material {
+                emission: Arc::new(Scaled { texture: color, scale: intensity }),
+                ..Material::emissive(Vec3::zero(), 0.0)
+            });
+        }
+        other => return Err(d.error(format!("unknown material type '{}'", other))),
+    };
+
+    let default_ir = match material_type {
+        MaterialType::Dielectric | MaterialType::Principled => 1.5,
+        _ => 0.0,
+    };
+
+    let mut material = Material::new(Vec3::new(1.0, 1.0, 1.0), 0.0, default_ir, material_type);
+    let albedo = d.take_texture("albedo", textures)?;
+    if let Some(albedo) = &albedo {
+        material.albedo = albedo.clone();
+    }
+    if let Some(roughness) = d.take_texture("roughness", textures)? {
+        material.roughness = roughness;
+    }
+    material.ir = d.take_f32("ir")?.unwrap_or(default_ir);
+
+    if let MaterialType::Metal = material_type {
+        material.conductor = parse_conductor(d)?;
+    }
+
+    if let MaterialType::Principled = material_type {
+        if let Some(base_color) = d.take_texture("base_color", textures)? {
+            if albedo.is_some() {
+                return Err(d.error("base_color and albedo are the same, give only one".to_string()));
+            }
+            material.albedo = base_color;
+        }
+        material.principled = parse_principled(d)?;
+        if let Some(emission) = d.take_texture("emission", textures)? {
+            let strength = d.take_f32("emission_strength")?.unwrap_or(1.0);
+            material.emission = Arc::new(Scaled { texture: emission, scale: strength });
+        }
+    }
+
+    if let MaterialType::Metal | MaterialType::Principled = material_type {
+        material.anisotropy = d.take_f32("anisotropy")?.unwrap_or(0.0);
+        if !(-1.0..=1.0).contains(&material.anisotropy) {
+            return Err(d.error("anisotropy must be between -1 and 1".to_string()));
+        }
+    }
+
+    if let MaterialType::Dielectric | MaterialType::Principled = material_type {
+        material.medium = parse_medium(d, "medium_", grids)?;
+        material.absorption = d.take_vec3("absorption")?.unwrap_or(Vec3::zero());
+        if let MaterialType::Dielectric = material_type {
+            material.thin = d.take_bool("thin")?.unwrap_or(false);
+        }
+        let a = material.absorption;
+        if a.x < 0.0 || a.y < 0.0 || a.z < 0.0 {
+            return Err(d.error("absorption can't be negative".to_string()));
+        }
+        if material.ir <= 0.0 {
+            return Err(d.error("ir must be positive".to_string()));
+        }
+    }
+
+    let normal_map = d.take_texture("normal_map", textures)?;
+    let bump = d.take_texture("bump", textures)?;
+    let bump_scale = d.take_f32("bump_scale")?;
+    material.detail = match (normal_map, bump) {
+        (Some(_), Some(_)) => return Err(d.error("only one of normal_map= and bump= can be given".to_string())),
+        (Some(texture), None) => Some(SurfaceDetail::NormalMap(texture)),
+        (None, Some(height)) => Some(SurfaceDetail::BumpMap { height, scale: bump_scale.unwrap_or(1.0) }),
+        (None, None) => None,
+    };
+    if bump_scale.is_some() && !matches!(material.detail, Some(SurfaceDetail::BumpMap { .. })) {
+        return Err(d.error("bump_scale= needs bump=".to_string()));
+    }
+
+    Ok(material)
+}
+
+/**
+ *  density=f albedo=r,g,b g=f grid=name of a medium, with the keys
+ *  prefixed by prefix. None if there is no density.
+ */
+fn parse_medium(d: &mut Directive, prefix: &str, grids: &HashMap<String, Arc<DensityGrid>>) -> Result<Option<Medium>, SceneError> {
+    let density = d.take_f32(&format!("{}density", prefix))?;
+    let albedo = d.take_vec3(&format!("{}albedo", prefix))?;
+    let g = d.take_f32(&format!("{}g", prefix))?;
+    let grid = match d.take(&format!("{}grid", prefix)) {
+        Some(name) => Some(grids.get(name).cloned().ok_or_else(|| d.error(format!("unknown grid '{}'", name)))?),
+        None => None,
+    };
+    let density = match density {
+        Some(density) => density,
+        None if albedo.is_none() && g.is_none() && grid.is_none() => return Ok(None),
+        None => return Err(d.error(format!("{}albedo=, {}g= and {}grid= need {}density=", prefix, prefix, prefix, prefix))),
+    };
+
+    let albedo = albedo.unwrap_or(Vec3::new(1.0, 1.0, 1.0));
+    let g = g.unwrap_or(0.0);
+    if density < 0.0 {
+        return Err(d.error("density can't be negative".to_string()));
+    }
+    if [albedo.x, albedo.y, albedo.z].iter().any(|a| !(0.0..=1.0).contains(a)) {
+        return Err(d.error("albedo of a medium must be between 0 and 1".to_string()));
+    }
+    if g <= -1.0 || g >= 1.0 {
+        return Err(d.error("g must be between -1 and 1".to_string()));
+    }
+    let medium = Medium::new(density, albedo, g);
+    Ok(Some(match grid {
+        Some(grid) => medium.with_grid(grid),
+        None => medium,
+    }))
+}
+
+/**
+ *  grid <name> file=smoke.vol min=x,y,z max=x,y,z
+ *  grid <name> file=smoke.raw resolution=x,y,z format=<u8|f32> min=x,y,z max=x,y,z
+ */
+fn parse_grid(d: &mut Directive, dir: &Path) -> Result<DensityGrid, SceneError> {
+    let path = dir.join(d.take_str("file")?);
+    let grid = match path.extension().and_then(|e| e.to_str()) {
+        Some("vol") => load_vol(&path).map_err(|source| SceneError::Io { path, source })?,
+        Some("nvdb") => load_nvdb(&path).map_err(|source| SceneError::Io { path, source })?,
+        Some("raw") => {
+            let resolution = d.require_vec3("resolution")?;
+            if [resolution.x, resolution.y, resolution.z].iter().any(|&n| n < 1.0 || n.fract() != 0.0) {
+                return Err(d.error("resolution must be three positive integers".to_string()));
+            }
+            let resolution = [resolution.x as usize, resolution.y as usize, resolution.z as usize];
+            let format = match d.take("format").unwrap_or("f32") {
+                "u8" => RawFormat::U8,
+                "f32" => RawFormat::F32,
+                other => return Err(d.error(format!("unknown grid format '{}'", other))),
+            };
+            load_raw(&path, resolution, format).map_err(|source| SceneError::Io { path, source })?
+        }
+        _ => return Err(d.error(format!("{}: unsupported grid file, use .nvdb, .vol or .raw", path.display()))),
+    };
+
+    let min = d.take_vec3("min")?.unwrap_or(grid.bounds.min);
+    let max = d.take_vec3("max")?.unwrap_or(grid.bounds.max);
+    if !(min.x < max.x && min.y < max.y && min.z < max.z) {
+        return Err(d.error("min has to be below max".to_string()));
+    }
+    Ok(grid.with_bounds(min, max))
+}
+
+/**
+ *  The knobs of a principled material, each between 0 and 1.
+ */
+fn parse_principled(d: &mut Directive) -> Result<Principled, SceneError> {
+    let defaults = Principled::default();
+    let mut knob = |key: &str, default: f32| -> Result<f32, SceneError> {
+        let value = d.take_f32(key)?.unwrap_or(default);
+        if !(0.0..=1.0).contains(&value) {
+            return Err(d.error(format!("{} must be between 0 and 1", key)));
+        }
+        Ok(value)
+    };
+    Ok(Principled {
+        metallic: knob("metallic", defaults.metallic)?,
+        specular: knob("specular", defaults.specular)?,
+        specular_tint: knob("specular_tint", defaults.specular_tint)?,
+        clearcoat: knob("clearcoat", defaults.clearcoat)?,
+        clearcoat_roughness: knob("clearcoat_roughness", defaults.clearcoat_roughness)?,
+        sheen: knob("sheen", defaults.sheen)?,
+        sheen_tint: knob("sheen_tint", defaults.sheen_tint)?,
+        transmission: knob("transmission", defaults.transmission)?,
+    })
+}
+
+/**
+ *  conductor=name or eta=r,g,b k=r,g,b of a metal.
+ */
+fn parse_conductor(d: &mut Directive) -> Result<Option<Conductor>, SceneError> {
+    let preset = match d.take("conductor") {
+        Some(name) => Some(
+            Conductor::from_name(name).ok_or_else(|| d.error(format!("unknown conductor '{}'", name)))?,
+        ),
+        None => None,
+    };
+
+    match (preset, d.take_vec3("eta")?, d.take_vec3("k")?) {
+        (preset, None, None) => Ok(preset),
+        (None, Some(eta), Some(k)) => Ok(Some(Conductor { eta, k })),
+        (Some(_), _, _) => Err(d.error("conductor= can't be combined with eta= and k=".to_string())),
+        _ => Err(d.error("eta= and k= have to be given together".to_string())),
+    }
+}
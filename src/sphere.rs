@@ -1,22 +1,39 @@
-use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material};
+use std::f32::consts::PI;
 
-#[derive(Clone, Copy)]
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb, onb::{Onb, random_in_cone}, sampler::Sampler};
+
+#[derive(Clone)]
 pub struct Sphere {
+    // Center at time 0, and at time 1 for moving spheres.
     pub center: Vec3,
+    pub center1: Vec3,
     pub radius: f32,
     pub material: Material,
 }
 
 impl Sphere {
     pub fn new(center: Vec3, radius: f32, material: Material) -> Sphere {
-        Sphere { center, radius, material }
+        Sphere { center, center1: center, radius, material }
+    }
+
+    /**
+     *  Sphere moving in a straight line from center0 at time 0 to center1
+     *  at time 1. It stays put before and after.
+     */
+    pub fn moving(center0: Vec3, center1: Vec3, radius: f32, material: Material) -> Sphere {
+        Sphere { center: center0, center1, radius, material }
+    }
+
+    pub fn center_at(&self, time: f32) -> Vec3 {
+        self.center + f32::clamp(time, 0.0, 1.0) * (self.center1 - self.center)
     }
 }
 
 impl Intersectable for Sphere {
 
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) ->  Option<Intersection> {
-        let oc = r.origin - self.center;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        let center = self.center_at(r.time);
+        let oc = r.origin - center;
         let a = r.direction.length_squared();
         let half_b = oc.dot(r.direction);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -38,12 +55,77 @@ impl Intersectable for Sphere {
         }
 
         let point = r.at(root);
-        let outward_normal = (point - self.center) / self.radius;
+        let outward_normal = (point - center) / self.radius;
         let t = root;
 
-        let mut intersection = Intersection::new(point, outward_normal, t, self.material);
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
         intersection.set_face_normal(r, outward_normal);
+        (intersection.u, intersection.v) = sphere_uv(outward_normal);
+        (intersection.dpdu, intersection.dpdv) = sphere_tangents(outward_normal, self.radius);
 
         Some(intersection)
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::new(self.center - r, self.center + r);
+        Aabb::surrounding(&start, &Aabb::new(self.center1 - r, self.center1 + r))
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        if self.hit(&Ray::new(origin, direction).with_time(time), 0.001, f32::MAX).is_none() {
+            return 0.0;
+        }
+
+        // From inside every direction hits, and they are sampled uniformly.
+        let distance_squared = (self.center_at(time) - origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI);
+        }
+
+        // 1 - cos_theta_max, written so it doesn't round to zero for far away
+        // spheres.
+        let sin2_theta_max = self.radius * self.radius / distance_squared;
+        let solid_angle = 2.0 * PI * sin2_theta_max / (1.0 + f32::sqrt(1.0 - sin2_theta_max));
+        1.0 / solid_angle
+    }
+
+    /**
+     *  Uniformly samples the cone of directions the sphere covers as seen
+     *  from origin.
+     */
+    fn random_direction(&self, origin: Vec3, time: f32, sampler: &mut Sampler) -> Vec3 {
+        let to_center = self.center_at(time) - origin;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vector(sampler);
+        }
+
+        let cos_theta_max = f32::sqrt(1.0 - self.radius * self.radius / distance_squared);
+        Onb::from_w(to_center).local(random_in_cone(sampler, cos_theta_max))
+    }
+}
+
+/**
+ *  Texture coordinates of a point on the unit sphere. u goes around the y
+ *  axis starting at -x, v goes from the bottom (v = 0) to the top (v = 1).
+ */
+pub fn sphere_uv(p: Vec3) -> (f32, f32) {
+    let theta = f32::acos(-p.y);
+    let phi = f32::atan2(-p.z, p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+/**
+ *  Derivatives of the point on a sphere of radius with respect to u and v,
+ *  at the unit normal p. Zero at the poles where u is undefined.
+ */
+pub fn sphere_tangents(p: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let ring = f32::sqrt(p.x * p.x + p.z * p.z);
+    if ring < 1e-6 {
+        return (Vec3::zero(), Vec3::zero());
+    }
+    let dpdu = 2.0 * PI * radius * Vec3::new(p.z, 0.0, -p.x);
+    let dpdv = PI * radius * Vec3::new(-p.y * p.x / ring, ring, -p.y * p.z / ring);
+    (dpdu, dpdv)
+}
@@ -0,0 +1,318 @@
+use std::sync::Arc;
+
+use crate::{vec3::Vec3, image::Image, sampler::Sampler};
+
+/**
+ *  A material parameter that varies over a surface, looked up by texture
+ *  coordinates or by the hit point in space.
+ */
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, point: Vec3) -> Vec3;
+
+    /**
+     *  The value if it is the same everywhere.
+     */
+    fn constant(&self) -> Option<Vec3> {
+        None
+    }
+}
+
+/**
+ *  A texture that can be shared between materials and render threads.
+ */
+pub type TextureRef = Arc<dyn Texture + Send + Sync>;
+
+/**
+ *  Shorthand for a texture of a single color.
+ */
+pub fn solid(color: Vec3) -> TextureRef {
+    Arc::new(SolidColor(color))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolidColor(pub Vec3);
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _point: Vec3) -> Vec3 {
+        self.0
+    }
+
+    fn constant(&self) -> Option<Vec3> {
+        Some(self.0)
+    }
+}
+
+/**
+ *  Where a checker pattern is laid out.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckerSpace {
+    // Cubes in world space, scale of them per unit.
+    Solid,
+    // Squares in texture space, scale of them per unit of u and v.
+    Uv,
+}
+
+/**
+ *  Alternates between two textures.
+ */
+pub struct Checker {
+    pub even: TextureRef,
+    pub odd: TextureRef,
+    pub scale: f32,
+    pub space: CheckerSpace,
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, point: Vec3) -> Vec3 {
+        let cells = match self.space {
+            CheckerSpace::Solid => {
+                let p = point * self.scale;
+                p.x.floor() as i64 + p.y.floor() as i64 + p.z.floor() as i64
+            }
+            CheckerSpace::Uv => (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64,
+        };
+
+        if cells.rem_euclid(2) == 0 {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+/**
+ *  Gradient noise after Ken Perlin, with random unit vectors at the lattice
+ *  points. The lattice is generated from a seed so renders stay
+ *  reproducible.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut sampler = Sampler::new(seed);
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| Vec3::random_unit_vector(&mut sampler))
+            .collect();
+
+        Perlin {
+            gradients,
+            perm_x: Perlin::permutation(&mut sampler),
+            perm_y: Perlin::permutation(&mut sampler),
+            perm_z: Perlin::permutation(&mut sampler),
+        }
+    }
+
+    fn permutation(sampler: &mut Sampler) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+        for i in (1..PERLIN_POINTS).rev() {
+            let target = (sampler.next_u32() as usize) % (i + 1);
+            p.swap(i, target);
+        }
+        p
+    }
+
+    /**
+     *  Smooth noise in [-1, 1].
+     */
+    pub fn noise(&self, p: Vec3) -> f32 {
+        let (u, v, w) = (p.x - p.x.floor(), p.y - p.y.floor(), p.z - p.z.floor());
+        let (i, j, k) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+
+        // Hermite smoothing of the weights.
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mask = PERLIN_POINTS as i64 - 1;
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & mask) as usize]
+                        ^ self.perm_y[((j + dj) & mask) as usize]
+                        ^ self.perm_z[((k + dk) & mask) as usize];
+                    let weight = Vec3::new(u - di as f32, v - dj as f32, w - dk as f32);
+
+                    let (fi, fj, fk) = (di as f32, dj as f32, dk as f32);
+                    sum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * self.gradients[index].dot(weight);
+                }
+            }
+        }
+        sum
+    }
+
+    /**
+     *  Sum of depth octaves of noise, each at twice the frequency and half
+     *  the weight of the one before.
+     */
+    pub fn turbulence(&self, p: Vec3, depth: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut point = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(point);
+            weight *= 0.5;
+            point *= 2.0;
+        }
+        f32::abs(sum)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    // Plain noise, soft blobs.
+    Noise,
+    // Several octaves of noise, a cloudy pattern.
+    Turbulence,
+    // Stripes along z distorted by turbulence.
+    Marble,
+}
+
+/**
+ *  Procedural noise in space, scale being its frequency.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoiseTexture {
+    pub perlin: Perlin,
+    pub kind: NoiseKind,
+    pub color: Vec3,
+    pub scale: f32,
+    pub depth: u32,
+}
+
+impl NoiseTexture {
+    pub fn new(kind: NoiseKind, color: Vec3, scale: f32, seed: u64) -> NoiseTexture {
+        NoiseTexture { perlin: Perlin::new(seed), kind, color, scale, depth: 7 }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: Vec3) -> Vec3 {
+        let p = point * self.scale;
+        let t = match self.kind {
+            NoiseKind::Noise => 0.5 * (1.0 + self.perlin.noise(p)),
+            NoiseKind::Turbulence => self.perlin.turbulence(p, self.depth),
+            NoiseKind::Marble => 0.5 * (1.0 + f32::sin(p.z + 10.0 * self.perlin.turbulence(p, self.depth))),
+        };
+        self.color * t
+    }
+}
+
+/**
+ *  What happens to texture coordinates outside [0, 1].
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+impl WrapMode {
+    fn wrap(&self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n { i } else { 2 * n - 1 - i }
+            }
+            WrapMode::Clamp => i.clamp(0, n - 1),
+        };
+        i as usize
+    }
+}
+
+/**
+ *  A bilinearly filtered image. v = 0 is the bottom row.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /**
+     *  Texture of image. Colors are decoded from sRGB unless the image holds
+     *  linear data such as roughness.
+     */
+    pub fn new(image: &Image, wrap: WrapMode, srgb: bool) -> ImageTexture {
+        let decode = |c: u8| {
+            let c = c as f32 / 255.0;
+            if !srgb {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                f32::powf((c + 0.055) / 1.055, 2.4)
+            }
+        };
+
+        let pixels = image
+            .as_bytes()
+            .chunks_exact(3)
+            .map(|rgb| Vec3::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2])))
+            .collect();
+
+        ImageTexture { width: image.width(), height: image.height(), pixels, wrap }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = self.wrap.wrap(x, self.width);
+        let y = self.wrap.wrap(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: Vec3) -> Vec3 {
+        if self.pixels.is_empty() {
+            return Vec3::new(1.0, 0.0, 1.0);
+        }
+
+        // Pixel centers are at half integer coordinates.
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - tx) * self.texel(x0, y0) + tx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - tx) * self.texel(x0, y0 + 1) + tx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+/**
+ *  Another texture multiplied by a constant, e.g. an emission texture by
+ *  the intensity of the light.
+ */
+pub struct Scaled {
+    pub texture: TextureRef,
+    pub scale: f32,
+}
+
+impl Texture for Scaled {
+    fn value(&self, u: f32, v: f32, point: Vec3) -> Vec3 {
+        self.texture.value(u, v, point) * self.scale
+    }
+
+    fn constant(&self) -> Option<Vec3> {
+        self.texture.constant().map(|c| c * self.scale)
+    }
+}
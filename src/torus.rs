@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb};
+
+/**
+ *  Largest real root of x^3 + a x^2 + b x + c.
+ */
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // Depressed to y^3 + p y + q with x = y - a / 3.
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let shift = -a / 3.0;
+
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    if discriminant >= 0.0 {
+        // One real root (Cardano).
+        let s = f64::sqrt(discriminant);
+        f64::cbrt(-q / 2.0 + s) + f64::cbrt(-q / 2.0 - s) + shift
+    } else {
+        // Three real roots, the largest at angle 0 (Viète).
+        let m = 2.0 * f64::sqrt(-p / 3.0);
+        let angle = f64::acos(f64::clamp(3.0 * q / (p * m), -1.0, 1.0)) / 3.0;
+        m * f64::cos(angle) + shift
+    }
+}
+
+/**
+ *  Real roots of x^4 + a x^3 + b x^2 + c x + d by Ferrari's method,
+ *  polished with a few Newton steps. Double roots may be reported twice.
+ */
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depressed to y^4 + p y^2 + q y + r with x = y - a / 4.
+    let p = b - 3.0 * a * a / 8.0;
+    let q = c - a * b / 2.0 + a * a * a / 8.0;
+    let r = d - a * c / 4.0 + a * a * b / 16.0 - 3.0 * a * a * a * a / 256.0;
+    let shift = -a / 4.0;
+
+    let mut roots = vec![];
+    let mut quadratic = |b: f64, c: f64| {
+        let discriminant = b * b - 4.0 * c;
+        if discriminant >= 0.0 {
+            let s = f64::sqrt(discriminant);
+            roots.push((-b - s) / 2.0 + shift);
+            roots.push((-b + s) / 2.0 + shift);
+        }
+    };
+
+    // (y^2 + (s + p) / 2)^2 = s (y - q / 2s)^2 for the root s of the
+    // resolvent cubic, which splits into two quadratics.
+    let s = largest_cubic_root(2.0 * p, p * p - 4.0 * r, -q * q);
+    if s <= 1e-12 {
+        // Biquadratic, a quadratic in y^2.
+        let discriminant = p * p - 4.0 * r;
+        if discriminant >= 0.0 {
+            for z in [(-p - discriminant.sqrt()) / 2.0, (-p + discriminant.sqrt()) / 2.0] {
+                if z >= 0.0 {
+                    roots.push(-z.sqrt() + shift);
+                    roots.push(z.sqrt() + shift);
+                }
+            }
+        }
+    } else {
+        let sqrt_s = s.sqrt();
+        quadratic(-sqrt_s, (s + p) / 2.0 + q / (2.0 * sqrt_s));
+        quadratic(sqrt_s, (s + p) / 2.0 - q / (2.0 * sqrt_s));
+    }
+
+    for x in roots.iter_mut() {
+        for _ in 0..2 {
+            let f = (((*x + a) * *x + b) * *x + c) * *x + d;
+            let df = ((4.0 * *x + 3.0 * a) * *x + 2.0 * b) * *x + c;
+            if df != 0.0 {
+                *x -= f / df;
+            }
+        }
+    }
+    roots
+}
+
+/**
+ *  Ring around the y axis through center, with the tube of minor_radius
+ *  running at major_radius from the axis. u goes around the y axis like on
+ *  spheres, v around the tube starting at its outside.
+ */
+#[derive(Clone)]
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vec3, major_radius: f32, minor_radius: f32, material: Material) -> Torus {
+        Torus { center, major_radius, minor_radius, material }
+    }
+}
+
+impl Intersectable for Torus {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        // Solved along the unit direction from the point of the ray closest
+        // to the center, which keeps the coefficients small.
+        let length = r.direction.length() as f64;
+        let d = r.direction / length as f32;
+        let start = (self.center - r.origin).dot(d) as f64;
+        let o = r.origin + start as f32 * d - self.center;
+        let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)
+        let big = self.major_radius as f64;
+        let small = self.minor_radius as f64;
+        let m = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + big * big - small * small;
+        let four_r2 = 4.0 * big * big;
+        let roots = solve_quartic(
+            4.0 * m,
+            4.0 * m * m + 2.0 * k - four_r2 * (dx * dx + dz * dz),
+            4.0 * m * k - 2.0 * four_r2 * (ox * dx + oz * dz),
+            k * k - four_r2 * (ox * ox + oz * oz),
+        );
+
+        let t = roots
+            .into_iter()
+            .map(|s| ((start + s) / length) as f32)
+            .filter(|&t| t >= t_min && t <= t_max)
+            .min_by(f32::total_cmp)?;
+
+        let point = r.at(t);
+        let p = point - self.center;
+        let ring = f32::sqrt(p.x * p.x + p.z * p.z);
+        if ring == 0.0 {
+            return None;
+        }
+        let axis_point = self.major_radius * Vec3::new(p.x / ring, 0.0, p.z / ring);
+        let outward_normal = (p - axis_point).normalized();
+
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
+        intersection.set_face_normal(r, outward_normal);
+        let phi = f32::atan2(-p.z, p.x) + PI;
+        let theta = f32::atan2(p.y, ring - self.major_radius).rem_euclid(2.0 * PI);
+        intersection.u = phi / (2.0 * PI);
+        intersection.v = theta / (2.0 * PI);
+        intersection.dpdu = 2.0 * PI * Vec3::new(p.z, 0.0, -p.x);
+        intersection.dpdv = 2.0 * PI * self.minor_radius * Vec3::new(
+            -f32::sin(theta) * p.x / ring,
+            f32::cos(theta),
+            -f32::sin(theta) * p.z / ring,
+        );
+        Some(intersection)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let reach = self.major_radius + self.minor_radius;
+        let r = Vec3::new(reach, self.minor_radius, reach);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
@@ -0,0 +1,346 @@
+use std::ops;
+
+use crate::{vec3::Vec3, aabb::Aabb, utils::deg_to_rad};
+
+/**
+ *  4x4 matrix acting on column vectors, stored row by row.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    /**
+     *  Determinant of the upper left 3x3 part, the change in volume of an
+     *  affine transform.
+     */
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /**
+     *  Inverse by Gauss-Jordan elimination with partial pivoting, None if
+     *  the matrix is singular.
+     */
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap_or(col);
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+/**
+ *  An affine transform, kept together with its inverse since rays are
+ *  moved into object space by the inverse and results back out by the
+ *  transform itself.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { matrix: Mat4::identity(), inverse: Mat4::identity() }
+    }
+
+    /**
+     *  None if matrix can't be inverted.
+     */
+    pub fn from_matrix(matrix: Mat4) -> Option<Transform> {
+        matrix.inverse().map(|inverse| Transform { matrix, inverse })
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        let mut matrix = Mat4::identity();
+        let mut inverse = Mat4::identity();
+        for axis in 0..3 {
+            matrix.m[axis][3] = offset[axis];
+            inverse.m[axis][3] = -offset[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /**
+     *  Scales by factor along each axis, which must not be zero.
+     */
+    pub fn scale(factor: Vec3) -> Transform {
+        let mut matrix = Mat4::identity();
+        let mut inverse = Mat4::identity();
+        for axis in 0..3 {
+            matrix.m[axis][axis] = factor[axis];
+            inverse.m[axis][axis] = 1.0 / factor[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /**
+     *  Counter clockwise rotation by degrees around axis, looking against
+     *  the axis.
+     */
+    pub fn rotate(axis: Vec3, degrees: f32) -> Transform {
+        let a = axis.normalized();
+        let (sin, cos) = f32::sin_cos(deg_to_rad(degrees));
+        let matrix = Mat4::new([
+            [a.x * a.x + (1.0 - a.x * a.x) * cos, a.x * a.y * (1.0 - cos) - a.z * sin, a.x * a.z * (1.0 - cos) + a.y * sin, 0.0],
+            [a.x * a.y * (1.0 - cos) + a.z * sin, a.y * a.y + (1.0 - a.y * a.y) * cos, a.y * a.z * (1.0 - cos) - a.x * sin, 0.0],
+            [a.x * a.z * (1.0 - cos) - a.y * sin, a.y * a.z * (1.0 - cos) + a.x * sin, a.z * a.z + (1.0 - a.z * a.z) * cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // Rotations are orthogonal.
+        Transform { matrix, inverse: matrix.transpose() }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    /**
+     *  This transform followed by next.
+     */
+    pub fn then(&self, next: &Transform) -> Transform {
+        *next * *self
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        let m = &self.matrix.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x, y, z) / w
+        }
+    }
+
+    /**
+     *  Transforms a direction, which unlike a point isn't moved.
+     */
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.matrix.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /**
+     *  Transforms a surface normal by the inverse transpose, so it stays
+     *  perpendicular to the transformed surface. Not normalized.
+     */
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inverse.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /**
+     *  Box around the transformed corners of bounds.
+     */
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let mut result = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+                if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+                if i & 4 == 0 { bounds.min.z } else { bounds.max.z },
+            );
+            result = result.grow(self.point(corner));
+        }
+        result
+    }
+}
+
+impl ops::Mul for Transform {
+    type Output = Transform;
+
+    /**
+     *  a * b applies b first, then a.
+     */
+    fn mul(self, other: Transform) -> Transform {
+        Transform { matrix: self.matrix * other.matrix, inverse: other.inverse * self.inverse }
+    }
+}
+
+/**
+ *  Placement of an animated object at one point in time, kept in parts so
+ *  it can be blended with the next keyframe. Rotations are in degrees
+ *  around x, then y, then z, and may go past 360 to spin several times.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub scale: Vec3,
+    pub rotation: Vec3,
+    pub translation: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32) -> Keyframe {
+        Keyframe { time, scale: Vec3::new(1.0, 1.0, 1.0), rotation: Vec3::zero(), translation: Vec3::zero() }
+    }
+
+    /**
+     *  Scale, then rotation, then translation.
+     */
+    pub fn transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&Transform::rotate(Vec3::new(1.0, 0.0, 0.0), self.rotation.x))
+            .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), self.rotation.y))
+            .then(&Transform::rotate(Vec3::new(0.0, 0.0, 1.0), self.rotation.z))
+            .then(&Transform::translate(self.translation))
+    }
+
+    fn lerp(&self, other: &Keyframe, time: f32) -> Keyframe {
+        let s = (time - self.time) / (other.time - self.time);
+        Keyframe {
+            time,
+            scale: self.scale + s * (other.scale - self.scale),
+            rotation: self.rotation + s * (other.rotation - self.rotation),
+            translation: self.translation + s * (other.translation - self.translation),
+        }
+    }
+}
+
+/**
+ *  A transform changing over time, blended linearly between keyframes.
+ *  Before the first and after the last keyframe it holds still.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    /**
+     *  keyframes must not be empty. They are sorted by time, and none may
+     *  scale by zero.
+     */
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "animated transform without keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn at(&self, time: f32) -> Transform {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0].transform();
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].transform();
+        }
+        self.keyframes[next - 1].lerp(&self.keyframes[next], time).transform()
+    }
+
+    /**
+     *  Box around everything bounds sweeps through. Each segment between
+     *  keyframes is sampled finely enough that rotations bulge at most a
+     *  fraction of a percent past the samples, and the box is padded by that.
+     */
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let mut result = self.keyframes[0].transform().bounds(bounds);
+        for pair in self.keyframes.windows(2) {
+            let turn = pair[1].rotation - pair[0].rotation;
+            let degrees = turn.x.abs() + turn.y.abs() + turn.z.abs();
+            let steps = f32::clamp(f32::ceil(degrees / 2.0), 1.0, 1000.0) as usize;
+            let bulge = 1.0 - f32::cos(deg_to_rad(degrees / steps as f32 / 2.0));
+
+            for step in 1..=steps {
+                let time = pair[0].time + (pair[1].time - pair[0].time) * step as f32 / steps as f32;
+                let transform = pair[0].lerp(&pair[1], time).transform();
+                let placed = transform.bounds(bounds);
+                // Points turn around the translated origin.
+                let radius = (placed.centroid() - transform.point(Vec3::zero())).length() + 0.5 * placed.extent().length();
+                let pad = bulge * radius;
+                let pad = Vec3::new(pad, pad, pad);
+                result = Aabb::surrounding(&result, &Aabb::new(placed.min - pad, placed.max + pad));
+            }
+        }
+        result
+    }
+}
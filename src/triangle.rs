@@ -0,0 +1,161 @@
+use crate::{ray::{Intersectable, Intersection, Ray}, vec3::Vec3, material::Material, aabb::Aabb, sampler::Sampler};
+
+// Rays closer to parallel with the triangle plane than this, relative to
+// the size of the triangle and the ray direction, are misses.
+const EPSILON: f32 = 1e-8;
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: Option<[(f32, f32); 3]>,
+    pub material: Material,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: Material) -> Triangle {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+
+    /**
+     *  Per-vertex normals which are interpolated across the face for smooth
+     *  shading.
+     */
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    /**
+     *  Per-vertex texture coordinates.
+     */
+    pub fn with_uvs(mut self, uvs: [(f32, f32); 3]) -> Triangle {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /**
+     *  Normal of the plane the triangle lies in, following the counter
+     *  clockwise winding of the vertices.
+     */
+    pub fn face_normal(&self) -> Vec3 {
+        let [v0, v1, v2] = self.vertices;
+        (v1 - v0).cross(v2 - v0).normalized()
+    }
+
+    pub fn area(&self) -> f32 {
+        let [v0, v1, v2] = self.vertices;
+        0.5 * (v1 - v0).cross(v2 - v0).length()
+    }
+}
+
+impl Intersectable for Triangle {
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Intersection<'_>> {
+        // Möller–Trumbore
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+
+        let pvec = r.direction.cross(edge2);
+        let det = edge1.dot(pvec);
+
+        // det scales with both edges and the direction, so the tolerance does
+        // as well or small triangles would always be missed.
+        if f32::abs(det) < EPSILON * edge1.length() * edge2.length() * r.direction.length() {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - v0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(edge1);
+        let b2 = r.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(qvec) * inv_det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let point = r.at(t);
+        let outward_normal = edge1.cross(edge2).normalized();
+
+        let mut intersection = Intersection::new(point, outward_normal, t, &self.material);
+        intersection.set_face_normal(r, outward_normal);
+        intersection.barycentric = Vec3::new(b0, b1, b2);
+
+        if let Some([n0, n1, n2]) = self.normals {
+            let mut shading_normal = (b0 * n0 + b1 * n1 + b2 * n2).normalized();
+            // Keep the shading normal on the same side as the one we hit.
+            if shading_normal.dot(intersection.normal) < 0.0 {
+                shading_normal = -shading_normal;
+            }
+            intersection.normal = shading_normal;
+        }
+
+        let [uv0, uv1, uv2] = self.uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        intersection.u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+        intersection.v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+
+        // Solve the edges for the point's derivatives in u and v.
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() > EPSILON {
+            intersection.dpdu = (dv2 * edge1 - dv1 * edge2) / det;
+            intersection.dpdv = (du1 * edge2 - du2 * edge1) / det;
+        }
+
+        Some(intersection)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [v0, v1, v2] = self.vertices;
+        let min = v0.min(v1).min(v2);
+        let max = v0.max(v1).max(v2);
+
+        // Pad flat boxes so axis aligned triangles still have volume.
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        Aabb::new(min - pad, max + pad)
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, _time: f32) -> f32 {
+        let hit = match self.hit(&Ray::new(origin, direction), 0.001, f32::MAX) {
+            Some(hit) => hit,
+            None => return 0.0,
+        };
+
+        // Uniform density over the area, converted to solid angle.
+        let distance_squared = hit.t * hit.t * direction.length_squared();
+        let cosine = f32::abs(direction.dot(self.face_normal())) / direction.length();
+        if cosine < EPSILON {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area())
+    }
+
+    /**
+     *  Direction towards a point picked uniformly on the triangle.
+     */
+    fn random_direction(&self, origin: Vec3, _time: f32, sampler: &mut Sampler) -> Vec3 {
+        let [v0, v1, v2] = self.vertices;
+        let s = f32::sqrt(sampler.next_f32());
+        let b1 = 1.0 - s;
+        let b2 = sampler.next_f32() * s;
+        let point = v0 + b1 * (v1 - v0) + b2 * (v2 - v0);
+        point - origin
+    }
+}
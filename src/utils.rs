@@ -1,21 +1,14 @@
 use std::f32::consts::PI;
 
-use rand::{thread_rng, Rng};
-
-/**
- *  Random number between [0, 1)
- */
-pub fn random_f32() -> f32 {
-    let mut rng = thread_rng();
-    rng.gen()
-}
-
 /**
- *  Random number between [min, max)
+ *  Mixes a seed with an index into a new, well distributed seed
+ *  (SplitMix64). Used to derive independent seeds for tiles and pixels.
  */
-pub fn random_range_f32(min: f32, max: f32) -> f32 {
-    let mut rng = thread_rng();
-    rng.gen_range(min..max)
+pub fn hash_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
 }
 
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
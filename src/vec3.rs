@@ -1,6 +1,6 @@
 use std::{ops::{self, Neg}};
 
-use crate::utils::{random_f32, random_range_f32};
+use crate::sampler::Sampler;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
@@ -26,35 +26,35 @@ impl Vec3 {
     pub fn new(x: f32, y: f32, z: f32) -> Vec3 { Vec3 { x, y, z } }
     pub fn zero() -> Vec3 { Vec3 { x: 0.0, y: 0.0, z: 0.0 } }
 
-    pub fn random() -> Vec3 {
+    pub fn random(sampler: &mut Sampler) -> Vec3 {
         Vec3 {
-            x: random_f32(),
-            y: random_f32(),
-            z: random_f32(),
+            x: sampler.next_f32(),
+            y: sampler.next_f32(),
+            z: sampler.next_f32(),
         }
     }
 
-    pub fn random_range(min: f32, max: f32) -> Vec3 {
+    pub fn random_range(sampler: &mut Sampler, min: f32, max: f32) -> Vec3 {
         Vec3 {
-            x: random_range_f32(min, max),
-            y: random_range_f32(min, max),
-            z: random_range_f32(min, max),
+            x: sampler.range_f32(min, max),
+            y: sampler.range_f32(min, max),
+            z: sampler.range_f32(min, max),
         }
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(sampler: &mut Sampler) -> Vec3 {
         loop {
-            let p = Vec3::random_range(-1.0, 1.0);
+            let p = Vec3::random_range(sampler, -1.0, 1.0);
             if p.length_squared() >= 1.0 { continue; }
             return p;
         }
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
+    pub fn random_in_unit_disk(sampler: &mut Sampler) -> Vec3 {
         loop {
             let p = Vec3::new(
-                random_range_f32(-1.0, 1.0), 
-                random_range_f32(-1.0, 1.0), 
+                sampler.range_f32(-1.0, 1.0), 
+                sampler.range_f32(-1.0, 1.0), 
                 0.0
             );
             if p.length_squared() >= 1.0 { continue; }
@@ -62,8 +62,8 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::normalized(&Vec3::random_in_unit_sphere())
+    pub fn random_unit_vector(sampler: &mut Sampler) -> Vec3 {
+        Vec3::normalized(&Vec3::random_in_unit_sphere(sampler))
     }
 
     /**
@@ -134,6 +134,28 @@ impl Vec3 {
         let r_out_parallel = -f32::sqrt(f32::abs(1.0 - r_out_perp.length_squared())) * n;
         r_out_perp + r_out_parallel
     }
+
+    /**
+     *  Component wise minimum of the two vectors.
+     */
+    pub fn min(&self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: f32::min(self.x, other.x),
+            y: f32::min(self.y, other.y),
+            z: f32::min(self.z, other.z),
+        }
+    }
+
+    /**
+     *  Component wise maximum of the two vectors.
+     */
+    pub fn max(&self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: f32::max(self.x, other.x),
+            y: f32::max(self.y, other.y),
+            z: f32::max(self.z, other.z),
+        }
+    }
 }
 
 impl Default for Vec3 {
@@ -142,10 +164,23 @@ impl Default for Vec3 {
     }
 }
 
-/**
+/*
  *  Operator overloading. Each operation is element wise.
  */
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl ops::Sub for Vec3 {
     type Output = Vec3;
 
@@ -1,6 +1,41 @@
 #[cfg(test)]
 mod tests {
-    use raytracer::vec3::Vec3;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use raytracer::{
+        vec3::{Vec3, IVec3},
+        ray::{Ray, Intersectable, Intersection},
+        sphere::Sphere,
+        triangle::Triangle,
+        bvh::Bvh,
+        aabb::Aabb,
+        hittable_list::HittableList,
+        material::{Material, MaterialType, Principled, SurfaceDetail},
+        obj::{load_obj, parse_mtl, ObjError},
+        scene::{parse_scene, load_scene, SceneError},
+        cli::{parse_args, Command, Options},
+        image::{Image, ImageFormat},
+        hdr::{to_rgbe, from_rgbe, write_hdr, read_hdr, save_hdr, HdrImage},
+        environment::{Environment, EnvironmentMap, PreethamSky},
+        framebuffer::{Framebuffer, Gamma, Reinhard, PostProcess},
+        render::{RenderContext, render, render_multithreaded, ray_color, tiles},
+        light::{LightList, PunctualLight},
+        camera::Camera,
+        microfacet::{Conductor, Ggx, fresnel_dielectric},
+        medium::{Medium, Phase},
+        grid::{DensityGrid, RawFormat, BRICK_SIZE, parse_nvdb, parse_raw, parse_vol, write_vol},
+        sampler::Sampler,
+        transform::{AnimatedTransform, Keyframe, Mat4, Transform},
+        instance::Instance,
+        plane::{Plane, Disc, Quad, PLANE_EXTENT},
+        cuboid::Cuboid,
+        cylinder::{Cylinder, Cone},
+        torus::Torus,
+        texture::{
+            Texture, Checker, CheckerSpace, ImageTexture, NoiseKind, NoiseTexture, WrapMode, solid,
+        },
+    };
 
     #[test]
     fn vector_add() {
@@ -161,4 +196,2480 @@ mod tests {
         assert_eq!(v2.z, 5.0);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn bvh_matches_linear_scan() {
+        let mut sampler = Sampler::new(42);
+        let mut spheres: Vec<Sphere> = vec![];
+        for _ in 0..500 {
+            let center = Vec3::random_range(&mut sampler, -10.0, 10.0);
+            spheres.push(Sphere::new(center, sampler.range_f32(0.05, 0.5), Material::default()));
+        }
+        let bvh = Bvh::new(spheres.clone());
+        assert_eq!(bvh.len(), spheres.len());
+
+        for _ in 0..2000 {
+            let r = Ray::new(Vec3::random_range(&mut sampler, -12.0, 12.0), Vec3::random_range(&mut sampler, -1.0, 1.0));
+
+            let mut expected: Option<f32> = None;
+            for sphere in &spheres {
+                if let Some(hit) = sphere.hit(&r, 0.001, expected.unwrap_or(f32::MAX)) {
+                    expected = Some(hit.t);
+                }
+            }
+
+            let actual = bvh.hit(&r, 0.001, f32::MAX).map(|hit| hit.t);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn bvh_empty() {
+        let bvh: Bvh<Sphere> = Bvh::new(vec![]);
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        assert!(bvh.hit(&r, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn hittable_list_mixed_objects() {
+        let mut inner = HittableList::new();
+        inner.add(Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::default()));
+
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::default()));
+        world.add(inner);
+        assert_eq!(world.len(), 2);
+
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let hit = world.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.t, 4.0);
+
+        let bvh = world.into_bvh();
+        let hit = bvh.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.t, 4.0);
+    }
+
+    #[test]
+    fn triangle_hit_barycentric() {
+        let tri = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Material::default(),
+        ).with_uvs([(0.0, 0.0), (2.0, 0.0), (0.0, 4.0)]);
+
+        let r = Ray::new(Vec3::new(0.25, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = tri.hit(&r, 0.001, f32::MAX).unwrap();
+
+        assert_eq!(hit.t, 1.0);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(hit.barycentric, Vec3::new(0.25, 0.25, 0.5));
+        assert_eq!(hit.u, 0.5);
+        assert_eq!(hit.v, 2.0);
+    }
+
+    #[test]
+    fn triangle_miss() {
+        let tri = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Material::default(),
+        );
+
+        let outside = Ray::new(Vec3::new(0.75, 0.75, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(tri.hit(&outside, 0.001, f32::MAX).is_none());
+
+        let parallel = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(tri.hit(&parallel, 0.001, f32::MAX).is_none());
+
+        let behind = Ray::new(Vec3::new(0.25, 0.25, -2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(tri.hit(&behind, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn triangle_tiny() {
+        // Finely tessellated meshes have triangles far smaller than one
+        // unit, they have to be hit just the same.
+        let size = 1e-5;
+        let tri = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(size, 0.0, -1.0),
+            Vec3::new(0.0, size, -1.0),
+            Material::default(),
+        );
+        let r = Ray::new(Vec3::new(0.25 * size, 0.25 * size, 0.0), Vec3::new(0.0, 0.0, -0.5));
+        let hit = tri.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        let grazing = Ray::new(Vec3::new(0.0, 0.0, -1.0 + size), Vec3::new(1.0, 1.0, 0.0));
+        assert!(tri.hit(&grazing, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn triangle_smooth_normal() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let tilted = Vec3::new(1.0, 0.0, 1.0).normalized();
+        let tri = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Material::default(),
+        ).with_normals([n, tilted, n]);
+
+        // Hitting from the back flips the shading normal towards the ray.
+        let r = Ray::new(Vec3::new(0.5, 0.25, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = tri.hit(&r, 0.001, f32::MAX).unwrap();
+
+        assert!(!hit.front_face);
+        assert!(hit.normal.z < 0.0);
+        assert!(hit.normal.x < 0.0);
+        assert!((hit.normal.length() - 1.0).abs() < 1e-6);
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+    }
+
+    #[test]
+    fn obj_load_quad() {
+        let mesh = load_obj(&fixture("quad.obj")).unwrap();
+
+        // The quad is split into two triangles, plus one more from the back group.
+        assert_eq!(mesh.triangles.len(), 3);
+        assert_eq!(mesh.groups.len(), 2);
+        assert_eq!(mesh.groups[0].name, "front");
+        assert_eq!((mesh.groups[0].first, mesh.groups[0].count), (0, 2));
+        assert_eq!(mesh.groups[1].name, "back");
+        assert_eq!((mesh.groups[1].first, mesh.groups[1].count), (2, 1));
+
+        let front = &mesh.triangles[1];
+        assert_eq!(front.vertices[2], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(front.uvs.unwrap()[2], (0.0, 1.0));
+        assert_eq!(front.normals.unwrap()[0], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(front.material.albedo.constant(), Some(Vec3::new(0.8, 0.1, 0.1)));
+        assert_eq!(front.material.roughness.constant(), Some(Vec3::new(1.0, 1.0, 1.0)));
+        assert!(matches!(front.material.material_type, MaterialType::Diffuse));
+
+        // Negative indices count from the end.
+        let back = &mesh.triangles[2];
+        assert_eq!(back.vertices[0], Vec3::new(0.0, 1.0, 0.0));
+        assert!(back.normals.is_none());
+        assert_eq!(back.material.ir, 1.45);
+        assert!(matches!(back.material.material_type, MaterialType::Dielectric));
+    }
+
+    #[test]
+    fn obj_missing_file() {
+        match load_obj(&fixture("does_not_exist.obj")) {
+            Err(ObjError::Io { .. }) => {},
+            _ => panic!("expected io error"),
+        }
+
+        match load_obj(&fixture("missing_mtl.obj")) {
+            Err(ObjError::Io { path, .. }) => assert!(path.ends_with("does_not_exist.mtl")),
+            _ => panic!("expected io error"),
+        }
+    }
+
+    #[test]
+    fn obj_malformed_lines() {
+        match load_obj(&fixture("bad_face.obj")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 4),
+            _ => panic!("expected parse error"),
+        }
+
+        match load_obj(&fixture("bad_number.obj")) {
+            Err(err @ ObjError::Parse { .. }) => {
+                assert!(err.to_string().contains("bad_number.obj:2:"));
+            }
+            _ => panic!("expected parse error"),
+        }
+    }
+
+    #[test]
+    fn scene_parse() {
+        let source = "
+            # comment
+            image width=200 height=100 samples=8 max_depth=5
+            camera lookfrom=0,0,5 lookat=0,0,0 vfov=40
+
+            material red diffuse albedo=0.9,0.1,0.1
+            material glass dielectric
+            sphere center=0,0,0 radius=1 material=red   # trailing comment
+            triangle v0=-1,0,-2 v1=1,0,-2 v2=0,2,-2 material=glass
+            mesh file=quad.obj
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), &fixture("")).unwrap();
+
+        assert_eq!((scene.width, scene.height), (200, 100));
+        assert_eq!(scene.samples_per_pixel, 8);
+        assert_eq!(scene.max_depth, 5);
+        assert_eq!(scene.camera.aspect_ratio, 2.0);
+        assert_eq!(scene.world.len(), 2 + 3);
+
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = scene.world.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.t, 4.0);
+        assert_eq!(hit.material.albedo.constant(), Some(Vec3::new(0.9, 0.1, 0.1)));
+    }
+
+    #[test]
+    fn scene_errors_have_line_numbers() {
+        let cases = [
+            ("image width=100\nsphere center=0,0 radius=1 material=x", 2),
+            ("material a diffuse\n\nsphere center=0,0,0 radius=1 material=b", 3),
+            ("material a diffuse albdo=1,1,1", 1),
+            ("image width=100\nimage width=1", 2),
+            ("camera\nlight", 2),
+            ("sphere center=0,0,0 radius=one material=a", 1),
+            ("material a diffuse\nsphere center=0,0,0 radius=0 material=a", 2),
+            ("material a diffuse\nsphere center=0,0,0 radius=-1 material=a", 2),
+            ("material a diffuse\nsphere center=0,0,0 radius=inf material=a", 2),
+            ("material a diffuse\nsphere center=0,0,0 radius=NaN material=a", 2),
+        ];
+
+        for (source, expected_line) in cases {
+            match parse_scene(source, Path::new("test.scene"), Path::new("")) {
+                Err(SceneError::Parse { line, .. }) => assert_eq!(line, expected_line, "{}", source),
+                _ => panic!("expected parse error for {}", source),
+            }
+        }
+
+        match load_scene(&fixture("does_not_exist.scene")) {
+            Err(SceneError::Io { .. }) => {},
+            _ => panic!("expected io error"),
+        }
+    }
+
+    #[test]
+    fn scene_example_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes").join("three_spheres.scene");
+        let scene = load_scene(&path).unwrap();
+        assert_eq!(scene.world.len(), 4);
+        assert_eq!(scene.environment, Environment::default());
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes").join("cornell_box.scene");
+        let scene = load_scene(&path).unwrap();
+        assert_eq!(scene.world.len(), 14);
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.environment, Environment::Constant(Vec3::zero()));
+        assert!(scene.world.objects().iter().any(|object| {
+            let r = Ray::new(Vec3::new(278.0, 300.0, 278.0), Vec3::new(0.0, 1.0, 0.0));
+            object.hit(&r, 0.001, f32::MAX).is_some_and(|hit| hit.material.is_emissive())
+        }));
+    }
+
+    #[test]
+    fn scene_emissive_and_background() {
+        let source = "
+            material lamp emissive color=1,0.5,0.25 intensity=4
+            sphere center=0,0,0 radius=1 material=lamp
+            background color=0.1,0.1,0.1
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        assert_eq!(scene.environment, Environment::Constant(Vec3::new(0.1, 0.1, 0.1)));
+
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = scene.world.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.emitted(&hit), Vec3::new(4.0, 2.0, 1.0));
+
+        let scene = parse_scene("background color=0,0,0\nbackground sky", Path::new("test.scene"), Path::new("")).unwrap();
+        assert_eq!(scene.environment, Environment::default());
+
+        let bad = [
+            "background",
+            "background night",
+            "material lamp emissive albedo=1,1,1",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn emissive_material() {
+        let lamp = Material::emissive(Vec3::new(1.0, 1.0, 1.0), 3.0);
+        let sphere = Sphere::new(Vec3::zero(), 1.0, lamp.clone());
+        let mut sampler = Sampler::new(0);
+
+        // Seen from outside the light shines, from inside it doesn't.
+        let outside = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = sphere.hit(&outside, 0.001, f32::MAX).unwrap();
+        assert_eq!(lamp.emitted(&hit), Vec3::new(3.0, 3.0, 3.0));
+        assert!(!lamp.scatter(&outside, &hit, &mut sampler).0);
+
+        let inside = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let hit = sphere.hit(&inside, 0.001, f32::MAX).unwrap();
+        assert_eq!(lamp.emitted(&hit), Vec3::zero());
+
+        // A ray hitting the light directly sees its emission, one missing
+        // everything sees the environment.
+        let environment = Environment::Constant(Vec3::zero());
+        let lights = LightList::new();
+        assert_eq!(ray_color(outside, &sphere, &lights, &environment, None, 8, &mut sampler), Vec3::new(3.0, 3.0, 3.0));
+        let miss = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray_color(miss, &sphere, &lights, &environment, None, 8, &mut sampler), Vec3::zero());
+    }
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn cli_parse_options() {
+        let command = parse_args(args("--width 640 -H=360 --spp=16 -d 8 -t 4 --seed 42 -o out.ppm scene.txt")).unwrap();
+        let expected = Options {
+            width: Some(640),
+            height: Some(360),
+            samples_per_pixel: Some(16),
+            max_depth: Some(8),
+            threads: Some(4),
+            single_threaded: false,
+            scene: Some(PathBuf::from("scene.txt")),
+            output: Some(PathBuf::from("out.ppm")),
+            seed: Some(42),
+        };
+        assert_eq!(command, Command::Render(expected));
+
+        assert_eq!(parse_args(args("")).unwrap(), Command::Render(Options::default()));
+        assert_eq!(parse_args(args("--spp 4 --help")).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn cli_validation() {
+        assert!(parse_args(args("--spp 0")).is_err());
+        assert!(parse_args(args("--width -5")).is_err());
+        assert!(parse_args(args("--threads many")).is_err());
+        assert!(parse_args(args("--output")).is_err());
+        assert!(parse_args(args("--frobnicate")).is_err());
+        assert!(parse_args(args("--single-threaded --threads 8")).is_err());
+        assert!(parse_args(args("--single-threaded=yes")).is_err());
+        assert!(parse_args(args("a.scene --scene b.scene")).is_err());
+    }
+
+    #[test]
+    fn cli_image_size_keeps_aspect_ratio() {
+        let mut options = Options::default();
+        assert_eq!(options.image_size(400, 200), (400, 200));
+
+        options.width = Some(100);
+        assert_eq!(options.image_size(400, 200), (100, 50));
+
+        options.width = None;
+        options.height = Some(100);
+        assert_eq!(options.image_size(400, 200), (200, 100));
+    }
+
+    fn test_image() -> Image {
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, IVec3::new(255, 0, 0));
+        image.set_pixel(2, 1, IVec3::new(1, 2, 300));
+        image
+    }
+
+    #[test]
+    fn image_format_from_extension() {
+        assert_eq!(ImageFormat::from_path(Path::new("out.png")), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("dir/OUT.PPM")), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path(Path::new("out.jpg")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("out")), None);
+    }
+
+    #[test]
+    fn image_write_ppm() {
+        let image = test_image();
+        assert_eq!(image.get_pixel(2, 1), IVec3::new(1, 2, 255));
+
+        let mut bytes: Vec<u8> = vec![];
+        image.write_ppm(&mut bytes).unwrap();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], image.as_bytes());
+
+        let mut text: Vec<u8> = vec![];
+        image.write_ppm_ascii(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("P3\n3 2\n255\n255 0 0\n"));
+        assert!(text.ends_with("1 2 255\n"));
+    }
+
+    /**
+     *  Accepts every write but fails to flush, like a closed pipe behind a
+     *  buffer.
+     */
+    struct FailingFlush;
+
+    impl std::io::Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn image_write_errors_surface_on_flush() {
+        let image = test_image();
+        assert!(image.write_ppm(std::io::BufWriter::new(FailingFlush)).is_err());
+        assert!(image.write_ppm_ascii(std::io::BufWriter::new(FailingFlush)).is_err());
+        assert!(image.write_png(std::io::BufWriter::new(FailingFlush)).is_err());
+        assert!(write_hdr(std::io::BufWriter::new(FailingFlush), 1, 1, &[Vec3::zero()]).is_err());
+    }
+
+    #[test]
+    fn image_save_png() {
+        let image = test_image();
+        let path = std::env::temp_dir().join(format!("raytracer_test_{}.png", std::process::id()));
+        image.save(&path).unwrap();
+
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(&data[..info.buffer_size()], image.as_bytes());
+
+        assert!(image.save(Path::new("out.bmp")).is_err());
+    }
+
+    #[test]
+    fn hdr_rgbe_encoding() {
+        assert_eq!(to_rgbe(Vec3::zero()), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Vec3::new(1.0, 0.5, 0.25)), [128, 64, 32, 129]);
+        // Values above one are kept instead of clamped.
+        assert_eq!(to_rgbe(Vec3::new(6.0, 0.0, 1.0)), [192, 0, 32, 131]);
+        assert_eq!(to_rgbe(Vec3::new(-1.0, 0.5, 0.0)), [0, 128, 0, 128]);
+        // Out of range values saturate.
+        assert_eq!(to_rgbe(Vec3::new(f32::INFINITY, 1.0, 0.0)), [255, 0, 0, 255]);
+        assert_eq!(to_rgbe(Vec3::new(f32::MAX, 0.0, f32::MAX)), [255, 0, 255, 255]);
+        assert_eq!(to_rgbe(Vec3::new(1e38, 0.0, 0.0)), [150, 0, 0, 255]);
+    }
+
+    #[test]
+    fn hdr_write_flat() {
+        let pixels = [Vec3::new(1.0, 0.5, 0.25), Vec3::new(6.0, 0.0, 1.0)];
+        let mut bytes: Vec<u8> = vec![];
+        write_hdr(&mut bytes, 1, 2, &pixels).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 1\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], &[128, 64, 32, 129, 192, 0, 32, 131]);
+    }
+
+    #[test]
+    fn hdr_write_rle() {
+        let width = 20;
+        let pixels: Vec<Vec3> = (0..width)
+            .map(|x| if x < 10 { Vec3::new(1.0, 1.0, 1.0) } else { Vec3::new(x as f32, 0.0, 0.0) })
+            .collect();
+        let mut bytes: Vec<u8> = vec![];
+        write_hdr(&mut bytes, width, 1, &pixels).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 20\n";
+        let data = &bytes[header.len()..];
+        assert_eq!(&data[..4], &[2, 2, 0, 20]);
+
+        // Decode the four channels again and compare with the flat encoding.
+        let mut channels: Vec<Vec<u8>> = vec![vec![]; 4];
+        let mut i = 4;
+        for channel in channels.iter_mut() {
+            while channel.len() < width {
+                let n = data[i] as usize;
+                if n > 128 {
+                    channel.extend(std::iter::repeat_n(data[i + 1], n - 128));
+                    i += 2;
+                } else {
+                    channel.extend_from_slice(&data[i + 1..i + 1 + n]);
+                    i += 1 + n;
+                }
+            }
+        }
+        assert_eq!(i, data.len());
+
+        for (x, pixel) in pixels.iter().enumerate() {
+            let rgbe = to_rgbe(*pixel);
+            for c in 0..4 {
+                assert_eq!(channels[c][x], rgbe[c]);
+            }
+        }
+    }
+
+    #[test]
+    fn framebuffer_accumulate() {
+        let mut fb = Framebuffer::new(4, 3);
+        fb.add_sample(1, 2, Vec3::new(1.0, 2.0, 3.0));
+        fb.add_sample(1, 2, Vec3::new(3.0, 2.0, 1.0));
+
+        assert_eq!(fb[(1, 2)].samples, 2);
+        assert_eq!(fb[(1, 2)].mean(), Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(fb[(0, 0)].mean(), Vec3::zero());
+        assert_eq!(fb.to_linear()[2 * 4 + 1], Vec3::new(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn framebuffer_merge_tiles() {
+        let mut fb = Framebuffer::new(4, 4);
+        fb.add_sample(3, 3, Vec3::new(1.0, 1.0, 1.0));
+
+        let mut tile = Framebuffer::new(2, 2);
+        tile.add_sample(0, 0, Vec3::new(0.5, 0.5, 0.5));
+        tile.add_sample(1, 1, Vec3::new(3.0, 3.0, 3.0));
+
+        // The part of the tile outside the framebuffer is dropped.
+        fb.merge(&tile, 2, 2);
+        fb.merge(&tile, 3, 3);
+
+        assert_eq!(fb[(2, 2)].mean(), Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(fb[(3, 3)].samples, 3);
+        assert_eq!(fb[(3, 3)].sum, Vec3::new(4.5, 4.5, 4.5));
+        assert_eq!(fb.pixels().iter().map(|p| p.samples).sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn framebuffer_to_image() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.add_sample(0, 0, Vec3::new(0.25, 4.0, -1.0));
+        fb.add_sample(1, 0, Vec3::new(1.0, 1.0, 1.0));
+
+        let image = fb.to_image(&Gamma::default());
+        assert_eq!(image.get_pixel(0, 0), IVec3::new(128, 255, 0));
+
+        let reinhard = Reinhard { exposure: 1.0, gamma: Gamma { gamma: 1.0 } };
+        assert_eq!(reinhard.apply(Vec3::new(1.0, 3.0, 0.0)), Vec3::new(0.5, 0.75, 0.0));
+        let image = fb.to_image(&reinhard);
+        assert_eq!(image.get_pixel(1, 0), IVec3::new(128, 128, 128));
+    }
+
+    #[test]
+    fn render_tiles_cover_image() {
+        let (width, height) = (70, 33);
+        let mut covered = vec![0; width * height];
+        for tile in tiles(width, height) {
+            for y in tile.y0..tile.y0 + tile.height {
+                for x in tile.x0..tile.x0 + tile.width {
+                    covered[y * width + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+    }
+
+    fn test_render_context(seed: u64) -> RenderContext {
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::default()));
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::new(
+            Vec3::new(0.8, 0.6, 0.2), 0.3, 0.0, MaterialType::Metal)));
+
+        let camera = Camera::new(
+            Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+            90.0, 2.0, 0.0, 1.0);
+
+        RenderContext {
+            width: 50,
+            height: 25,
+            samples_per_pixel: 2,
+            max_depth: 4,
+            camera,
+            world: Box::new(world.into_bvh()),
+            lights: LightList::new(),
+            environment: Environment::default(),
+            fog: None,
+            seed,
+        }
+    }
+
+    #[test]
+    fn render_independent_of_thread_count() {
+        let ctx = test_render_context(1234);
+        let single = render(&ctx);
+        assert_eq!(single.pixels()[0].samples, 2);
+        assert_eq!(render_multithreaded(&ctx, 1), single);
+        assert_eq!(render_multithreaded(&ctx, 3), single);
+        assert_eq!(render_multithreaded(&ctx, 8), single);
+
+        let other_seed = render(&test_render_context(4321));
+        assert_ne!(other_seed, single);
+    }
+
+    #[test]
+    fn sampler_is_deterministic() {
+        let mut a = Sampler::new(7);
+        let mut b = Sampler::new(7);
+        let mut c = Sampler::new(8);
+        let xs: Vec<u32> = (0..16).map(|_| a.next_u32()).collect();
+        let ys: Vec<u32> = (0..16).map(|_| b.next_u32()).collect();
+        let zs: Vec<u32> = (0..16).map(|_| c.next_u32()).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(xs, zs);
+
+        let mut p = Sampler::for_pixel(7, 3, 4, 10);
+        let mut q = Sampler::for_pixel(7, 4, 3, 10);
+        assert_ne!(p.next_u64(), q.next_u64());
+    }
+
+    #[test]
+    fn sampler_ranges() {
+        let mut sampler = Sampler::new(1);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let x = sampler.next_f32();
+            assert!((0.0..1.0).contains(&x));
+            sum += x;
+
+            let y = sampler.range_f32(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&y));
+
+            let p = Vec3::random_in_unit_disk(&mut sampler);
+            assert!(p.length_squared() < 1.0 && p.z == 0.0);
+        }
+        assert!((sum / 10000.0 - 0.5).abs() < 0.02);
+    }
+
+    /**
+     *  Integrates a density over all directions by uniform sampling, which
+     *  has to give one.
+     */
+    fn integrate_pdf<F: Fn(Vec3) -> f32>(pdf: F, sampler: &mut Sampler) -> f32 {
+        let n = 200000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += pdf(Vec3::random_unit_vector(sampler));
+        }
+        sum / n as f32 * 4.0 * std::f32::consts::PI
+    }
+
+    /**
+     *  Checks that material samples directions the way it evaluates them:
+     *  its pdf integrates to about one (at least min_total, less where
+     *  rough lobes reach below the surface), each sample's weight is eval
+     *  over pdf, and the reflected light estimated by sampling matches the
+     *  one from evaluating all directions. Returns the fraction of samples
+     *  going through the surface.
+     */
+    fn check_bsdf_consistency(material: &Material, r: &Ray, hit: &Intersection, min_total: f32, sampler: &mut Sampler) -> f32 {
+        let total = integrate_pdf(|d| material.pdf(r, hit, d), sampler);
+        assert!(total > min_total && total < 1.03, "{}", total);
+
+        let n = 200000;
+        let (mut sampled, mut transmitted) = (Vec3::zero(), 0);
+        for _ in 0..n {
+            let (scatter, attenuation, scattered) = material.scatter(r, hit, sampler);
+            if scatter {
+                sampled += attenuation;
+                transmitted += (scattered.direction.dot(hit.normal) < 0.0) as usize;
+                let pdf = material.pdf(r, hit, scattered.direction);
+                let expected = material.eval(r, hit, scattered.direction) / pdf;
+                assert!((expected - attenuation).length() < 1e-3 * (1.0 + attenuation.length()), "{:?} {:?}", expected, attenuation);
+            }
+        }
+        sampled /= n as f32;
+
+        let mut evaluated = Vec3::zero();
+        for _ in 0..n {
+            evaluated += material.eval(r, hit, Vec3::random_unit_vector(sampler));
+        }
+        evaluated *= 4.0 * std::f32::consts::PI / n as f32;
+        assert!((sampled - evaluated).length() < 0.03, "{:?} {:?}", sampled, evaluated);
+        transmitted as f32 / n as f32
+    }
+
+    #[test]
+    fn light_sampling_pdfs() {
+        let mut sampler = Sampler::new(3);
+        let sphere = Sphere::new(Vec3::new(0.0, 2.0, 0.0), 0.5, Material::default());
+        let triangle = Triangle::new(
+            Vec3::new(-1.0, 3.0, -1.0), Vec3::new(1.0, 3.0, -1.0), Vec3::new(0.0, 3.0, 1.0),
+            Material::default());
+
+        for object in [&sphere as &dyn Intersectable, &triangle] {
+            assert!((integrate_pdf(|d| object.pdf_value(Vec3::zero(), d, 0.0), &mut sampler) - 1.0).abs() < 0.05);
+
+            // Sampled directions always point at the object.
+            for _ in 0..1000 {
+                let direction = object.random_direction(Vec3::zero(), 0.0, &mut sampler);
+                assert!(object.pdf_value(Vec3::zero(), direction, 0.0) > 0.0);
+            }
+        }
+
+        // Inside a sphere every direction is equally likely.
+        assert!((integrate_pdf(|d| sphere.pdf_value(sphere.center, d, 0.0), &mut sampler) - 1.0).abs() < 0.01);
+
+        let mut lights = LightList::new();
+        lights.add(sphere);
+        lights.add(triangle);
+        assert!((integrate_pdf(|d| lights.pdf_value(Vec3::zero(), d, 0.0), &mut sampler) - 1.0).abs() < 0.05);
+        assert_eq!(lights.pdf_value(Vec3::zero(), Vec3::new(0.0, -1.0, 0.0), 0.0), 0.0);
+
+        // Far away spheres cover a tiny but non-zero solid angle.
+        let far = Sphere::new(Vec3::new(0.0, 0.0, -5000.0), 0.2, Material::default());
+        let pdf = far.pdf_value(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(pdf.is_finite() && pdf > 0.0);
+    }
+
+    #[test]
+    fn light_sampling_converges_to_same_image() {
+        // A diffuse floor under a small, bright sphere light.
+        let floor = Material::new(Vec3::new(0.5, 0.5, 0.5), 0.0, 0.0, MaterialType::Diffuse);
+        let lamp = Sphere::new(Vec3::new(0.0, 2.0, 0.0), 0.25, Material::emissive(Vec3::new(1.0, 1.0, 1.0), 20.0));
+
+        let mut world = HittableList::new();
+        world.add(Triangle::new(Vec3::new(-50.0, 0.0, -50.0), Vec3::new(50.0, 0.0, 50.0), Vec3::new(50.0, 0.0, -50.0), floor.clone()));
+        world.add(Triangle::new(Vec3::new(-50.0, 0.0, -50.0), Vec3::new(-50.0, 0.0, 50.0), Vec3::new(50.0, 0.0, 50.0), floor));
+        world.add(lamp.clone());
+
+        let mut lights = LightList::new();
+        lights.add(lamp);
+
+        let environment = Environment::Constant(Vec3::zero());
+        let r = || Ray::new(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, -1.0, -3.0));
+        let estimate = |lights: &LightList, n: usize| {
+            let mut sampler = Sampler::new(11);
+            let mut sum = Vec3::zero();
+            for _ in 0..n {
+                sum += ray_color(r(), &world, lights, &environment, None, 3, &mut sampler);
+            }
+            sum / n as f32
+        };
+
+        let with_lights = estimate(&lights, 20000);
+        let without_lights = estimate(&LightList::new(), 400000);
+        assert!(with_lights.x > 0.0);
+        assert!((with_lights.x - without_lights.x).abs() < 0.05 * without_lights.x,
+            "{:?} vs {:?}", with_lights, without_lights);
+    }
+
+    #[test]
+    fn punctual_light_samples() {
+        let mut sampler = Sampler::new(5);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+
+        let point = PunctualLight::point(Vec3::new(0.0, 2.0, 0.0), white, 8.0);
+        let sample = point.sample(Vec3::zero(), &mut sampler).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, 2.0);
+        assert_eq!(sample.light, Vec3::new(2.0, 2.0, 2.0));
+
+        // Full strength inside the inner cone, fading out to nothing at the
+        // outer one.
+        let spot = PunctualLight::spot(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), white, 1.0, 30.0, 45.0);
+        let at = |x: f32, sampler: &mut Sampler| spot.sample(Vec3::new(x, 0.0, 0.0), sampler).map(|s| s.light.x);
+        assert_eq!(at(0.0, &mut sampler), Some(1.0));
+        let half_way = at(f32::tan(37.5f32.to_radians()), &mut sampler).unwrap();
+        assert!(half_way > 0.0 && half_way < 0.5);
+        assert_eq!(at(2.0, &mut sampler), None);
+        // Points right on a light get nothing instead of NaNs.
+        assert!(spot.sample(Vec3::new(0.0, 1.0, 0.0), &mut sampler).is_none());
+        assert!(point.sample(Vec3::new(0.0, 2.0, 0.0), &mut sampler).is_none());
+
+        let sharp = PunctualLight::directional(Vec3::new(0.0, -1.0, 0.0), white, 3.0, 0.0);
+        let sample = sharp.sample(Vec3::zero(), &mut sampler).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, f32::INFINITY);
+        assert_eq!(sample.light, Vec3::new(3.0, 3.0, 3.0));
+
+        let soft = PunctualLight::directional(Vec3::new(0.0, -1.0, 0.0), white, 3.0, 10.0);
+        for _ in 0..100 {
+            let sample = soft.sample(Vec3::zero(), &mut sampler).unwrap();
+            assert!(sample.direction.y >= f32::cos(5f32.to_radians()) - 1e-6);
+        }
+    }
+
+    #[test]
+    fn punctual_lights_cast_shadows() {
+        let floor = Material::new(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.0, MaterialType::Diffuse);
+        let mut world = HittableList::new();
+        world.add(Triangle::new(Vec3::new(-10.0, 0.0, -10.0), Vec3::new(10.0, 0.0, 10.0), Vec3::new(10.0, 0.0, -10.0), floor.clone()));
+        world.add(Triangle::new(Vec3::new(-10.0, 0.0, -10.0), Vec3::new(-10.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 10.0), floor.clone()));
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, floor.clone()));
+
+        let mut lights = LightList::new();
+        lights.add_punctual(PunctualLight::point(Vec3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 4.0));
+
+        // Only direct light: the floor at (3, 0, 0) is 13^0.5 away from the
+        // light, at a cosine of 2 / 13^0.5, with a BRDF of 1 / pi.
+        let environment = Environment::Constant(Vec3::zero());
+        let mut sampler = Sampler::new(0);
+        let lit = Ray::new(Vec3::new(3.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let expected = 4.0 / 13.0 * (2.0 / 13f32.sqrt()) / std::f32::consts::PI;
+        let color = ray_color(lit, &world, &lights, &environment, None, 2, &mut sampler);
+        assert!((color.x - expected).abs() < 1e-4, "{} vs {}", color.x, expected);
+
+        let shadowed = Ray::new(Vec3::new(0.0, 0.4, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(ray_color(shadowed, &world, &lights, &environment, None, 2, &mut sampler), Vec3::zero());
+    }
+
+    #[test]
+    fn scene_punctual_lights() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes").join("punctual_lights.scene");
+        let scene = load_scene(&path).unwrap();
+        assert_eq!(scene.lights.len(), 3);
+        assert!(!scene.lights.has_shapes());
+
+        let source = "point_light position=1,2,3 color=1,0,0 intensity=2";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        assert_eq!(scene.lights.punctual(), &[PunctualLight::point(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 0.0, 0.0), 2.0)]);
+
+        let bad = [
+            "point_light color=1,1,1",
+            "spot_light position=0,0,0 direction=0,-1,0 outer=120",
+            "spot_light position=0,0,0 direction=0,0,0",
+            "directional_light direction=0,0,0",
+            "directional_light direction=0,-1,0 angle=-1",
+            "point_light position=0,0,0 radius=2",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn hdr_read_back() {
+        for width in [3, 20] {
+            let pixels: Vec<Vec3> = (0..width * 2)
+                .map(|i| Vec3::new(i as f32 * 0.75, 1.0, if i % 7 == 0 { 100.0 } else { 0.0 }))
+                .collect();
+            let mut bytes: Vec<u8> = vec![];
+            write_hdr(&mut bytes, width, 2, &pixels).unwrap();
+
+            let image = read_hdr(&bytes[..]).unwrap();
+            assert_eq!((image.width, image.height), (width, 2));
+            for (read, written) in image.pixels.iter().zip(&pixels) {
+                assert!((read.x - written.x).abs() <= 0.01 * written.length());
+                assert!((read.z - written.z).abs() <= 0.01 * written.length());
+            }
+        }
+
+        assert_eq!(from_rgbe([0, 0, 0, 0]), Vec3::zero());
+        assert!(read_hdr(&b"P6\n1 1\n255\n"[..]).is_err());
+        assert!(read_hdr(&b"#?RADIANCE\n\n+Y 1 +X 1\n\0\0\0\0"[..]).is_err());
+        assert!(read_hdr(&b"#?RADIANCE\n\n-Y 2 +X 1\n\0\0\0\0"[..]).is_err());
+        for resolution in ["-Y 100000 +X 100000", "-Y 18446744073709551615 +X 2", "-Y 0 +X 18446744073709551615"] {
+            let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution);
+            let error = read_hdr(header.as_bytes()).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{}", resolution);
+        }
+    }
+
+    /**
+     *  Equirectangular image that is white in the upper half with a much
+     *  brighter spot, and black below the horizon.
+     */
+    fn test_environment_image() -> HdrImage {
+        let (width, height) = (64, 32);
+        let mut pixels = vec![Vec3::zero(); width * height];
+        for y in 0..height / 2 {
+            for x in 0..width {
+                pixels[y * width + x] = Vec3::new(1.0, 1.0, 1.0);
+            }
+        }
+        pixels[5 * width + 40] = Vec3::new(5000.0, 5000.0, 5000.0);
+        HdrImage { width, height, pixels }
+    }
+
+    #[test]
+    fn environment_map_sampling() {
+        let map = EnvironmentMap::new(test_environment_image(), 1.0, 30.0);
+        let mut sampler = Sampler::new(9);
+
+        assert!((integrate_pdf(|d| map.pdf(d), &mut sampler) - 1.0).abs() < 0.05);
+        assert_eq!(map.radiance(Vec3::new(0.0, 1.0, 0.0)), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(map.radiance(Vec3::new(0.3, -1.0, 0.0)), Vec3::zero());
+
+        // Samples agree with pdf(), never point at black pixels, and mostly
+        // at the bright one.
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let (direction, pdf) = map.sample(&mut sampler).unwrap();
+            assert!((pdf - map.pdf(direction)).abs() <= 1e-3 * pdf);
+            let radiance = map.radiance(direction);
+            assert!(radiance.x >= 1.0);
+            if radiance.x > 1.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 500, "{}", bright);
+    }
+
+    #[test]
+    fn environment_map_lighting() {
+        // A white environment above a diffuse floor reflects albedo times
+        // its radiance, with or without the bright spot, which lights the
+        // floor by a known amount on top.
+        let floor = Material::new(Vec3::new(0.5, 0.5, 0.5), 0.0, 0.0, MaterialType::Diffuse);
+        let mut world = HittableList::new();
+        world.add(Triangle::new(Vec3::new(-1e4, 0.0, -1e4), Vec3::new(1e4, 0.0, 1e4), Vec3::new(1e4, 0.0, -1e4), floor.clone()));
+        world.add(Triangle::new(Vec3::new(-1e4, 0.0, -1e4), Vec3::new(-1e4, 0.0, 1e4), Vec3::new(1e4, 0.0, 1e4), floor));
+
+        let mut image = test_environment_image();
+        image.pixels[5 * 64 + 40] = Vec3::new(1.0, 1.0, 1.0);
+        let environment = Environment::Map(EnvironmentMap::new(image, 2.0, 0.0));
+
+        let mut sampler = Sampler::new(4);
+        let lights = LightList::new();
+        let r = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let n = 2000;
+        let mut sum = Vec3::zero();
+        for _ in 0..n {
+            sum += ray_color(Ray::new(r.origin, r.direction), &world, &lights, &environment, None, 2, &mut sampler);
+        }
+        let mean = sum / n as f32;
+        assert!((mean.x - 1.0).abs() < 0.02, "{}", mean.x);
+    }
+
+    #[test]
+    fn preetham_sky() {
+        let sun = Vec3::new(1.0, 1.0, 0.0);
+        let sky = PreethamSky::new(sun, 3.0, 1.0);
+
+        let zenith = sky.radiance(Vec3::new(0.0, 1.0, 0.0));
+        let near_sun = sky.radiance(Vec3::new(1.0, 1.1, 0.0));
+        let away = sky.radiance(Vec3::new(-1.0, 1.0, 0.0));
+        assert!(zenith.x > 0.0 && zenith.x.is_finite());
+        assert!(near_sun.y > zenith.y && near_sun.y > away.y);
+        // Blue sky away from the sun.
+        assert!(away.z > away.x);
+
+        // Below the horizon nothing breaks down.
+        let below = sky.radiance(Vec3::new(0.0, -1.0, 1.0));
+        assert!(below.x.is_finite() && below.x >= 0.0);
+        let low_sun = PreethamSky::new(Vec3::new(1.0, -0.2, 0.0), 3.0, 1.0);
+        assert!(low_sun.radiance(Vec3::new(0.0, 1.0, 0.0)).x.is_finite());
+    }
+
+    #[test]
+    fn scene_environments() {
+        let path = std::env::temp_dir().join(format!("raytracer_env_{}.hdr", std::process::id()));
+        let image = test_environment_image();
+        save_hdr(&path, image.width, image.height, &image.pixels).unwrap();
+
+        let dir = path.parent().unwrap();
+        let file = path.file_name().unwrap().to_str().unwrap();
+        let source = format!("environment map file={} intensity=2 rotation=90", file);
+        let scene = parse_scene(&source, Path::new("test.scene"), dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(scene.environment.is_sampled());
+        assert_eq!(scene.environment.radiance(Vec3::new(0.0, 1.0, 0.0)).y, 2.0 * from_rgbe(to_rgbe(Vec3::new(1.0, 1.0, 1.0))).y);
+
+        let sources = [
+            ("environment gradient zenith=0,0,1", Environment::Gradient { horizon: Vec3::new(1.0, 1.0, 1.0), zenith: Vec3::new(0.0, 0.0, 1.0) }),
+            ("environment constant color=1,2,3", Environment::Constant(Vec3::new(1.0, 2.0, 3.0))),
+            ("environment sky sun=0,1,1 turbidity=4", Environment::Sky(PreethamSky::new(Vec3::new(0.0, 1.0, 1.0), 4.0, 1.0))),
+        ];
+        for (source, expected) in sources {
+            let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+            assert_eq!(scene.environment, expected);
+        }
+
+        let bad = [
+            "environment",
+            "environment night",
+            "environment map",
+            "environment map file=does_not_exist.hdr",
+            "environment sky turbidity=50",
+            "environment constant horizon=1,1,1",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn texture_checker() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let solid_checker = Checker { even: solid(white), odd: solid(Vec3::zero()), scale: 2.0, space: CheckerSpace::Solid };
+        assert_eq!(solid_checker.value(0.0, 0.0, Vec3::new(0.25, 0.25, 0.25)), white);
+        assert_eq!(solid_checker.value(0.0, 0.0, Vec3::new(0.75, 0.25, 0.25)), Vec3::zero());
+        assert_eq!(solid_checker.value(0.0, 0.0, Vec3::new(-0.25, 0.25, 0.25)), Vec3::zero());
+        assert_eq!(solid_checker.constant(), None);
+
+        let uv_checker = Checker { space: CheckerSpace::Uv, ..solid_checker };
+        assert_eq!(uv_checker.value(0.1, 0.1, Vec3::new(0.75, 0.0, 0.0)), white);
+        assert_eq!(uv_checker.value(0.6, 0.1, Vec3::zero()), Vec3::zero());
+        assert_eq!(uv_checker.value(0.6, 0.6, Vec3::zero()), white);
+    }
+
+    #[test]
+    fn texture_noise() {
+        let mut sampler = Sampler::new(5);
+        for kind in [NoiseKind::Noise, NoiseKind::Turbulence, NoiseKind::Marble] {
+            let texture = NoiseTexture::new(kind, Vec3::new(1.0, 1.0, 1.0), 4.0, 1);
+            let same = NoiseTexture::new(kind, Vec3::new(1.0, 1.0, 1.0), 4.0, 1);
+            let mut values = vec![];
+            for _ in 0..1000 {
+                let p = Vec3::random_range(&mut sampler, -10.0, 10.0);
+                let value = texture.value(0.0, 0.0, p);
+                assert!((0.0..=1.5).contains(&value.x), "{:?} {}", kind, value.x);
+                assert_eq!(value, same.value(0.0, 0.0, p));
+                values.push(value.x);
+            }
+            let min = values.iter().cloned().fold(f32::MAX, f32::min);
+            let max = values.iter().cloned().fold(f32::MIN, f32::max);
+            assert!(max - min > 0.2, "{:?} is flat", kind);
+        }
+
+        // Noise is continuous.
+        let texture = NoiseTexture::new(NoiseKind::Noise, Vec3::new(1.0, 1.0, 1.0), 1.0, 1);
+        let p = Vec3::new(1.3, 2.7, -0.4);
+        let d = texture.value(0.0, 0.0, p) - texture.value(0.0, 0.0, p + Vec3::new(1e-3, 0.0, 0.0));
+        assert!(d.x.abs() < 0.01);
+    }
+
+    #[test]
+    fn texture_image_filtering() {
+        // Black on the left, white on the right, bottom row red.
+        let mut image = Image::new(2, 2);
+        image.set_pixel(1, 0, IVec3::new(255, 255, 255));
+        image.set_pixel(0, 1, IVec3::new(255, 0, 0));
+        image.set_pixel(1, 1, IVec3::new(255, 0, 0));
+
+        let texture = ImageTexture::new(&image, WrapMode::Clamp, false);
+        assert_eq!(texture.value(0.25, 0.75, Vec3::zero()), Vec3::zero());
+        assert_eq!(texture.value(0.75, 0.75, Vec3::zero()), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(texture.value(0.25, 0.25, Vec3::zero()), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.value(0.5, 0.75, Vec3::zero()), Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(texture.value(-3.0, 0.75, Vec3::zero()), Vec3::zero());
+
+        let repeat = ImageTexture::new(&image, WrapMode::Repeat, false);
+        assert_eq!(repeat.value(1.25, 0.75, Vec3::zero()), Vec3::zero());
+        assert_eq!(repeat.value(1.0, 0.75, Vec3::zero()), Vec3::new(0.5, 0.5, 0.5));
+
+        let mirror = ImageTexture::new(&image, WrapMode::Mirror, false);
+        assert_eq!(mirror.value(1.25, 0.75, Vec3::zero()), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(mirror.value(1.0, 0.75, Vec3::zero()), Vec3::new(1.0, 1.0, 1.0));
+
+        // sRGB middle gray is darker in linear space.
+        image.set_pixel(0, 0, IVec3::new(128, 128, 128));
+        let srgb = ImageTexture::new(&image, WrapMode::Clamp, true);
+        let gray = srgb.value(0.25, 0.75, Vec3::zero()).x;
+        assert!((gray - 0.2158).abs() < 1e-3, "{}", gray);
+    }
+
+    #[test]
+    fn image_load_png() {
+        let image = test_image();
+        let path = std::env::temp_dir().join(format!("raytracer_load_{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let loaded = Image::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!(loaded.as_bytes(), image.as_bytes());
+
+        assert!(Image::load(Path::new("texture.bmp")).is_err());
+        assert!(Image::load(Path::new("does_not_exist.png")).is_err());
+    }
+
+    #[test]
+    fn sphere_uv() {
+        let material = Material::new(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.0, MaterialType::Diffuse);
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 2.0, material);
+        let uv = |origin: Vec3, direction: Vec3| {
+            let hit = sphere.hit(&Ray::new(origin, direction), 0.001, f32::MAX).unwrap();
+            (hit.u, hit.v)
+        };
+
+        let (u, v) = uv(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        assert!((u - 0.25).abs() < 1e-5 && (v - 0.5).abs() < 1e-5, "{} {}", u, v);
+        let (u, v) = uv(Vec3::new(-10.0, 0.0, -5.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(u.abs() < 1e-5 && (v - 0.5).abs() < 1e-5, "{} {}", u, v);
+        let (_, v) = uv(Vec3::new(0.0, 10.0, -5.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((v - 1.0).abs() < 1e-5);
+        let (_, v) = uv(Vec3::new(0.0, -10.0, -5.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(v.abs() < 1e-5);
+    }
+
+    #[test]
+    fn scene_textures() {
+        let path = std::env::temp_dir().join(format!("raytracer_texture_{}.png", std::process::id()));
+        test_image().save(&path).unwrap();
+
+        let source = format!("
+            texture tiles uv_checker even=1,1,1 odd=0 scale=2
+            texture photo image file={} wrap=mirror colorspace=linear
+            texture clouds turbulence color=0.5,0.5,1 scale=3 seed=7
+            material ground diffuse albedo=tiles roughness=0.5
+            material wall diffuse albedo=photo
+            material glow emissive color=clouds intensity=4
+            sphere center=0,0,-2 radius=1 material=ground
+        ", path.file_name().unwrap().to_str().unwrap());
+        let scene = parse_scene(&source, Path::new("test.scene"), path.parent().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let hit = scene.world.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.albedo.value(0.1, 0.1, hit.point), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(hit.material.albedo.value(0.6, 0.1, hit.point), Vec3::zero());
+        assert_eq!(hit.material.roughness_at(&hit), 0.5);
+
+        let bad = [
+            "texture",
+            "texture t",
+            "texture t wood",
+            "texture t image",
+            "texture t image file=does_not_exist.png",
+            "texture t noise seed=-1",
+            "texture t solid color=1,1,1\ntexture u image file=t.png wrap=around",
+            "material m diffuse albedo=missing",
+            "material m emissive color=missing",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn sphere_tangents() {
+        let material = Material::default();
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, material);
+        let mut sampler = Sampler::new(9);
+        for _ in 0..100 {
+            let target = sphere.center + sphere.radius * Vec3::random_unit_vector(&mut sampler);
+            let origin = target + 5.0 * (target - sphere.center);
+            let hit = sphere.hit(&Ray::new(origin, target - origin), 0.001, f32::MAX).unwrap();
+            assert!(hit.dpdu.dot(hit.normal).abs() < 1e-3);
+            assert!(hit.dpdv.dot(hit.normal).abs() < 1e-3);
+
+            // Walking along dpdu a little changes u by that much.
+            let step = 1e-3;
+            let p = hit.point + step * hit.dpdu;
+            let d = (p - sphere.center).normalized();
+            let next = sphere.hit(&Ray::new(sphere.center + 10.0 * d, -d), 0.001, f32::MAX).unwrap();
+            if (next.u - hit.u).abs() < 0.5 {
+                assert!((next.u - hit.u - step).abs() < 1e-4, "{} {}", next.u, hit.u);
+                assert!((next.v - hit.v).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_tangents() {
+        let material = Material::default();
+        // u runs along y, v along -x.
+        let triangle = Triangle::new(Vec3::zero(), Vec3::new(0.0, 2.0, 0.0), Vec3::new(-2.0, 0.0, 0.0), material)
+            .with_uvs([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        let hit = triangle.hit(&Ray::new(Vec3::new(-0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.dpdu, Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(hit.dpdv, Vec3::new(-2.0, 0.0, 0.0));
+
+        // Without a parametrization there is no frame.
+        let flat = Triangle::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Material::default())
+            .with_uvs([(0.5, 0.5); 3]);
+        let hit = flat.hit(&Ray::new(Vec3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.dpdu, Vec3::zero());
+    }
+
+    // Height rising along u.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f32, _v: f32, _point: Vec3) -> Vec3 {
+            Vec3::new(u, u, u)
+        }
+    }
+
+    fn detail_normal(detail: Option<SurfaceDetail>) -> Vec3 {
+        let material = Material { detail, ..Material::default() };
+        let mut hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &material);
+        hit.front_face = true;
+        hit.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        hit.dpdv = Vec3::new(0.0, 1.0, 0.0);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        material.apply_detail(&r, &mut hit);
+        hit.normal
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn normal_and_bump_maps() {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(detail_normal(None), up);
+
+        let flat = solid(Vec3::new(0.5, 0.5, 1.0));
+        assert_eq!(detail_normal(Some(SurfaceDetail::NormalMap(flat))), up);
+        let tilted = solid(Vec3::new(0.75, 0.5, 1.0));
+        assert_near(detail_normal(Some(SurfaceDetail::NormalMap(tilted))), Vec3::new(0.5, 0.0, 1.0).normalized());
+        let towards_v = solid(Vec3::new(0.5, 0.0, 1.0));
+        assert_near(detail_normal(Some(SurfaceDetail::NormalMap(towards_v))), Vec3::new(0.0, -1.0, 1.0).normalized());
+
+        // Pointing below the surface falls back to the geometric normal.
+        let below = solid(Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(detail_normal(Some(SurfaceDetail::NormalMap(below))), up);
+
+        let constant = solid(Vec3::new(0.3, 0.3, 0.3));
+        assert_near(detail_normal(Some(SurfaceDetail::BumpMap { height: constant, scale: 1.0 })), up);
+        let ramp = std::sync::Arc::new(Ramp);
+        assert_near(detail_normal(Some(SurfaceDetail::BumpMap { height: ramp, scale: 0.5 })), Vec3::new(-0.5, 0.0, 1.0).normalized());
+    }
+
+    #[test]
+    fn scene_surface_detail() {
+        let source = "
+            texture n solid color=0.5,0.5,1
+            texture h turbulence scale=4
+            material a diffuse normal_map=n
+            material b metal bump=h bump_scale=0.1
+            sphere center=0,0,-2 radius=1 material=b
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert!(matches!(hit.material.detail, Some(SurfaceDetail::BumpMap { scale, .. }) if scale == 0.1));
+
+        let bad = [
+            "material m diffuse normal_map=missing",
+            "material m diffuse bump=0.5 normal_map=0.5,0.5,1",
+            "material m diffuse bump_scale=2",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn mtl_texture_maps() {
+        let path = std::env::temp_dir().join(format!("raytracer_maps_{}.png", std::process::id()));
+        test_image().save(&path).unwrap();
+        let file = path.file_name().unwrap().to_str().unwrap();
+        let mtl = path.with_extension("mtl");
+
+        let source = format!("newmtl a\nmap_Kd {0}\nnorm {0}\nnewmtl b\nmap_Bump -bm 0.25 {0}\n", file);
+        let materials = parse_mtl(&source, &mtl).unwrap();
+        let red = materials["a"].albedo.value(1.0 / 6.0, 0.75, Vec3::zero());
+        assert!(red.x > 0.99 && red.y < 0.01, "{:?}", red);
+        assert!(matches!(materials["a"].detail, Some(SurfaceDetail::NormalMap(_))));
+        assert!(matches!(materials["b"].detail, Some(SurfaceDetail::BumpMap { scale, .. }) if scale == 0.25));
+
+        let bad = [format!("newmtl a\nmap_Kd -clamp on {}", file), "newmtl a\nnorm".to_string()];
+        for source in &bad {
+            assert!(matches!(parse_mtl(source, &mtl), Err(ObjError::Parse { .. })), "{}", source);
+        }
+        assert!(matches!(parse_mtl("newmtl a\nmap_Kd missing.png", &mtl), Err(ObjError::Texture { .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mtl_roughness() {
+        let source = "
+            newmtl glass
+            Ns 96
+            d 0.5
+            newmtl frosted
+            d 0.5
+            Pr 0.3
+            Ns 10
+            newmtl metal
+            Ns 98
+            illum 3
+        ";
+        let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+        // Ns is about highlights, glass only gets rough by Pr.
+        assert!(matches!(materials["glass"].material_type, MaterialType::Dielectric));
+        assert_eq!(materials["glass"].roughness.constant(), Some(Vec3::zero()));
+        assert_eq!(materials["frosted"].roughness.constant(), Some(Vec3::new(0.3, 0.3, 0.3)));
+        let metal = materials["metal"].roughness.constant().unwrap();
+        assert!((metal.x - f32::sqrt(0.02)).abs() < 1e-6, "{:?}", metal);
+    }
+
+    #[test]
+    fn ggx_distribution() {
+        let mut sampler = Sampler::new(21);
+        for ggx in [Ggx::new(0.3, 0.3), Ggx::new(0.2, 0.6), Ggx::from_roughness(0.7, -0.5)] {
+            // Projected microfacet area adds up to the macro surface.
+            let area = integrate_pdf(|h| ggx.d(h) * f32::max(h.z, 0.0), &mut sampler);
+            assert!((area - 1.0).abs() < 0.03, "{:?} {}", ggx, area);
+
+            for wo in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.6, 0.3, 0.5).normalized()] {
+                let total = integrate_pdf(|h| ggx.pdf_visible(wo, h), &mut sampler);
+                assert!((total - 1.0).abs() < 0.03, "{:?} {}", ggx, total);
+
+                for _ in 0..100 {
+                    let h = ggx.sample_visible(wo, sampler.next_f32(), sampler.next_f32());
+                    assert!(h.z > 0.0 && wo.dot(h) >= -1e-4 && (h.length() - 1.0).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel() {
+        for conductor in [Conductor::GOLD, Conductor::COPPER, Conductor::ALUMINIUM, Conductor::SILVER] {
+            let (eta, k) = (conductor.eta.x, conductor.k.x);
+            let normal = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+            assert!((conductor.fresnel(1.0).x - normal).abs() < 1e-4);
+            assert!(conductor.fresnel(1e-4).y > 0.99);
+        }
+        // Gold reflects more red than blue.
+        let gold = Conductor::GOLD.fresnel(1.0);
+        assert!(gold.x > 0.9 && gold.z < 0.5, "{:?}", gold);
+        assert_eq!(Conductor::from_name("copper"), Some(Conductor::COPPER));
+        assert_eq!(Conductor::from_name("brass"), None);
+    }
+
+    #[test]
+    fn metal_sampling_matches_evaluation() {
+        let mut sampler = Sampler::new(4);
+        let mut metal = Material::new(Vec3::new(0.9, 0.6, 0.3), 0.5, 0.0, MaterialType::Metal);
+        for (conductor, anisotropy) in [(None, 0.0), (Some(Conductor::GOLD), 0.7)] {
+            metal.conductor = conductor;
+            metal.anisotropy = anisotropy;
+            let mut hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &metal);
+            hit.front_face = true;
+            hit.dpdu = Vec3::new(1.0, 0.0, 0.0);
+            hit.dpdv = Vec3::new(0.0, 1.0, 0.0);
+            let r = Ray::new(Vec3::new(-1.0, 0.3, 1.0), Vec3::new(1.0, -0.3, -1.0));
+            assert!(!metal.is_specular(&hit));
+
+            check_bsdf_consistency(&metal, &r, &hit, 0.8, &mut sampler);
+        }
+
+        // Smooth metals are mirrors tinted by the Fresnel term.
+        let mirror = Material::new(Vec3::new(0.8, 0.8, 0.8), 0.0, 0.0, MaterialType::Metal);
+        let hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &mirror);
+        assert!(mirror.is_specular(&hit));
+        let (scatter, attenuation, scattered) = mirror.scatter(&Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0)), &hit, &mut sampler);
+        assert!(scatter);
+        assert_near(attenuation, Vec3::new(0.8, 0.8, 0.8));
+        assert_near(scattered.direction, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn scene_metals() {
+        let source = "
+            material a metal conductor=gold roughness=0.4 anisotropy=-0.5
+            material b metal eta=1,1,1 k=2,2,2
+            sphere center=0,0,-2 radius=1 material=a
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.conductor, Some(Conductor::GOLD));
+        assert_eq!(hit.material.anisotropy, -0.5);
+
+        let bad = [
+            "material m metal conductor=brass",
+            "material m metal conductor=gold eta=1,1,1 k=1,1,1",
+            "material m metal eta=1,1,1",
+            "material m metal anisotropy=2",
+            "material m diffuse conductor=gold",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert!(fresnel_dielectric(0.3, 1.0) < 1e-6);
+        // Past the critical angle from inside.
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric(1e-3, 1.5) > 0.99);
+    }
+
+    #[test]
+    fn rough_dielectric_sampling_matches_evaluation() {
+        let mut sampler = Sampler::new(8);
+        let mut glass = Material::new(Vec3::new(0.9, 0.5, 0.2), 0.6, 1.5, MaterialType::Dielectric);
+        for (thin, front_face) in [(false, true), (false, false), (true, true)] {
+            glass.thin = thin;
+            let mut hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &glass);
+            hit.front_face = front_face;
+            let r = Ray::new(Vec3::new(-0.4, 0.2, 1.0), Vec3::new(0.4, -0.2, -1.0));
+            assert!(!glass.is_specular(&hit));
+
+            assert!(check_bsdf_consistency(&glass, &r, &hit, 0.85, &mut sampler) > 0.5, "{} {}", thin, front_face);
+        }
+    }
+
+    #[test]
+    fn smooth_and_thin_dielectrics() {
+        let mut sampler = Sampler::new(12);
+        let mut glass = Material::new(Vec3::new(0.5, 1.0, 1.0), 0.0, 1.5, MaterialType::Dielectric);
+        let mut hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &glass);
+        hit.front_face = true;
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(glass.is_specular(&hit));
+
+        let n = 100000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let (scatter, attenuation, scattered) = glass.scatter(&r, &hit, &mut sampler);
+            assert!(scatter);
+            assert_eq!(attenuation, Vec3::new(1.0, 1.0, 1.0));
+            reflected += (scattered.direction.z > 0.0) as usize;
+        }
+        assert!((reflected as f32 / n as f32 - 0.04).abs() < 0.005);
+
+        // Thin sheets let light through unbent, tinted by albedo, and
+        // reflect a little more.
+        glass.thin = true;
+        let hit = Intersection { front_face: true, ..Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &glass) };
+        let r = Ray::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0));
+        let mut reflected = 0;
+        for _ in 0..n {
+            let (_, attenuation, scattered) = glass.scatter(&r, &hit, &mut sampler);
+            if scattered.direction.z > 0.0 {
+                reflected += 1;
+            } else {
+                assert_near(scattered.direction.normalized(), r.direction.normalized());
+                assert_eq!(attenuation, Vec3::new(0.5, 1.0, 1.0));
+            }
+        }
+        let f = fresnel_dielectric(f32::sqrt(0.5), 1.5);
+        assert!((reflected as f32 / n as f32 - 2.0 * f / (1.0 + f)).abs() < 0.005);
+    }
+
+    #[test]
+    fn glass_absorption() {
+        let mut glass = Material::new(Vec3::zero(), 0.0, 1.5, MaterialType::Dielectric);
+        glass.absorption = Vec3::new(0.5, 0.0, 1.0);
+        let hit = Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &glass);
+        assert_near(glass.transmittance(&hit, 2.0), Vec3::new((-1.0f32).exp(), 1.0, (-2.0f32).exp()));
+        let front = Intersection { front_face: true, ..Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &glass) };
+        assert_eq!(glass.transmittance(&front, 2.0), Vec3::new(1.0, 1.0, 1.0));
+
+        // Without refraction the light straight through the center of a
+        // sphere travels two radii inside it.
+        glass.ir = 1.0;
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, glass));
+        let lights = LightList::new();
+        let environment = Environment::Constant(Vec3::new(1.0, 1.0, 1.0));
+        let mut sampler = Sampler::new(1);
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let color = ray_color(r, &world, &lights, &environment, None, 10, &mut sampler);
+        assert_near(color, Vec3::new((-1.0f32).exp(), 1.0, (-2.0f32).exp()));
+    }
+
+    #[test]
+    fn scene_dielectrics() {
+        let source = "
+            material a dielectric ir=1.33 absorption=0.1,0.2,0.3 roughness=0.2
+            material b dielectric thin=true albedo=0.9,0.9,0.9
+            sphere center=0,0,-2 radius=1 material=a
+            sphere center=0,0,2 radius=1 material=b
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.absorption, Vec3::new(0.1, 0.2, 0.3));
+        assert_eq!(hit.material.ir, 1.33);
+        assert!(!hit.material.thin);
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).unwrap();
+        assert!(hit.material.thin);
+
+        let bad = [
+            "material m dielectric thin=yes",
+            "material m dielectric absorption=-1,0,0",
+            "material m dielectric ir=0",
+            "material m metal thin=true",
+            "material m diffuse absorption=1,1,1",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn principled_sampling_matches_evaluation() {
+        let mut sampler = Sampler::new(14);
+        let knobs = [
+            Principled::default(),
+            Principled { metallic: 1.0, ..Principled::default() },
+            Principled { clearcoat: 1.0, clearcoat_roughness: 0.4, sheen: 1.0, specular_tint: 0.5, ..Principled::default() },
+            Principled { metallic: 0.3, transmission: 0.7, ..Principled::default() },
+        ];
+        for principled in knobs {
+            let material = Material::principled(Vec3::new(0.8, 0.5, 0.3), 0.5, principled);
+            let hit = Intersection { front_face: true, ..Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &material) };
+            let r = Ray::new(Vec3::new(-0.4, 0.2, 1.0), Vec3::new(0.4, -0.2, -1.0));
+            assert!(!material.is_specular(&hit));
+
+            check_bsdf_consistency(&material, &r, &hit, 0.85, &mut sampler);
+        }
+    }
+
+    #[test]
+    fn principled_energy() {
+        // A white material lit from everywhere can't reflect more than it
+        // receives, whichever knobs are turned up.
+        let mut sampler = Sampler::new(15);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let knobs = [
+            Principled::default(),
+            Principled { metallic: 1.0, ..Principled::default() },
+            Principled { clearcoat: 1.0, clearcoat_roughness: 0.3, ..Principled::default() },
+            Principled { transmission: 1.0, ..Principled::default() },
+        ];
+        for principled in knobs {
+            let material = Material::principled(white, 0.3, principled);
+            let hit = Intersection { front_face: true, ..Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &material) };
+            let r = Ray::new(Vec3::new(0.0, -0.3, 1.0), Vec3::new(0.0, 0.3, -1.0));
+            let n = 100000;
+            let mut sum = Vec3::zero();
+            for _ in 0..n {
+                let (scatter, attenuation, _) = material.scatter(&r, &hit, &mut sampler);
+                if scatter {
+                    sum += attenuation;
+                }
+            }
+            let albedo = sum / n as f32;
+            assert!(albedo.x < 1.02 && albedo.x > 0.8, "{:?} {:?}", principled, albedo);
+        }
+
+        // Smooth metals and glass don't diffuse, away from the mirror
+        // direction they are black.
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        for principled in [Principled { metallic: 1.0, ..Principled::default() }, Principled { transmission: 1.0, ..Principled::default() }] {
+            let material = Material::principled(white, 0.05, principled);
+            let hit = Intersection { front_face: true, ..Intersection::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, &material) };
+            assert!(material.eval(&r, &hit, Vec3::new(1.0, 0.0, 1.0)).length() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn scene_principled() {
+        let source = "
+            texture glow solid color=1,0.5,0
+            material a principled base_color=0.2,0.4,0.6 metallic=0.5 clearcoat=1 sheen=0.3 transmission=0.2 roughness=0.3
+            material b principled emission=glow emission_strength=3
+            sphere center=0,0,-2 radius=1 material=a
+            sphere center=0,0,2 radius=1 material=b
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert!(matches!(hit.material.material_type, MaterialType::Principled));
+        assert_eq!(hit.material.albedo.constant(), Some(Vec3::new(0.2, 0.4, 0.6)));
+        assert_eq!(hit.material.principled.metallic, 0.5);
+        assert_eq!(hit.material.principled.clearcoat, 1.0);
+        assert_eq!(hit.material.principled.specular, 0.5);
+        assert_eq!(hit.material.ir, 1.5);
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.emitted(&hit), Vec3::new(3.0, 1.5, 0.0));
+        assert!(scene.lights.has_shapes());
+
+        let bad = [
+            "material m principled metallic=2",
+            "material m principled sheen=-0.1",
+            "material m principled thin=true",
+            "material m principled albedo=1,0,0 base_color=0,1,0",
+            "material m diffuse clearcoat=1",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn phase_functions() {
+        let mut sampler = Sampler::new(16);
+        let direction = Vec3::new(0.3, -1.0, 0.2);
+        for phase in [Phase::Isotropic, Phase::from_g(0.7), Phase::from_g(-0.4)] {
+            let total = integrate_pdf(|d| phase.eval(direction, d), &mut sampler);
+            assert!((total - 1.0).abs() < 0.05, "{:?} {}", phase, total);
+
+            // The mean cosine of the scattering angle is g.
+            let g = match phase {
+                Phase::Isotropic => 0.0,
+                Phase::HenyeyGreenstein(g) => g,
+            };
+            let n = 100000;
+            let mut mean = 0.0;
+            for _ in 0..n {
+                let scattered = phase.sample(direction, &mut sampler);
+                assert!((scattered.length() - 1.0).abs() < 1e-4);
+                mean += scattered.dot(direction.normalized());
+            }
+            assert!((mean / n as f32 - g).abs() < 0.01, "{:?} {}", phase, mean / n as f32);
+        }
+        assert_eq!(Phase::from_g(0.0), Phase::Isotropic);
+    }
+
+    #[test]
+    fn fog_transmittance() {
+        // Black fog only absorbs, the sky behind it is dimmed by
+        // Beer-Lambert over the distance the fog reaches.
+        let world = HittableList::new();
+        let lights = LightList::new();
+        let environment = Environment::Constant(Vec3::new(1.0, 1.0, 1.0));
+        let mut fog = Medium::new(0.5, Vec3::zero(), 0.0);
+        fog.distance = 2.0;
+        assert!((fog.transmittance(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), 2.0, &mut Sampler::new(0)) - (-1.0f32).exp()).abs() < 1e-6);
+
+        let mut sampler = Sampler::new(17);
+        let n = 100000;
+        let mut sum = Vec3::zero();
+        for _ in 0..n {
+            let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -3.0));
+            sum += ray_color(r, &world, &lights, &environment, Some(&fog), 4, &mut sampler);
+        }
+        assert!((sum.x / n as f32 - (-1.0f32).exp()).abs() < 0.01, "{}", sum.x / n as f32);
+
+        // Without a distance the fog only fills the space between objects.
+        fog.distance = 0.0;
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray_color(r, &world, &lights, &environment, Some(&fog), 4, &mut sampler), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn white_volume_furnace() {
+        // A white scattering volume in a white environment loses no light,
+        // whatever path it takes inside.
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::zero(), 1.0, Material::interface(Medium::new(3.0, Vec3::new(1.0, 1.0, 1.0), 0.5))));
+        let lights = LightList::new();
+        let environment = Environment::Constant(Vec3::new(1.0, 1.0, 1.0));
+
+        let mut sampler = Sampler::new(18);
+        let n = 20000;
+        let mut sum = Vec3::zero();
+        for _ in 0..n {
+            let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.1, -1.0));
+            sum += ray_color(r, &world, &lights, &environment, None, 200, &mut sampler);
+        }
+        assert!((sum.x / n as f32 - 1.0).abs() < 0.01, "{}", sum.x / n as f32);
+    }
+
+    #[test]
+    fn shadows_through_media() {
+        let floor = Material::new(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.0, MaterialType::Diffuse);
+        let mut world = HittableList::new();
+        world.add(Triangle::new(Vec3::new(-10.0, 0.0, -10.0), Vec3::new(10.0, 0.0, 10.0), Vec3::new(10.0, 0.0, -10.0), floor.clone()));
+        world.add(Triangle::new(Vec3::new(-10.0, 0.0, -10.0), Vec3::new(-10.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 10.0), floor));
+        let mut lights = LightList::new();
+        lights.add_punctual(PunctualLight::point(Vec3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 4.0));
+        let environment = Environment::Constant(Vec3::zero());
+        let mut sampler = Sampler::new(19);
+
+        // Straight below the light, seen from the side past the volume.
+        let r = || Ray::new(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, -1.0, -3.0));
+        let unblocked = ray_color(r(), &world, &lights, &environment, None, 2, &mut sampler);
+        assert!((unblocked.x - 1.0 / std::f32::consts::PI).abs() < 1e-4);
+
+        // The shadow ray crosses one unit of a black volume.
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, Material::interface(Medium::new(1.0, Vec3::zero(), 0.0))));
+        let color = ray_color(r(), &world, &lights, &environment, None, 2, &mut sampler);
+        assert!((color.x - unblocked.x * (-1.0f32).exp()).abs() < 1e-4, "{} vs {}", color.x, unblocked.x);
+    }
+
+    #[test]
+    fn scene_media() {
+        let source = "
+            material smoke volume density=2 albedo=0.5,0.5,0.5 g=0.3
+            material jade dielectric medium_density=4 medium_albedo=0.6,0.9,0.7
+            sphere center=0,0,-2 radius=1 material=smoke
+            sphere center=0,0,2 radius=1 material=jade
+            fog density=0.1 g=-0.2 distance=20
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new("")).unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert!(hit.material.is_interface());
+        assert_eq!(hit.material.medium, Some(Medium::new(2.0, Vec3::new(0.5, 0.5, 0.5), 0.3)));
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.material.medium.as_ref().unwrap().albedo, Vec3::new(0.6, 0.9, 0.7));
+        let fog = scene.fog.unwrap();
+        assert_eq!(fog.phase, Phase::HenyeyGreenstein(-0.2));
+        assert_eq!(fog.albedo, Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(fog.distance, 20.0);
+
+        let bad = [
+            "material m volume",
+            "material m volume density=-1",
+            "material m volume density=1 albedo=2,0,0",
+            "material m volume density=1 g=1",
+            "material m dielectric medium_albedo=1,1,1",
+            "material m metal medium_density=1",
+            "fog albedo=1,1,1",
+            "fog density=1 distance=-1",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new("")).is_err(), "{}", source);
+        }
+    }
+
+    /**
+     *  A 20x12x10 grid of a smooth bump in the box from (-1, 0, -1) to
+     *  (3, 2, 1), so it spans several bricks.
+     */
+    fn test_grid() -> DensityGrid {
+        let resolution = [20, 12, 10];
+        let mut values = vec![];
+        for z in 0..10 {
+            for y in 0..12 {
+                for x in 0..20 {
+                    let (x, y, z) = (x as f32 / 19.0, y as f32 / 11.0, z as f32 / 9.0);
+                    values.push(f32::max(0.0, 1.0 - 2.0 * ((x - 0.3).powi(2) + (y - 0.5).powi(2) + (z - 0.5).powi(2))));
+                }
+            }
+        }
+        DensityGrid::new(resolution, values).with_bounds(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(3.0, 2.0, 1.0))
+    }
+
+    #[test]
+    fn grid_lookup() {
+        let grid = DensityGrid::new([2, 1, 1], vec![1.0, 3.0]);
+        // Voxel centers, halfway between them and clamped at the sides.
+        assert_eq!(grid.density(Vec3::new(0.25, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density(Vec3::new(0.75, 0.5, 0.5)), 3.0);
+        assert_eq!(grid.density(Vec3::new(0.5, 0.5, 0.5)), 2.0);
+        assert_eq!(grid.density(Vec3::new(0.05, 0.1, 0.9)), 1.0);
+        assert_eq!(grid.density(Vec3::new(1.01, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(Vec3::new(0.5, -0.5, 0.5)), 0.0);
+
+        // Placed in the world by its bounds.
+        let grid = grid.with_bounds(Vec3::new(10.0, 0.0, 0.0), Vec3::new(14.0, 1.0, 1.0));
+        assert_eq!(grid.density(Vec3::new(13.0, 0.5, 0.5)), 3.0);
+        assert_eq!(grid.density(Vec3::new(0.5, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn grid_majorants_bound_density() {
+        let grid = test_grid();
+        let mut sampler = Sampler::new(20);
+        for _ in 0..20000 {
+            let p = Vec3::new(-1.0 + 4.0 * sampler.next_f32(), 2.0 * sampler.next_f32(), -1.0 + 2.0 * sampler.next_f32());
+            let brick = [(p.x + 1.0) / 4.0 * 20.0, p.y / 2.0 * 12.0, (p.z + 1.0) / 2.0 * 10.0]
+                .map(|v| v as usize / BRICK_SIZE);
+            assert!(grid.density(p) <= grid.majorant(brick[0], brick[1], brick[2]) + 1e-6, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn grid_march_covers_the_ray() {
+        let grid = test_grid();
+        let mut sampler = Sampler::new(21);
+        for _ in 0..1000 {
+            let origin = Vec3::new(1.0, 1.0, 0.0) + 4.0 * Vec3::random_unit_vector(&mut sampler);
+            let direction = (Vec3::new(1.0, 1.0, 0.0) + Vec3::random_unit_vector(&mut sampler) - origin).normalized();
+
+            let mut segments = vec![];
+            grid.march(origin, direction, 0.0, f32::INFINITY, |start, end, majorant| {
+                segments.push((start, end, majorant));
+                true
+            });
+
+            // Contiguous, and bounding the density along them.
+            assert!(!segments.is_empty());
+            for pair in segments.windows(2) {
+                assert!((pair[0].1 - pair[1].0).abs() < 1e-4, "{:?}", segments);
+            }
+            for &(start, end, majorant) in &segments {
+                for i in 0..10 {
+                    let t = start + (end - start) * (i as f32 + 0.5) / 10.0;
+                    assert!(grid.density(origin + t * direction) <= majorant + 1e-5);
+                }
+            }
+            // Nothing outside them.
+            let (first, last) = (segments[0].0, segments[segments.len() - 1].1);
+            assert_eq!(grid.density(origin + (first - 0.01) * direction), 0.0);
+            assert_eq!(grid.density(origin + (last + 0.01) * direction), 0.0);
+        }
+
+        // A ray missing the grid visits nothing.
+        let mut visited = false;
+        grid.march(Vec3::new(0.0, 5.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0, f32::INFINITY, |_, _, _| {
+            visited = true;
+            true
+        });
+        assert!(!visited);
+    }
+
+    #[test]
+    fn grid_tracking() {
+        let medium = Medium::new(0.8, Vec3::new(1.0, 1.0, 1.0), 0.0).with_grid(Arc::new(test_grid()));
+        let origin = Vec3::new(-2.0, 0.9, 0.2);
+        let direction = Vec3::new(1.0, 0.1, -0.1).normalized();
+        let distance = 6.0;
+
+        // Optical depth by quadrature.
+        let steps = 10000;
+        let mut depth = 0.0;
+        for i in 0..steps {
+            let t = distance * (i as f32 + 0.5) / steps as f32;
+            depth += medium.density_at(origin + t * direction) * distance / steps as f32;
+        }
+        let expected = f32::exp(-depth);
+        assert!(expected > 0.05 && expected < 0.9, "{}", expected);
+
+        let mut sampler = Sampler::new(22);
+        let n = 50000;
+        let (mut ratio, mut escaped) = (0.0, 0);
+        for _ in 0..n {
+            ratio += medium.transmittance(origin, direction, distance, &mut sampler);
+            match medium.sample_distance(origin, direction, distance, &mut sampler) {
+                Some(t) => assert!(t > 0.0 && t < distance && medium.density_at(origin + t * direction) > 0.0),
+                None => escaped += 1,
+            }
+        }
+        assert!((ratio / n as f32 - expected).abs() < 0.01, "{} vs {}", ratio / n as f32, expected);
+        assert!((escaped as f32 / n as f32 - expected).abs() < 0.01, "{} vs {}", escaped as f32 / n as f32, expected);
+    }
+
+    #[test]
+    fn grid_files() {
+        let grid = test_grid();
+        let bytes = write_vol(&grid);
+        assert_eq!(&bytes[..4], b"VOL\x03");
+        assert_eq!(parse_vol(&bytes).unwrap(), grid);
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[3] = 2;
+        assert!(parse_vol(&wrong_version).is_err());
+        assert!(parse_vol(&bytes[..bytes.len() - 4]).is_err());
+        assert!(parse_vol(b"VOL").is_err());
+
+        let mut empty = bytes.clone();
+        empty[8..12].copy_from_slice(&0i32.to_le_bytes());
+        assert!(parse_vol(&empty).is_err());
+        let mut huge = bytes.clone();
+        huge[8..20].copy_from_slice(&[i32::MAX.to_le_bytes(); 3].concat());
+        assert!(parse_vol(&huge).is_err());
+        assert!(parse_raw(&[0], [usize::MAX, 2, 1], RawFormat::U8).is_err());
+        assert!(parse_raw(&[0], [1 << 20, 1 << 20, 1], RawFormat::U8).is_err());
+
+        let raw = parse_raw(&[0, 255, 51, 102], [2, 2, 1], RawFormat::U8).unwrap();
+        assert_eq!(raw.voxel(1, 0, 0), 1.0);
+        assert_eq!(raw.voxel(0, 1, 0), 0.2);
+        assert!(parse_raw(&[0, 255, 51], [2, 2, 1], RawFormat::U8).is_err());
+        let floats: Vec<u8> = [0.5f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(parse_raw(&floats, [1, 1, 2], RawFormat::F32).unwrap().voxel(0, 0, 1), 2.0);
+    }
+
+    /**
+     *  A NanoVDB file with a float grid of the given leaves, each a list of
+     *  active voxels in the same 8^3 block. Only the fields parse_nvdb()
+     *  reads are filled in.
+     */
+    fn nanovdb_file(leaves: &[&[([i32; 3], f32)]], scale: f64, translation: [f64; 3]) -> Vec<u8> {
+        let mut bytes = b"NanoVDB2".to_vec();
+        bytes.extend(((32u32 << 21) | (6 << 10)).to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+
+        let mut metadata = vec![0u8; 176];
+        metadata[136..140].copy_from_slice(&8u32.to_le_bytes());
+        bytes.extend(metadata);
+        bytes.extend(b"density\0");
+
+        let mut grid = vec![0u8; 672];
+        grid[..8].copy_from_slice(b"NanoVDB1");
+        grid[636..640].copy_from_slice(&1u32.to_le_bytes());
+        for (i, at) in [384, 416, 448].into_iter().enumerate() {
+            grid[at..at + 8].copy_from_slice(&scale.to_le_bytes());
+            grid[528 + 8 * i..536 + 8 * i].copy_from_slice(&translation[i].to_le_bytes());
+        }
+        bytes.extend(grid);
+
+        let mut tree = vec![0u8; 64];
+        tree[..8].copy_from_slice(&64u64.to_le_bytes());
+        tree[32..36].copy_from_slice(&(leaves.len() as u32).to_le_bytes());
+        bytes.extend(tree);
+
+        for voxels in leaves {
+            let mut leaf = vec![0u8; 2144];
+            let min = voxels.iter().fold([i32::MAX; 3], |m, (p, _)| [0, 1, 2].map(|a| m[a].min(p[a])));
+            let max = voxels.iter().fold([i32::MIN; 3], |m, (p, _)| [0, 1, 2].map(|a| m[a].max(p[a])));
+            for a in 0..3 {
+                leaf[4 * a..4 * a + 4].copy_from_slice(&min[a].to_le_bytes());
+                leaf[12 + a] = (max[a] - min[a]) as u8;
+            }
+            for (p, value) in voxels.iter() {
+                let n = (((p[0] & 7) << 6) | ((p[1] & 7) << 3) | (p[2] & 7)) as usize;
+                leaf[16 + n / 8] |= 1 << (n % 8);
+                leaf[96 + 4 * n..100 + 4 * n].copy_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend(leaf);
+        }
+        bytes
+    }
+
+    #[test]
+    fn nanovdb_grids() {
+        let bytes = nanovdb_file(&[&[([1, 2, 3], 0.5), ([2, 2, 3], 1.0)], &[([9, 2, 3], 2.0)]], 0.5, [1.0, 0.0, 0.0]);
+        let grid = parse_nvdb(&bytes).unwrap();
+        assert_eq!(grid.resolution(), [9, 1, 1]);
+        assert_eq!([grid.voxel(0, 0, 0), grid.voxel(1, 0, 0), grid.voxel(4, 0, 0), grid.voxel(8, 0, 0)], [0.5, 1.0, 0.0, 2.0]);
+        assert_near(grid.bounds.min, Vec3::new(1.25, 0.75, 1.25));
+        assert_near(grid.bounds.max, Vec3::new(5.75, 1.25, 1.75));
+        // Voxel (2, 2, 3) is centered on its index coordinate.
+        assert!((grid.density(Vec3::new(2.0, 1.0, 1.5)) - 1.0).abs() < 1e-5);
+
+        let mut compressed = bytes.clone();
+        compressed[14] = 1;
+        let mut double = bytes.clone();
+        double[16 + 176 + 8 + 636] = 2;
+        let mut rotated = bytes.clone();
+        rotated[16 + 176 + 8 + 392..16 + 176 + 8 + 400].copy_from_slice(&1.0f64.to_le_bytes());
+        for bad in [compressed, double, rotated, bytes[..bytes.len() - 4].to_vec(), nanovdb_file(&[], 1.0, [0.0; 3])] {
+            assert!(parse_nvdb(&bad).is_err());
+        }
+        assert!(parse_nvdb(b"VOL\x03").is_err());
+
+        // Corrupt headers are errors, not overflows or huge allocations.
+        let (name_size, tree, leaves) = (16 + 136, 16 + 176 + 8 + 672, 16 + 176 + 8 + 672 + 64);
+        let corrupt = |at: usize, value: &[u8]| {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + value.len()].copy_from_slice(value);
+            parse_nvdb(&corrupt)
+        };
+        assert!(corrupt(name_size, &u32::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(tree, &u64::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(tree + 32, &u32::MAX.to_le_bytes()).is_err());
+        let far_apart = [i32::MIN.to_le_bytes(), i32::MIN.to_le_bytes(), i32::MIN.to_le_bytes(), [255, 255, 255, 0]].concat();
+        assert!(corrupt(leaves, &far_apart).is_err());
+        let mut far_apart = bytes.clone();
+        far_apart[leaves + 2144..leaves + 2144 + 12].copy_from_slice(&[i32::MAX.to_le_bytes(); 3].concat());
+        far_apart[leaves + 2144 + 12..leaves + 2144 + 15].copy_from_slice(&[255, 255, 255]);
+        assert!(parse_nvdb(&far_apart).is_err());
+    }
+
+    #[test]
+    fn scene_grids() {
+        let dir = std::env::temp_dir();
+        let name = format!("raytracer_grid_{}", std::process::id());
+        std::fs::write(dir.join(format!("{}.vol", name)), write_vol(&test_grid())).unwrap();
+        std::fs::write(dir.join(format!("{}.raw", name)), [0u8, 128, 255, 64, 0, 0, 0, 0]).unwrap();
+        std::fs::write(dir.join(format!("{}.nvdb", name)), nanovdb_file(&[&[([0, 0, 0], 1.0)]], 1.0, [0.0; 3])).unwrap();
+
+        let source = format!("
+            grid a file={name}.vol
+            grid b file={name}.raw resolution=2,2,2 format=u8 min=0,0,0 max=2,2,2
+            grid c file={name}.nvdb
+            material steam volume grid=c density=1
+            material smoke volume grid=a density=5
+            material jade dielectric medium_grid=b medium_density=2
+            sphere center=0,0,-2 radius=1 material=smoke
+            sphere center=0,0,2 radius=1 material=jade
+        ", name = name);
+        let scene = parse_scene(&source, Path::new("test.scene"), &dir);
+        let bad = [
+            format!("grid a file={}.raw", name),
+            format!("grid a file={}.raw resolution=3,2,2 format=u8", name),
+            format!("grid a file={}.raw resolution=2,2,2 format=u16", name),
+            format!("grid a file={}.raw resolution=2,2,2.5 format=u8", name),
+            format!("grid a file={}.vol min=1,1,1 max=0,0,0", name),
+            "grid a file=missing.vol".to_string(),
+            "grid a file=smoke.vdb".to_string(),
+            "material m volume grid=nothing density=1".to_string(),
+        ];
+        let errors: Vec<bool> = bad.iter().map(|source| parse_scene(source, Path::new("test.scene"), &dir).is_err()).collect();
+        std::fs::remove_file(dir.join(format!("{}.vol", name))).unwrap();
+        std::fs::remove_file(dir.join(format!("{}.raw", name))).unwrap();
+        std::fs::remove_file(dir.join(format!("{}.nvdb", name))).unwrap();
+
+        let scene = scene.unwrap();
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        let medium = hit.material.medium.as_ref().unwrap();
+        assert_eq!(medium.grid.as_deref(), Some(&test_grid()));
+        assert_eq!(medium.density, 5.0);
+        let hit = scene.world.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).unwrap();
+        let grid = hit.material.medium.as_ref().unwrap().grid.as_ref().unwrap();
+        assert_eq!(grid.bounds.max, Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(grid.voxel(1, 1, 0), 64.0 / 255.0);
+
+        for (source, error) in bad.iter().zip(errors) {
+            assert!(error, "{}", source);
+        }
+    }
+
+    #[test]
+    fn transforms() {
+        let t = Transform::scale(Vec3::new(2.0, 1.0, 0.5))
+            .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), 90.0))
+            .then(&Transform::translate(Vec3::new(1.0, 2.0, 3.0)));
+
+        // Scaled, then x turned to -z, then moved.
+        assert_near(t.point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0, 2.0, 1.0));
+        assert_near(t.vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 0.0, -2.0));
+        assert_near(t.inverse().point(t.point(Vec3::new(0.3, -2.0, 5.0))), Vec3::new(0.3, -2.0, 5.0));
+
+        let product = t.matrix * t.inverse;
+        let computed = t.matrix.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                assert!((product.m[i][j] - identity).abs() < 1e-5);
+                assert!((computed.m[i][j] - t.inverse.m[i][j]).abs() < 1e-5);
+            }
+        }
+        assert!((t.matrix.determinant3() - 1.0).abs() < 1e-5);
+        assert_eq!(Transform::from_matrix(Mat4::new([[0.0; 4]; 4])), None);
+
+        // Normals stay perpendicular to transformed tangents.
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        assert!(t.vector(tangent).dot(t.normal(normal)).abs() < 1e-5);
+
+        let bounds = t.bounds(&Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)));
+        assert_near(bounds.min, Vec3::new(0.5, 1.0, 1.0));
+        assert_near(bounds.max, Vec3::new(1.5, 3.0, 5.0));
+    }
+
+    #[test]
+    fn instances_match_placed_objects() {
+        let unit = Arc::new(Sphere::new(Vec3::zero(), 1.0, Material::default()));
+        let transform = Transform::scale(Vec3::new(2.0, 2.0, 2.0)).then(&Transform::translate(Vec3::new(3.0, 0.0, 0.0)));
+        let instance = Instance::new(unit.clone(), transform);
+        let placed = Sphere::new(Vec3::new(3.0, 0.0, 0.0), 2.0, Material::default());
+
+        let mut sampler = Sampler::new(5);
+        for origin in [Vec3::new(-4.0, 1.0, 0.5), Vec3::new(3.0, 0.5, 0.0)] {
+            for _ in 0..100 {
+                let r = Ray::new(origin, Vec3::random_unit_vector(&mut sampler) * 3.0);
+                match (instance.hit(&r, 0.001, f32::MAX), placed.hit(&r, 0.001, f32::MAX)) {
+                    (Some(a), Some(b)) => {
+                        assert!((a.t - b.t).abs() < 1e-4);
+                        assert_near(a.point, b.point);
+                        assert_near(a.normal, b.normal);
+                        assert_eq!(a.front_face, b.front_face);
+                        assert!((a.u - b.u).abs() < 1e-4);
+                    }
+                    (None, None) => {}
+                    _ => panic!("instance and sphere disagree"),
+                }
+            }
+        }
+        assert_eq!(instance.bounding_box().min, placed.bounding_box().min);
+        assert_eq!(instance.bounding_box().max, placed.bounding_box().max);
+
+        // Stretched into an ellipsoid, normals follow the gradient of
+        // x^2 / 4 + y^2 + z^2.
+        let ellipsoid = Instance::new(unit.clone(), Transform::scale(Vec3::new(2.0, 1.0, 1.0)));
+        let hit = ellipsoid.hit(&Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert_near(hit.point, Vec3::new(-2.0, 0.0, 0.0));
+        let p = Vec3::new(f32::sqrt(2.0), f32::sqrt(0.5), 0.0);
+        let hit = ellipsoid.hit(&Ray::new(p * 2.0, -p), 0.001, f32::MAX).unwrap();
+        assert_near(hit.point, p);
+        assert_near(hit.normal, Vec3::new(p.x / 4.0, p.y, 0.0).normalized());
+        assert!(ellipsoid.hit(&Ray::new(Vec3::new(0.0, 1.5, -5.0), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).is_none());
+
+        // Both instances share the one sphere.
+        assert_eq!(Arc::strong_count(&unit), 3);
+    }
+
+    #[test]
+    fn instance_light_pdfs() {
+        let mut sampler = Sampler::new(8);
+        let sphere = Instance::new(
+            Arc::new(Sphere::new(Vec3::zero(), 0.5, Material::default())),
+            Transform::scale(Vec3::new(2.0, 0.5, 1.0))
+                .then(&Transform::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0))
+                .then(&Transform::translate(Vec3::new(0.0, 2.0, 0.0))));
+        let triangle = Instance::new(
+            Arc::new(Triangle::new(
+                Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0),
+                Material::default())),
+            Transform::scale(Vec3::new(1.0, 1.0, 3.0))
+                .then(&Transform::rotate(Vec3::new(0.0, 0.0, 1.0), 20.0))
+                .then(&Transform::translate(Vec3::new(0.0, -2.0, 0.0))));
+
+        for object in [&sphere as &dyn Intersectable, &triangle] {
+            assert!((integrate_pdf(|d| object.pdf_value(Vec3::zero(), d, 0.0), &mut sampler) - 1.0).abs() < 0.05);
+            for _ in 0..1000 {
+                let direction = object.random_direction(Vec3::zero(), 0.0, &mut sampler);
+                assert!(object.hit(&Ray::new(Vec3::zero(), direction), 0.001, f32::MAX).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn scene_instances() {
+        let source = "
+            material white diffuse albedo=0.8,0.8,0.8
+            material lamp emissive color=1,1,1 intensity=4
+            object post
+            sphere center=0,0,0 radius=1 material=white scale=0.1,1,0.1
+            sphere center=0,1.5,0 radius=0.2 material=lamp
+            end
+            object pair
+            instance post translate=-1,0,0
+            instance post translate=1,0,0
+            end
+            instance pair translate=0,0,-5
+            instance pair translate=0,0,5 rotate_y=90 scale=2
+            sphere center=0,0,0 radius=1 material=white scale=2 translate=10,0,0
+            triangle v0=0,0,0 v1=1,0,0 v2=0,1,0 material=lamp rotate_x=-90 translate=0,-3,0
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new(".")).unwrap();
+        assert_eq!(scene.world.len(), 4);
+        assert_eq!(scene.lights.len(), 5);
+
+        let hit = scene.world.hit(&Ray::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.9).abs() < 1e-4);
+        let hit = scene.world.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.8).abs() < 1e-4);
+        let hit = scene.world.hit(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-4);
+        let hit = scene.world.hit(&Ray::new(Vec3::new(0.2, -1.0, -0.2), Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!(hit.material.is_emissive());
+
+        let bad = [
+            "end",
+            "instance nothing",
+            "object a",
+            "object a\nend",
+            "object a\nobject b\nend\nend",
+            "object a\npoint_light position=0,1,0\nend",
+            "material m diffuse\nobject a\nsphere center=0,0,0 radius=1 material=m\nmaterial n diffuse\nend",
+            "material m diffuse\nobject a\nsphere center=0,0,0 radius=1 material=m\ncamera vfov=30\nend",
+            "material m diffuse\nobject a\nsphere center=0,0,0 radius=1 material=m\nimage width=10 height=10\nend",
+            "material m diffuse\nobject a\nsphere center=0,0,0 radius=1 material=m\ntexture t solid color=1,1,1\nend",
+            "material m diffuse\nobject a\nsphere center=0,0,0 radius=1 material=m\nkeyframe k time=0\nend",
+            "material m diffuse\nsphere center=0,0,0 radius=1 material=m scale=1,0,1",
+            "material m diffuse\nsphere center=0,0,0 radius=1 material=m rotate_w=10",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new(".")).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn shutter_times() {
+        let camera = Camera::new(
+            Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 90.0, 1.0, 0.0, 1.0);
+
+        // A shutter that doesn't move doesn't use up random numbers.
+        let mut a = Sampler::new(1);
+        let mut b = Sampler::new(1);
+        assert_eq!(camera.get_ray(0.5, 0.5, &mut a).time, 0.0);
+        assert_eq!(camera.with_shutter(0.5, 0.5).get_ray(0.5, 0.5, &mut b).time, 0.5);
+        assert_eq!(a.next_f32(), b.next_f32());
+
+        let camera = camera.with_shutter(0.25, 0.75).with_aspect_ratio(2.0);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let time = camera.get_ray(0.5, 0.5, &mut a).time;
+            assert!((0.25..=0.75).contains(&time));
+            sum += time;
+        }
+        assert!((sum / 10000.0 - 0.5).abs() < 0.01);
+
+        // Bounces happen at the time of the ray that arrived.
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -2.0), 1.0, Material::default());
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)).with_time(0.6);
+        let hit = sphere.hit(&r, 0.001, f32::MAX).unwrap();
+        let (_, _, scattered) = hit.material.scatter(&r, &hit, &mut a);
+        assert_eq!(scattered.time, 0.6);
+    }
+
+    #[test]
+    fn moving_spheres() {
+        let sphere = Sphere::moving(Vec3::new(-1.0, 0.0, -3.0), Vec3::new(1.0, 0.0, -3.0), 0.25, Material::default());
+        let r = |time: f32| Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)).with_time(time);
+        assert!(sphere.hit(&r(0.0), 0.001, f32::MAX).is_none());
+        assert!(sphere.hit(&r(1.0), 0.001, f32::MAX).is_none());
+        let hit = sphere.hit(&r(0.5), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.75).abs() < 1e-5);
+        assert_near(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        // The ray sees the sphere for the quarter of the time it is near
+        // the middle, and it holds still outside the shutter.
+        let mut sampler = Sampler::new(4);
+        let hits = (0..10000).filter(|_| sphere.hit(&r(sampler.next_f32()), 0.001, f32::MAX).is_some()).count();
+        assert!((hits as f32 / 10000.0 - 0.25).abs() < 0.02);
+        assert_eq!(sphere.center_at(-3.0), sphere.center);
+        assert_eq!(sphere.center_at(7.0), sphere.center1);
+
+        let bounds = sphere.bounding_box();
+        assert_eq!(bounds.min, Vec3::new(-1.25, -0.25, -3.25));
+        assert_eq!(bounds.max, Vec3::new(1.25, 0.25, -2.75));
+
+        // Lights are sampled where they are at that time.
+        let origin = Vec3::new(0.0, 0.0, -2.5);
+        for time in [0.0, 0.3, 1.0] {
+            assert!((integrate_pdf(|d| sphere.pdf_value(origin, d, time), &mut sampler) - 1.0).abs() < 0.05);
+            for _ in 0..100 {
+                let direction = sphere.random_direction(origin, time, &mut sampler);
+                assert!(sphere.hit(&Ray::new(origin, direction).with_time(time), 0.001, f32::MAX).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn animated_transforms() {
+        let mut start = Keyframe::new(0.0);
+        start.translation = Vec3::new(2.0, 0.0, 0.0);
+        let mut end = Keyframe::new(1.0);
+        end.rotation = Vec3::new(0.0, 360.0, 0.0);
+        end.translation = Vec3::new(2.0, 1.0, 0.0);
+        end.scale = Vec3::new(3.0, 1.0, 1.0);
+        let motion = AnimatedTransform::new(vec![end, start]);
+
+        // Half way it has turned half way around.
+        let half = motion.at(0.5);
+        assert_near(half.point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 0.5, 0.0));
+        assert_eq!(motion.at(-1.0), start.transform());
+        assert_eq!(motion.at(2.0), end.transform());
+
+        let unit = Arc::new(Sphere::new(Vec3::new(1.0, 0.0, 0.0), 0.5, Material::default()));
+        let instance = Instance::animated(unit, motion.clone());
+        let bounds = instance.bounding_box();
+        let mut sampler = Sampler::new(6);
+        for _ in 0..2000 {
+            let time = sampler.next_f32();
+            let direction = Vec3::random_unit_vector(&mut sampler);
+            let p = motion.at(time).point(Vec3::new(1.0, 0.0, 0.0) + 0.5 * direction);
+            for axis in 0..3 {
+                assert!(p[axis] >= bounds.min[axis] && p[axis] <= bounds.max[axis]);
+            }
+        }
+
+        let r = |time: f32| Ray::new(Vec3::new(3.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_time(time);
+        let hit = instance.hit(&r(0.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-4);
+        assert!(instance.hit(&r(0.5), 0.001, f32::MAX).is_none());
+        assert!((integrate_pdf(|d| instance.pdf_value(Vec3::zero(), d, 0.7), &mut sampler) - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn scene_motion() {
+        let source = "
+            camera shutter_open=0.2 shutter_close=0.8
+            material white diffuse albedo=0.8,0.8,0.8
+            keyframe lift time=1 translate=0,2,0
+            keyframe lift time=0
+            sphere center=-1,0,-3 center1=1,0,-3 radius=0.25 material=white
+            sphere center=0,0,0 radius=0.5 material=white translate=0,0,-6 motion=lift
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new(".")).unwrap();
+        let mut sampler = Sampler::new(2);
+        let r = scene.camera.get_ray(0.5, 0.5, &mut sampler);
+        assert!((0.2..=0.8).contains(&r.time));
+
+        // Later camera lines keep the shutter unless they change it.
+        let later = parse_scene("camera shutter_open=0.2 shutter_close=0.8\ncamera vfov=30\ncamera shutter_open=0.5",
+            Path::new("test.scene"), Path::new(".")).unwrap();
+        let times: Vec<f32> = (0..100).map(|_| later.camera.get_ray(0.5, 0.5, &mut sampler).time).collect();
+        assert!(times.iter().all(|t| (0.5..=0.8).contains(t)) && times.iter().any(|&t| t > 0.6));
+
+        let r = |time: f32| Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)).with_time(time);
+        assert!((scene.world.hit(&r(0.5), 0.001, f32::MAX).unwrap().t - 2.75).abs() < 1e-4);
+        assert!((scene.world.hit(&r(0.0), 0.001, f32::MAX).unwrap().t - 5.5).abs() < 1e-4);
+        assert!(scene.world.hit(&r(1.0), 0.001, f32::MAX).is_none());
+
+        let bad = [
+            "camera shutter_open=1 shutter_close=0.5",
+            "keyframe a translate=1,0,0",
+            "keyframe a time=0\nkeyframe a time=0 translate=1,0,0",
+            "material m diffuse\nsphere center=0,0,0 radius=1 material=m motion=nothing",
+            "material m diffuse\nkeyframe a time=0\nsphere center=0,0,0 radius=1 material=m motion=a\nkeyframe a time=1 translate=0,1,0",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new(".")).is_err(), "{}", source);
+        }
+    }
+
+    /**
+     *  Fires rays from all around at object and checks that every hit lies
+     *  in its bounds, with a unit normal and dpdu x dpdv on the outside.
+     */
+    fn check_surface(object: &dyn Intersectable, sampler: &mut Sampler) -> usize {
+        let bounds = object.bounding_box();
+        let center = bounds.centroid();
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = center + 10.0 * Vec3::random_unit_vector(sampler);
+            let target = center + Vec3::random_unit_vector(sampler);
+            let Some(hit) = object.hit(&Ray::new(origin, target - origin), 0.001, f32::MAX) else {
+                continue;
+            };
+            hits += 1;
+            let pad = Vec3::new(1e-3, 1e-3, 1e-3);
+            assert!(hit.point.min(bounds.min - pad) == bounds.min - pad && hit.point.max(bounds.max + pad) == bounds.max + pad);
+            assert!((hit.normal.length() - 1.0).abs() < 1e-4);
+            let outward = if hit.front_face { hit.normal } else { -hit.normal };
+            assert!(hit.dpdu.cross(hit.dpdv).dot(outward) > 0.0, "{:?}", hit.point);
+            assert!((0.0..=1.0).contains(&hit.u));
+        }
+        hits
+    }
+
+    #[test]
+    fn planes_discs_and_quads() {
+        let down = |x: f32, z: f32| Ray::new(Vec3::new(x, 2.0, z), Vec3::new(0.0, -2.0, 0.0));
+        let plane = Plane::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 3.0, 0.0), Material::default());
+        let hit = plane.hit(&down(5.0, -7.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-6);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!((hit.u.abs() + hit.v.abs() - 12.0).abs() < 1e-4);
+        let along = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(plane.hit(&along, 0.001, f32::MAX).is_none());
+        assert!(plane.hit(&down(2.0 * PLANE_EXTENT, 0.0), 0.001, f32::MAX).is_none());
+        let bounds = plane.bounding_box();
+        assert!(bounds.max.y - bounds.min.y < 0.01 && bounds.max.x >= PLANE_EXTENT);
+
+        let disc = Disc::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, Material::default());
+        let hit = disc.hit(&down(0.0, 0.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6 && (hit.v - 1.0).abs() < 1e-6);
+        assert!((disc.hit(&down(0.6, 0.6), 0.001, f32::MAX).unwrap().v - (1.0 - 0.72f32.sqrt())).abs() < 1e-4);
+        assert!(disc.hit(&down(0.8, 0.8), 0.001, f32::MAX).is_none());
+        let up = Ray::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(!disc.hit(&up, 0.001, f32::MAX).unwrap().front_face);
+
+        let quad = Quad::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0), Material::default());
+        assert_eq!(quad.area(), 8.0);
+        let hit = quad.hit(&down(0.0, 0.5), 0.001, f32::MAX).unwrap();
+        assert!(hit.front_face);
+        assert!((hit.u - 0.25).abs() < 1e-6 && (hit.v - 0.25).abs() < 1e-6);
+        assert!(quad.hit(&down(3.5, 0.0), 0.001, f32::MAX).is_none());
+        assert!(quad.hit(&down(0.0, -1.5), 0.001, f32::MAX).is_none());
+
+        let mut sampler = Sampler::new(11);
+        let tilted = Disc::new(Vec3::new(1.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 0.5, Material::default());
+        for object in [&disc as &dyn Intersectable, &quad, &tilted] {
+            assert!(check_surface(object, &mut sampler) > 0);
+        }
+        let origin = Vec3::new(0.3, 2.0, 0.2);
+        for object in [&disc as &dyn Intersectable, &quad, &tilted] {
+            assert!((integrate_pdf(|d| object.pdf_value(origin, d, 0.0), &mut sampler) - 1.0).abs() < 0.05);
+            for _ in 0..1000 {
+                let direction = object.random_direction(origin, 0.0, &mut sampler);
+                assert!(object.pdf_value(origin, direction, 0.0) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn boxes() {
+        let cuboid = Cuboid::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, -2.0), Material::default());
+        assert_eq!(cuboid.bounding_box().min, Vec3::new(-1.0, 0.0, -2.0));
+
+        let hit = cuboid.hit(&Ray::new(Vec3::new(5.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-6);
+        assert_eq!(hit.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit.front_face);
+        assert!((hit.u - 0.5).abs() < 1e-6 && (hit.v - 2.0 / 3.0).abs() < 1e-6);
+
+        // From inside the far face is hit from behind.
+        let hit = cuboid.hit(&Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-6);
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let above = Ray::new(Vec3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(cuboid.hit(&above, 0.001, f32::MAX).is_none());
+        let behind = Ray::new(Vec3::new(5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(cuboid.hit(&behind, 0.001, f32::MAX).is_none());
+        // Grazing along the top face still enters by the side.
+        let grazing = Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let hit = cuboid.hit(&grazing, 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-6);
+        assert_eq!(hit.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        assert!(check_surface(&cuboid, &mut Sampler::new(12)) > 0);
+    }
+
+    #[test]
+    fn cylinders_and_cones() {
+        let base = Vec3::new(0.0, -1.0, 0.0);
+        let cylinder = Cylinder::new(base, 0.5, 2.0, Material::default());
+        let cone = Cone::new(base, 0.5, 2.0, Material::default());
+        let sideways = |y: f32| Ray::new(Vec3::new(5.0, y, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let hit = cylinder.hit(&sideways(0.5), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert_near(hit.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!((hit.v - 0.75).abs() < 1e-5);
+        // u runs around the axis the same way as on spheres.
+        for direction in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, -1.0)] {
+            let r = Ray::new(5.0 * direction.normalized(), -direction);
+            assert!((cylinder.hit(&r, 0.001, f32::MAX).unwrap().u - raytracer::sphere::sphere_uv(direction.normalized()).0).abs() < 1e-5);
+        }
+        assert!(cylinder.hit(&sideways(1.5), 0.001, f32::MAX).is_none());
+        let hit = cylinder.hit(&Ray::new(Vec3::new(0.2, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+        let hit = cylinder.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-5 && !hit.front_face);
+        let bounds = cylinder.bounding_box();
+        assert_eq!((bounds.min, bounds.max), (Vec3::new(-0.5, -1.0, -0.5), Vec3::new(0.5, 1.0, 0.5)));
+
+        // Halfway up the cone is half as wide.
+        let hit = cone.hit(&sideways(0.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.75).abs() < 1e-5);
+        assert_near(hit.normal, Vec3::new(4.0, 1.0, 0.0).normalized());
+        assert!(cone.hit(&sideways(1.01), 0.001, f32::MAX).is_none());
+        let hit = cone.hit(&Ray::new(Vec3::new(0.0, -3.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0.0, -1.0, 0.0));
+        let hit = cone.hit(&Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-4);
+
+        let mut sampler = Sampler::new(13);
+        assert!(check_surface(&cylinder, &mut sampler) > 0);
+        assert!(check_surface(&cone, &mut sampler) > 0);
+    }
+
+    #[test]
+    fn tori() {
+        let torus = Torus::new(Vec3::new(0.0, 1.0, 0.0), 2.0, 0.5, Material::default());
+        let sideways = Ray::new(Vec3::new(10.0, 1.0, 0.0), Vec3::new(-2.0, 0.0, 0.0));
+        let hit = torus.hit(&sideways, 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 3.75).abs() < 1e-4);
+        assert_near(hit.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!((hit.u - raytracer::sphere::sphere_uv(Vec3::new(1.0, 0.0, 0.0)).0).abs() < 1e-4 && hit.v.abs() < 1e-4);
+        // From inside the tube its inside is hit, from the hole the far side.
+        let hit = torus.hit(&sideways, 4.0, f32::MAX).unwrap();
+        assert!((hit.t - 4.25).abs() < 1e-4 && !hit.front_face);
+        let hit = torus.hit(&sideways, 4.5, f32::MAX).unwrap();
+        assert!((hit.t - 5.75).abs() < 1e-4 && hit.front_face);
+        assert_near(hit.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        let down = |x: f32| Ray::new(Vec3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(torus.hit(&down(0.0), 0.001, f32::MAX).is_none());
+        let hit = torus.hit(&down(2.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 3.5).abs() < 1e-4);
+        assert!((hit.v - 0.25).abs() < 1e-4);
+        assert!(torus.hit(&down(2.6), 0.001, f32::MAX).is_none());
+        let bounds = torus.bounding_box();
+        assert_eq!((bounds.min, bounds.max), (Vec3::new(-2.5, 0.5, -2.5), Vec3::new(2.5, 1.5, 2.5)));
+
+        assert!(check_surface(&torus, &mut Sampler::new(14)) > 0);
+    }
+
+    #[test]
+    fn scene_shapes() {
+        let source = "
+            material white diffuse albedo=0.8,0.8,0.8
+            material lamp emissive color=1,1,1 intensity=4
+            plane point=0,-1,0 normal=0,1,0 material=white
+            disc center=0,4,0 normal=0,-1,0 radius=1 material=lamp
+            quad corner=-1,5,-1 edge_u=2,0,0 edge_v=0,0,2 material=lamp
+            box min=2,0,-4 max=3,1,-3 material=lamp
+            cylinder base=0,0,-3 radius=0.5 height=1 material=white
+            cone base=-2,0,-3 radius=0.5 height=1 material=white translate=0,0,-1
+            torus center=0,0,-8 major_radius=1 minor_radius=0.25 material=white
+        ";
+        let scene = parse_scene(source, Path::new("test.scene"), Path::new(".")).unwrap();
+        // Only shapes that can be sampled become lights.
+        assert_eq!(scene.lights.len(), 2);
+
+        let hit = |origin: Vec3, direction: Vec3| scene.world.hit(&Ray::new(origin, direction), 0.001, f32::MAX).unwrap().t;
+        assert!((hit(Vec3::new(5.0, -0.5, 0.0), Vec3::new(0.0, -1.0, 0.0)) - 0.5).abs() < 1e-5);
+        assert!((hit(Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0)) - 2.5).abs() < 1e-5);
+        assert!((hit(Vec3::new(-2.0, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0)) - 3.75).abs() < 1e-4);
+        assert!((hit(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)) - 7.25).abs() < 1e-4);
+        assert!((hit(Vec3::new(2.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0)) - 3.0).abs() < 1e-5);
+        assert!((hit(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, 1.0, 0.0)) - 1.0).abs() < 1e-5);
+        assert!((hit(Vec3::new(0.9, 4.5, 0.9), Vec3::new(0.0, 1.0, 0.0)) - 0.5).abs() < 1e-5);
+
+        let bad = [
+            "material m diffuse\nplane point=0,0,0 normal=0,0,0 material=m",
+            "material m diffuse\ndisc center=0,0,0 normal=0,1,0 radius=0 material=m",
+            "material m diffuse\nquad corner=0,0,0 edge_u=1,0,0 edge_v=2,0,0 material=m",
+            "material m diffuse\nbox min=0,0,0 max=1,0,1 material=m",
+            "material m diffuse\ncylinder base=0,0,0 radius=1 height=-1 material=m",
+            "material m diffuse\ncone base=0,0,0 radius=1 material=m",
+            "material m diffuse\ntorus center=0,0,0 major_radius=1 minor_radius=0 material=m",
+        ];
+        for source in bad {
+            assert!(parse_scene(source, Path::new("test.scene"), Path::new(".")).is_err(), "{}", source);
+        }
+    }
+}